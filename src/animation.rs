@@ -0,0 +1,165 @@
+use eframe::egui;
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::AnimationDecoder;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// ブラウザと同様、極端に短いディレイはこの値に丸める
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// アニメーションの1フレーム
+pub struct AnimationFrame {
+    pub texture: egui::TextureHandle,
+    pub delay: Duration,
+}
+
+/// アニメーションGIF/WebPの再生状態
+pub struct AnimationPlayer {
+    frames: Vec<AnimationFrame>,
+    /// 現在のフレーム番号
+    current_frame: usize,
+    /// 現在のフレームを表示し始めた時刻
+    frame_started: Instant,
+    /// 一時停止中か
+    pub paused: bool,
+    /// 最後のフレームまで1回以上再生したか
+    pub completed_once: bool,
+}
+
+/// アニメーション画像の全フレームをデコードする
+/// 静止画（1フレームのみ）や非対応フォーマットの場合は None
+pub fn decode_frames(path: &Path) -> Option<Vec<(egui::ColorImage, Duration)>> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let reader = BufReader::new(File::open(path).ok()?);
+
+    let frames = match ext.as_str() {
+        "gif" => GifDecoder::new(reader).ok()?.into_frames().collect_frames().ok()?,
+        "webp" => {
+            let decoder = WebPDecoder::new(reader).ok()?;
+            if !decoder.has_animation() {
+                return None;
+            }
+            decoder.into_frames().collect_frames().ok()?
+        }
+        _ => return None,
+    };
+
+    if frames.len() < 2 {
+        return None;
+    }
+
+    Some(
+        frames
+            .into_iter()
+            .map(|frame| {
+                let delay = Duration::from(frame.delay());
+                let delay = if delay.is_zero() {
+                    DEFAULT_FRAME_DELAY
+                } else {
+                    delay.max(MIN_FRAME_DELAY)
+                };
+                let buffer = frame.into_buffer();
+                let (w, h) = buffer.dimensions();
+                let color_image = egui::ColorImage::from_rgba_unmultiplied(
+                    [w as usize, h as usize],
+                    buffer.as_raw(),
+                );
+                (color_image, delay)
+            })
+            .collect(),
+    )
+}
+
+impl AnimationPlayer {
+    /// 画像を読み込んでテクスチャ化する（アニメーションでなければ None）
+    pub fn load(ctx: &egui::Context, path: &Path) -> Option<Self> {
+        let decoded = decode_frames(path)?;
+        let name = path.display().to_string();
+        let frames = decoded
+            .into_iter()
+            .enumerate()
+            .map(|(i, (color_image, delay))| AnimationFrame {
+                texture: ctx.load_texture(
+                    format!("{}#{}", name, i),
+                    color_image,
+                    egui::TextureOptions::default(),
+                ),
+                delay,
+            })
+            .collect();
+
+        Some(Self {
+            frames,
+            current_frame: 0,
+            frame_started: Instant::now(),
+            paused: false,
+            completed_once: false,
+        })
+    }
+
+    /// 経過時間に応じてフレームを進める
+    /// 次に再描画が必要になるまでの時間を返す（一時停止中は None）
+    pub fn update(&mut self) -> Option<Duration> {
+        if self.paused {
+            return None;
+        }
+
+        let mut elapsed = self.frame_started.elapsed();
+        // 処理落ちしても実時間に追従するよう、経過分のフレームをまとめて進める
+        while elapsed >= self.frames[self.current_frame].delay {
+            elapsed -= self.frames[self.current_frame].delay;
+            self.frame_started += self.frames[self.current_frame].delay;
+            self.advance();
+        }
+
+        Some(self.frames[self.current_frame].delay - elapsed)
+    }
+
+    fn advance(&mut self) {
+        self.current_frame += 1;
+        if self.current_frame >= self.frames.len() {
+            self.current_frame = 0;
+            self.completed_once = true;
+        }
+    }
+
+    /// 一時停止/再開の切り替え
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        // 再開時は現在のフレームの表示を最初からやり直す
+        self.frame_started = Instant::now();
+    }
+
+    /// 1フレーム進める（一時停止状態になる）
+    pub fn step_forward(&mut self) {
+        self.paused = true;
+        self.advance();
+    }
+
+    /// 1フレーム戻す（一時停止状態になる）
+    pub fn step_back(&mut self) {
+        self.paused = true;
+        self.current_frame = if self.current_frame == 0 {
+            self.frames.len() - 1
+        } else {
+            self.current_frame - 1
+        };
+    }
+
+    /// 現在のフレームのテクスチャ
+    pub fn texture(&self) -> &egui::TextureHandle {
+        &self.frames[self.current_frame].texture
+    }
+
+    pub fn current_frame(&self) -> usize {
+        self.current_frame
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+}
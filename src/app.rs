@@ -1,30 +1,133 @@
 use eframe::egui::{self, Color32, Key, RichText, Vec2};
+use serde_json::{json, Value};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
+use crate::animation::AnimationPlayer;
+use crate::batch_rename::{self, BatchRename};
+use crate::compare::{self, CompareView};
 use crate::config::Config;
-use crate::file_tree::{FileNode, FileTree};
+use crate::duplicates::{DuplicateFinder, HashKind};
+use crate::file_ops::{self, CollisionStrategy, FileOp};
+use crate::file_tree::{FileNode, FileTree, TreeFileTags};
+use crate::fs_watcher::FsWatcher;
+use crate::hooks::{self, HookContext, HookEvent, HookOutput, HookRunner};
+use crate::http_api::{self, ApiCommand, ApiServer};
+use crate::image_loader;
 use crate::image_viewer::ImageViewer;
+use crate::metadata_info::{self, MetadataInfo};
+use crate::pending_changes::{PendingChanges, UnsavedPolicy};
+use crate::playlist::{self, Playlist};
+use crate::presentation::{Presentation, Transition};
+use crate::review_queue::ReviewQueue;
+use crate::safe_write;
+use crate::session::Session;
+use crate::single_instance::{InstanceListener, InstanceSocket, Invocation};
+use crate::sidecar;
 use crate::slideshow::Slideshow;
-use crate::tag_manager::{self, find_images_with_tag, is_image_file};
-use image as image_crate;
+use crate::sort_order::{self, SortOrder};
+use crate::tag_cache::TagCache;
+use crate::tag_database;
+use crate::tag_manager::{self, is_image_file};
+use crate::tag_query::TagQuery;
+use crate::tag_stats::{StatsCollection, TagStats};
+use crate::tag_suggestions::{self, TagSuggester};
+use crate::write_check;
+use crate::write_queue::{self, WriteOptions, WriteQueue, WriteResult};
+
+/// タグの統計の共起表に表示するタグの数
+const MAX_MATRIX_TAGS: usize = 20;
+
+/// フック設定ウィンドウに残す実行結果の数
+const MAX_HOOK_LOG: usize = 20;
+
+/// 表示状態が変わってから保存するまでの時間
+const SESSION_SAVE_DELAY: Duration = Duration::from_secs(1);
 
 pub struct TagEditorApp {
     inner: Rc<RefCell<InnerApp>>,
 }
 
+/// 未保存の確認ダイアログでの選択
+enum LeaveChoice {
+    Save,
+    Discard,
+    /// 保留にして後でまとめて保存する
+    Keep,
+    Cancel,
+}
+
+/// 表示中の画像から離れる操作（未保存の確認の後に実行する）
+enum LeaveAction {
+    Prev,
+    Next,
+    /// 画像を開く
+    Open(PathBuf),
+    /// フォルダか画像を開く（ドロップ・最近使った項目など）
+    OpenPath(PathBuf),
+    StartSlideshow(Playlist),
+    /// アプリを終了する
+    Close,
+}
+
 struct InnerApp {
     config: Config,
     image_viewer: ImageViewer,
     file_tree: FileTree,
     slideshow: Slideshow,
+    /// 全画面スライドショーの表示状態
+    presentation: Presentation,
+    compare: CompareView,
+    /// 開いているフォルダの変更監視
+    fs_watcher: FsWatcher,
+    /// 外部ツール向けの HTTP API（無効なら None）
+    api: Option<ApiServer>,
+    /// 2つ目の起動から渡された操作の受け付け（単一ウィンドウでなければ None）
+    instance: Option<InstanceListener>,
+    /// ユーザー定義のフックの実行
+    hooks: HookRunner,
+    /// 最近のフックの実行結果（新しい順）
+    hook_log: Vec<HookOutput>,
+    /// 最後に「画像を開いた」フックを実行した画像
+    hooked_image: Option<PathBuf>,
+    /// フック設定ウィンドウ
+    hooks_dialog_open: bool,
+    /// ファイルツリー表示用のタグ
+    tag_cache: TagCache,
+    /// ファイルツリーの絞り込み条件（タグの検索条件）
+    tree_filter: String,
+    /// まだ保存していない表示状態と、最後に変わった時刻
+    unsaved_session: Option<(Session, Instant)>,
+    /// 元に戻せるファイル操作（まとめて行った操作は1つにまとめる）
+    undo_stack: Vec<Vec<FileOp>>,
+    /// 一括リネームダイアログ（開いていなければ None）
+    batch_rename: Option<BatchRename>,
+    /// 重複画像ウィンドウ（開いていなければ None）
+    duplicate_finder: Option<DuplicateFinder>,
+    /// レビューキュー（有効な間は条件に合う画像だけを巡回する）
+    review: Option<ReviewQueue>,
+    /// タグの統計ウィンドウ（開いていなければ None）
+    tag_stats: Option<TagStats>,
+    /// 集計中のタグの統計（終わったら tag_stats に移す）
+    stats_collection: Option<StatsCollection>,
+    /// 似ている画像からのタグの提案
+    suggester: TagSuggester,
 
     /// 現在の画像のタグ
     current_tags: Vec<String>,
     /// タグが変更されたか
     tags_modified: bool,
+    /// 保存せずに離れた画像のタグ
+    pending: PendingChanges,
+    /// バックグラウンドでのタグの書き込み
+    write_queue: WriteQueue,
+    /// 未保存の確認中の操作
+    leave_prompt: Option<LeaveAction>,
+    /// 確認済みなので終了してよいか
+    allow_close: bool,
 
     /// 新しいタグの入力
     new_tag_input: String,
@@ -32,17 +135,17 @@ struct InnerApp {
     /// ホットキー設定モード
     #[allow(dead_code)]
     hotkey_config_mode: bool,
-    /// 設定中のホットキー番号
-    configuring_hotkey: Option<String>,
-    /// ホットキータグ入力
-    hotkey_tag_input: String,
 
     /// スライドショー設定ダイアログ
     slideshow_dialog_open: bool,
-    /// スライドショー対象タグ
+    /// スライドショー対象のタグ（slideshow_query なら検索条件）
     slideshow_tag: String,
+    /// slideshow_tag を検索条件として解釈するか（そうでなければ1つのタグ名）
+    slideshow_query: bool,
     /// スライドショー対象ディレクトリ
     slideshow_dir: Option<PathBuf>,
+    /// サブフォルダの画像も含めるか
+    slideshow_recursive: bool,
 
     /// ステータスメッセージ
     status_message: String,
@@ -50,21 +153,32 @@ struct InnerApp {
     // ウィンドウ開閉状態追跡用
     was_left_sidebar_open: bool,
     was_right_sidebar_open: bool,
+    was_metadata_window_open: bool,
+    /// メタデータインスペクタの表示内容（画像パスと読み込み結果）
+    metadata_info: Option<(PathBuf, MetadataInfo)>,
     /// 現在表示中のテクスチャ（spinner を出さないため同期で読み込む）
     current_texture: Option<egui::TextureHandle>,
     /// current_texture に対応する画像パス
     current_texture_path: Option<PathBuf>,
+    /// 表示中の画像を読み込めなかったときのエラー
+    load_error: Option<String>,
+    /// アニメーション画像の再生状態（静止画の場合は None）
+    current_animation: Option<AnimationPlayer>,
 }
 
 impl TagEditorApp {
-    pub fn new(cc: &eframe::CreationContext<'_>, initial_path: Option<PathBuf>) -> Self {
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        invocation: Invocation,
+        socket: Option<InstanceSocket>,
+    ) -> Self {
         // カスタムフォントを設定（日本語対応）
         // Meiryo UI を使用（より良い日本語表示）
         let mut fonts = egui::FontDefinitions::default();
         if let Ok(font_data) = std::fs::read("C:/Windows/Fonts/meiryo.ttc") {
             fonts.font_data.insert(
                 "jp_font".to_owned(),
-                egui::FontData::from_owned(font_data).into(),
+                egui::FontData::from_owned(font_data),
             );
             fonts
                 .families
@@ -87,37 +201,60 @@ impl TagEditorApp {
             image_viewer: ImageViewer::default(),
             file_tree: FileTree::default(),
             slideshow: Slideshow::default(),
+            presentation: Presentation::default(),
+            compare: CompareView::default(),
+            fs_watcher: FsWatcher::new(&cc.egui_ctx),
+            api: None,
+            instance: socket.map(|socket| socket.listen(&cc.egui_ctx)),
+            hooks: HookRunner::new(&cc.egui_ctx),
+            hook_log: Vec::new(),
+            hooked_image: None,
+            hooks_dialog_open: false,
+            tag_cache: TagCache::default(),
+            tree_filter: String::new(),
+            unsaved_session: None,
+            undo_stack: Vec::new(),
+            batch_rename: None,
+            duplicate_finder: None,
+            review: None,
+            tag_stats: None,
+            stats_collection: None,
+            suggester: TagSuggester::new(&cc.egui_ctx),
             current_tags: Vec::new(),
             tags_modified: false,
+            pending: PendingChanges::default(),
+            write_queue: WriteQueue::new(&cc.egui_ctx),
+            leave_prompt: None,
+            allow_close: false,
             new_tag_input: String::new(),
             hotkey_config_mode: false,
-            configuring_hotkey: None,
-            hotkey_tag_input: String::new(),
             slideshow_dialog_open: false,
             slideshow_tag: String::new(),
+            slideshow_query: false,
             slideshow_dir: None,
+            slideshow_recursive: false,
             status_message: String::new(),
             was_left_sidebar_open: false,
             was_right_sidebar_open: false,
+            was_metadata_window_open: false,
+            metadata_info: None,
             current_texture: None,
             current_texture_path: None,
+            load_error: None,
+            current_animation: None,
         };
 
-        // 初期パスが指定されていれば開く
-        if let Some(path) = initial_path {
-            if path.exists() {
-                if path.is_dir() {
-                    inner.file_tree.set_root(&path);
-                    inner.slideshow_dir = Some(path);
-                } else if is_image_file(&path) {
-                    inner.open_image(path.clone());
-                    if let Some(parent) = path.parent() {
-                        inner.file_tree.set_root(parent);
-                        inner.slideshow_dir = Some(parent.to_path_buf());
-                    }
-                }
-            }
+        tag_database::set_enabled(inner.config.library_mode);
+        inner.apply_sort_settings();
+        if inner.config.api_enabled {
+            inner.start_api(&cc.egui_ctx);
+        }
+
+        // 初期パスが指定されていれば開く。なければ前回の状態を復元
+        if invocation.path.is_none() && inner.config.restore_session {
+            inner.restore_session();
         }
+        inner.run_invocation(invocation);
 
         Self {
             inner: Rc::new(RefCell::new(inner)),
@@ -127,199 +264,2110 @@ impl TagEditorApp {
 
 impl InnerApp {
     fn handle_dropped_files(&mut self, ctx: &egui::Context) {
-        ctx.input(|i| {
-            for file in &i.raw.dropped_files {
-                if let Some(path) = &file.path {
-                    if path.is_dir() {
-                        self.file_tree.set_root(path);
-                        self.slideshow_dir = Some(path.clone());
-                    } else if is_image_file(path) {
-                        self.open_image(path.clone());
-                        if let Some(parent) = path.parent() {
-                            self.file_tree.set_root(parent);
-                            self.slideshow_dir = Some(parent.to_path_buf());
-                        }
-                    }
+        let paths: Vec<PathBuf> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+        // 複数ドロップされた場合は最後のものを開く
+        if let Some(path) = paths.last() {
+            self.request(LeaveAction::OpenPath(path.clone()));
+        }
+    }
+
+    /// フォルダか画像を開いて最近使った項目に記録する
+    fn open_path(&mut self, path: &Path) {
+        if path.is_dir() {
+            self.file_tree.set_root(path, &mut self.tag_cache);
+            self.slideshow_dir = Some(path.to_path_buf());
+            self.config.add_recent_folder(path.to_path_buf());
+        } else if is_image_file(path) {
+            self.open_image(path.to_path_buf());
+            if let Some(parent) = path.parent() {
+                self.file_tree.set_root(parent, &mut self.tag_cache);
+                self.slideshow_dir = Some(parent.to_path_buf());
+                self.config.add_recent_folder(parent.to_path_buf());
+            }
+            self.config.add_recent_image(path.to_path_buf());
+        } else {
+            self.status_message = format!("Not found: {}", path.display());
+            return;
+        }
+        self.config.save();
+    }
+
+    /// 現在の表示状態
+    fn current_session(&self) -> Session {
+        let mut expanded: Vec<PathBuf> = self.file_tree.expanded.iter().cloned().collect();
+        expanded.sort();
+        Session {
+            root: self.file_tree.root.as_ref().map(|r| r.path.clone()),
+            current_image: self.image_viewer.current_image.clone(),
+            index: self.image_viewer.current_index,
+            expanded,
+            tree_filter: self.tree_filter.clone(),
+        }
+    }
+
+    /// 表示状態が変わっていれば保存する（異常終了しても直前の状態に戻れるよう終了を待たずに保存）
+    /// 絞り込みの入力中などに毎回書き込まないよう、変化が SESSION_SAVE_DELAY 止まってから書く
+    /// 保存を待っている間は、あとどれだけ待つかを返す
+    fn save_session(&mut self, now: bool) -> Option<Duration> {
+        let session = self.current_session();
+        if session == self.config.session {
+            self.unsaved_session = None;
+            return None;
+        }
+        let changed_at = match &self.unsaved_session {
+            Some((unsaved, at)) if *unsaved == session => *at,
+            _ => Instant::now(),
+        };
+        let elapsed = changed_at.elapsed();
+        if now || elapsed >= SESSION_SAVE_DELAY {
+            self.config.session = session;
+            self.config.save();
+            self.unsaved_session = None;
+            None
+        } else {
+            self.unsaved_session = Some((session, changed_at));
+            Some(SESSION_SAVE_DELAY - elapsed)
+        }
+    }
+
+    /// 前回の表示状態を復元する（なくなったフォルダや画像は飛ばす）
+    fn restore_session(&mut self) {
+        let session = self.config.session.clone();
+        if let Some(root) = session.root.as_ref().filter(|p| p.is_dir()) {
+            self.file_tree.set_root(root, &mut self.tag_cache);
+            self.slideshow_dir = Some(root.clone());
+            // 親から順に展開する（sort 済みなので親が先に来る）
+            for path in session.expanded.iter().filter(|p| p.starts_with(root)) {
+                if path.is_dir() {
+                    self.file_tree.expand(path, &mut self.tag_cache);
                 }
             }
-        });
+        }
+        if let Some(image) = &session.current_image {
+            if image.is_file() {
+                self.open_image(image.clone());
+            } else if let Some(dir) = image.parent().filter(|d| d.is_dir()) {
+                // 画像が消えていたら同じ位置の画像を開く
+                self.image_viewer.open_index_in(dir, session.index, &mut self.tag_cache);
+                if let Some(path) = self.image_viewer.current_image.clone() {
+                    self.open_image(path);
+                }
+            }
+        }
+        self.tree_filter = session.tree_filter;
+    }
+
+    /// 表示中のフォルダをブックマークに追加/解除する
+    fn toggle_bookmark(&mut self) {
+        let Some(folder) = self.current_folder() else {
+            return;
+        };
+        self.status_message = if self.config.toggle_bookmark(folder.clone()) {
+            format!("Bookmarked: {}", folder.display())
+        } else {
+            format!("Bookmark removed: {}", folder.display())
+        };
+        self.config.save();
+    }
+
+    /// 一覧の項目を選ぶメニュー。選んだパスを返す
+    fn path_menu(ui: &mut egui::Ui, paths: &[PathBuf], shortcut: &str) -> Option<PathBuf> {
+        if paths.is_empty() {
+            ui.label("(Empty)");
+        }
+        let mut selected = None;
+        for (i, path) in paths.iter().enumerate() {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string());
+            let button = if i < 9 && !shortcut.is_empty() {
+                egui::Button::new(name).shortcut_text(format!("{}+{}", shortcut, i + 1))
+            } else {
+                egui::Button::new(name)
+            };
+            if ui
+                .add(button)
+                .on_hover_text(path.display().to_string())
+                .clicked()
+            {
+                selected = Some(path.clone());
+                ui.close_menu();
+            }
+        }
+        selected
     }
 
     fn open_image(&mut self, path: PathBuf) {
-        // 変更があれば確認せずに破棄（オートセーブがオフの場合は注意）
-        self.image_viewer.open(&path);
+        if self.image_viewer.current_image.as_ref() == Some(&path) {
+            // 開き直すときは編集中のタグを引き継ぐ
+            if self.tags_modified {
+                self.pending.insert(path.clone(), self.current_tags.clone());
+            }
+        } else {
+            self.leave_image();
+        }
+        // 比較中はフォーカス中のペインに開く（他のペインに表示中ならそのペインに移る）
+        if self.compare.is_active {
+            if let Some(old) = &self.image_viewer.current_image {
+                self.compare.invalidate_tags(old);
+            }
+            match self.compare.panes.iter().position(|p| p == &path) {
+                Some(pos) => self.compare.focused = pos,
+                None => self.compare.replace_focused_with(&path),
+            }
+        }
+        self.image_viewer.open(&path, &mut self.tag_cache);
         // キャッシュされているテクスチャは新しい画像に合わせて破棄
         self.current_texture = None;
         self.current_texture_path = None;
-        self.current_tags = tag_manager::load_tags(&path);
-        self.tags_modified = false;
+        self.current_animation = None;
+        self.load_current_tags();
         self.status_message = format!("Opened: {}", path.display());
     }
 
-    fn save_tags(&mut self) {
-        if let Some(path) = &self.image_viewer.current_image {
-            if let Err(e) = tag_manager::save_tags(path, &self.current_tags) {
-                self.status_message = format!("Error saving tags: {}", e);
-            } else {
+    /// 表示中の画像のタグを読み込む（保留中の編集があればそちらを使う）
+    fn load_current_tags(&mut self) {
+        let Some(path) = self.image_viewer.current_image.clone() else {
+            return;
+        };
+        match self.pending.take(&path) {
+            Some(tags) => {
+                self.current_tags = tags;
+                self.tags_modified = true;
+            }
+            None => {
+                self.current_tags = match self.write_queue.queued_tags(&path) {
+                    Some(tags) => tags.clone(),
+                    None => tag_manager::load_tags(&path),
+                };
                 self.tags_modified = false;
-                self.status_message = "Tags saved".to_string();
             }
         }
+        // 開く・前後に移動する・削除後に次へ進むなど、表示中の画像が変わったとき
+        if self.hooked_image.as_ref() != Some(&path) {
+            self.run_hooks(HookEvent::ImageOpened, &path, &self.current_tags, None, None);
+            self.hooked_image = Some(path);
+        }
     }
 
-    fn handle_keyboard(&mut self, ctx: &egui::Context) {
-        // キー文字列変換ヘルパー
-        fn key_from_str(s: &str) -> Option<Key> {
-            match s.to_lowercase().as_str() {
-                "0" => Some(Key::Num0), "1" => Some(Key::Num1), "2" => Some(Key::Num2),
-                "3" => Some(Key::Num3), "4" => Some(Key::Num4), "5" => Some(Key::Num5),
-                "6" => Some(Key::Num6), "7" => Some(Key::Num7), "8" => Some(Key::Num8),
-                "9" => Some(Key::Num9),
-                "a" => Some(Key::A), "b" => Some(Key::B), "c" => Some(Key::C), "d" => Some(Key::D),
-                "e" => Some(Key::E), "f" => Some(Key::F), "g" => Some(Key::G), "h" => Some(Key::H),
-                "i" => Some(Key::I), "j" => Some(Key::J), "k" => Some(Key::K), "l" => Some(Key::L),
-                "m" => Some(Key::M), "n" => Some(Key::N), "o" => Some(Key::O), "p" => Some(Key::P),
-                "q" => Some(Key::Q), "r" => Some(Key::R), "s" => Some(Key::S), "t" => Some(Key::T),
-                "u" => Some(Key::U), "v" => Some(Key::V), "w" => Some(Key::W), "x" => Some(Key::X),
-                "y" => Some(Key::Y), "z" => Some(Key::Z),
-                _ => None,
-            }
+    /// 表示中の画像から離れる前に未保存のタグを設定どおりに扱う
+    /// 確認できない場面（スライドショーの切り替えなど）では保留にして失わないようにする
+    fn leave_image(&mut self) {
+        if !self.tags_modified {
+            return;
+        }
+        let Some(path) = self.image_viewer.current_image.clone() else {
+            return;
+        };
+        match self.config.unsaved_policy {
+            UnsavedPolicy::AutoSave => self.save_tags(),
+            UnsavedPolicy::Discard => self.tags_modified = false,
+            UnsavedPolicy::Prompt => {}
         }
+        // 確認する設定の場合と保存に失敗した場合
+        if self.tags_modified {
+            self.pending.insert(path, self.current_tags.clone());
+            self.tags_modified = false;
+        }
+    }
 
-        ctx.input(|i| {
-            // Ctrl+S で保存
-            if i.modifiers.ctrl && i.key_pressed(Key::S) {
-                self.save_tags();
+    /// 表示中の画像から離れる操作を行う（確認する設定なら先に確認する）
+    fn request(&mut self, action: LeaveAction) {
+        let leaving_edits = match &action {
+            LeaveAction::Close => self.has_unsaved(),
+            LeaveAction::Open(path) => {
+                self.tags_modified && self.image_viewer.current_image.as_ref() != Some(path)
             }
+            _ => self.tags_modified,
+        };
+        if leaving_edits && self.config.unsaved_policy == UnsavedPolicy::Prompt {
+            self.leave_prompt = Some(action);
+        } else {
+            self.perform(action);
+        }
+    }
 
-            // Delete でゴミ箱へ
-            if i.key_pressed(Key::Delete) {
-                self.delete_current_image();
-            }
+    fn perform(&mut self, action: LeaveAction) {
+        match action {
+            LeaveAction::Prev => self.navigate_prev(),
+            LeaveAction::Next => self.navigate_next(),
+            LeaveAction::Open(path) => self.open_image(path),
+            LeaveAction::OpenPath(path) => self.open_path(&path),
+            LeaveAction::StartSlideshow(playlist) => self.run_playlist(&playlist),
+            // 終了は確認ダイアログで行う
+            LeaveAction::Close => {}
+        }
+    }
 
-            // 左右キーで画像移動
-            if i.key_pressed(Key::ArrowLeft) && !i.modifiers.ctrl {
-                self.navigate_prev();
+    /// 保存していない編集があるか
+    fn has_unsaved(&self) -> bool {
+        self.tags_modified || !self.pending.is_empty()
+    }
+
+    /// 表示中の画像と保留中の編集をすべて保存する
+    fn flush_pending(&mut self) {
+        let mut saved = 0;
+        let mut error = None;
+        if self.tags_modified {
+            self.save_tags();
+            if self.tags_modified {
+                error = Some(self.status_message.clone());
+            } else {
+                saved += 1;
             }
-            if i.key_pressed(Key::ArrowRight) && !i.modifiers.ctrl {
-                self.navigate_next();
+        }
+        for (path, tags) in self.pending.drain() {
+            // 保留中に削除された画像
+            if !path.is_file() {
+                continue;
             }
-
-            // Ctrl+F でファイルツリー表示切り替え
-            if i.modifiers.ctrl && i.key_pressed(Key::F) {
-                self.config.show_left_sidebar = !self.config.show_left_sidebar;
-                self.config.save();
+            match self.write_tags(&path, &tags) {
+                Ok(_) => saved += 1,
+                Err(e) => {
+                    error = Some(format!("{}: {}", path.display(), e));
+                    self.pending.insert(path, tags);
+                }
             }
+        }
+        self.status_message = match error {
+            Some(e) => format!("Error saving pending changes: {}", e),
+            None => format!("Saved {} images", saved),
+        };
+    }
 
-            // Ctrl+T でタグツリー表示切り替え
-            if i.modifiers.ctrl && i.key_pressed(Key::T) {
-                self.config.show_right_sidebar = !self.config.show_right_sidebar;
-                self.config.save();
+    /// 表示中の画像と保留中の編集をすべて破棄する
+    fn discard_pending(&mut self) {
+        self.pending.clear();
+        if let Some(path) = self.image_viewer.current_image.clone() {
+            self.current_tags = tag_manager::load_tags(&path);
+        }
+        self.tags_modified = false;
+        self.status_message = "Discarded pending changes".to_string();
+    }
+
+    /// ウィンドウを閉じようとしたときに未保存の編集を扱う
+    fn handle_close_request(&mut self, ctx: &egui::Context) {
+        if self.allow_close {
+            return;
+        }
+        // 書き込み待ちを済ませる（失敗したものは保留に戻るので確認の対象になる）
+        self.wait_for_writes();
+        if !self.has_unsaved() {
+            return;
+        }
+        match self.config.unsaved_policy {
+            UnsavedPolicy::Discard => return,
+            UnsavedPolicy::AutoSave => {
+                self.flush_pending();
+                if !self.has_unsaved() {
+                    return;
+                }
+                // 保存できなかったものがあれば確認する
             }
+            UnsavedPolicy::Prompt => {}
+        }
+        ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+        self.leave_prompt = Some(LeaveAction::Close);
+    }
 
-            // ホットキー処理
-            let hotkeys: Vec<_> = self.config.hotkey_tags.clone().into_iter().collect();
-            for (key_str, tag) in hotkeys {
-                if let Some(key) = key_from_str(&key_str) {
-                    if i.key_pressed(key) && !i.modifiers.ctrl && !i.modifiers.alt {
-                        tag_manager::toggle_tag(&mut self.current_tags, &tag);
-                        self.tags_modified = true;
-                        if self.config.auto_save {
-                             self.save_tags();
+    /// 未保存の編集の確認ダイアログ
+    fn show_leave_prompt(&mut self, ctx: &egui::Context) {
+        let Some(action) = &self.leave_prompt else {
+            return;
+        };
+        let closing = matches!(action, LeaveAction::Close);
+        let name = self
+            .image_viewer
+            .current_image
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let count = self.pending.len() + usize::from(self.tags_modified);
+
+        let mut choice = None;
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                if closing {
+                    ui.label(format!("{} images have unsaved tag changes.", count));
+                } else {
+                    ui.label(format!("Tags of {} have unsaved changes.", name));
+                }
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    if closing {
+                        if ui.button("Save All and Quit").clicked() {
+                            choice = Some(LeaveChoice::Save);
                         }
+                        if ui.button("Quit Without Saving").clicked() {
+                            choice = Some(LeaveChoice::Discard);
+                        }
+                    } else {
+                        if ui.button("Save").clicked() {
+                            choice = Some(LeaveChoice::Save);
+                        }
+                        if ui.button("Don't Save").clicked() {
+                            choice = Some(LeaveChoice::Discard);
+                        }
+                        if ui
+                            .button("Keep for Later")
+                            .on_hover_text("Keep the changes in the pending list and save them later")
+                            .clicked()
+                        {
+                            choice = Some(LeaveChoice::Keep);
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        choice = Some(LeaveChoice::Cancel);
+                    }
+                });
+            });
+
+        let Some(choice) = choice else {
+            return;
+        };
+        let Some(action) = self.leave_prompt.take() else {
+            return;
+        };
+        if closing {
+            match choice {
+                LeaveChoice::Save => {
+                    self.flush_pending();
+                    if self.has_unsaved() {
+                        return;
                     }
                 }
+                LeaveChoice::Discard => {}
+                LeaveChoice::Keep | LeaveChoice::Cancel => return,
             }
-        });
-    }
-
-    fn navigate_prev(&mut self) {
-        if self.tags_modified && self.config.auto_save {
-            self.save_tags();
+            self.allow_close = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            return;
         }
-        self.image_viewer.prev();
-        if let Some(path) = self.image_viewer.current_image.clone() {
-            self.current_tags = tag_manager::load_tags(&path);
-            self.tags_modified = false;
+        match choice {
+            LeaveChoice::Save => {
+                self.save_tags();
+                // 保存に失敗したら移動しない
+                if self.tags_modified {
+                    return;
+                }
+            }
+            LeaveChoice::Discard => self.tags_modified = false,
+            LeaveChoice::Keep => {
+                if let Some(path) = self.image_viewer.current_image.clone() {
+                    self.pending.insert(path, self.current_tags.clone());
+                }
+                self.tags_modified = false;
+            }
+            LeaveChoice::Cancel => return,
         }
+        self.perform(action);
     }
 
-    fn navigate_next(&mut self) {
-        if self.tags_modified && self.config.auto_save {
-            self.save_tags();
+    /// 監視中のフォルダの変更をツリーと画像リストに反映する
+    fn update_fs_watcher(&mut self) {
+        if !self.config.watch_files {
+            self.fs_watcher.unwatch_all();
+            return;
         }
-        self.image_viewer.next();
-        if let Some(path) = self.image_viewer.current_image.clone() {
-            self.current_tags = tag_manager::load_tags(&path);
-            self.tags_modified = false;
+        let root = self.file_tree.root.as_ref().map(|r| r.path.clone());
+        let current_dir = self
+            .image_viewer
+            .current_image
+            .as_ref()
+            .and_then(|p| p.parent())
+            .map(|p| p.to_path_buf());
+        self.fs_watcher.watch(root.as_deref(), current_dir.as_deref());
+        let Some(changes) = self.fs_watcher.poll() else {
+            return;
+        };
+
+        self.file_tree.refresh(&changes.dirs, &mut self.tag_cache);
+        for dir in &changes.dirs {
+            self.tag_cache.invalidate_dir(dir);
         }
-    }
 
-    fn delete_current_image(&mut self) {
-        if let Some(path) = self.image_viewer.current_image.clone() {
-            // ゴミ箱へ移動
-            if let Err(e) = trash::delete(&path) {
-                self.status_message = format!("Error deleting file: {}", e);
-                return;
+        // 表示中の画像の名前が変わったら追従する
+        for (from, to) in &changes.renamed {
+            self.pending.rename(from, to);
+            self.image_viewer.rename(from, to);
+            if self.current_texture_path.as_ref() == Some(from) {
+                self.current_texture_path = Some(to.clone());
             }
+        }
 
-            self.status_message = format!("Moved to trash: {}", path.display());
+        if current_dir.is_some_and(|dir| changes.dirs.contains(&dir)) && self.image_viewer.refresh(&mut self.tag_cache) {
+            // 表示中の画像が外部で削除された
+            self.tags_modified = false;
+            match self.image_viewer.current_image.clone() {
+                Some(path) => self.open_image(path),
+                None => {
+                    self.current_texture = None;
+                    self.current_texture_path = None;
+                    self.current_animation = None;
+                    self.current_tags.clear();
+                    self.tags_modified = false;
+                }
+            }
+            self.status_message = "Current image was removed outside the app".to_string();
+        }
 
-            // リストから削除して次の画像を表示
-            let mut next_path = None;
-            if let Some(pos) = self.image_viewer.images_in_dir.iter().position(|p| p == &path) {
-                self.image_viewer.images_in_dir.remove(pos);
-                
-                if !self.image_viewer.images_in_dir.is_empty() {
-                    let next_idx = if pos < self.image_viewer.images_in_dir.len() {
-                        pos
-                    } else {
-                        pos - 1
-                    };
-                    next_path = self.image_viewer.images_in_dir.get(next_idx).cloned();
+        if self.compare.is_active {
+            let mut i = 0;
+            while i < self.compare.panes.len() {
+                if self.compare.panes[i].exists() {
+                    i += 1;
+                } else {
+                    self.compare.remove_pane(i);
                 }
             }
+        }
 
-            if let Some(p) = next_path {
-                self.open_image(p);
-            } else {
-                // 画像がなくなった
-                self.image_viewer.close();
+        // サイドカーの変更は画像のタグの変更として扱う
+        let modified: HashSet<PathBuf> = changes
+            .modified
+            .iter()
+            .map(|p| {
+                if p.extension().is_some_and(|e| e.eq_ignore_ascii_case("xmp")) {
+                    p.with_extension("")
+                } else {
+                    p.clone()
+                }
+            })
+            .collect();
+        for path in &modified {
+            self.compare.invalidate(path);
+            self.tag_cache.invalidate(path);
+        }
+        if let Some(current) = self.image_viewer.current_image.clone() {
+            if modified.contains(&current) {
                 self.current_texture = None;
                 self.current_texture_path = None;
+                self.current_animation = None;
+                self.metadata_info = None;
+                // 未保存の編集があればそちらを優先する
+                if !self.tags_modified {
+                    self.current_tags = tag_manager::load_tags(&current);
+                }
+                self.status_message = format!("Reloaded: {}", current.display());
             }
-            
-             self.current_tags.clear();
-             self.tags_modified = false;
         }
     }
 
-    fn show_left_sidebar(&mut self, ui: &mut egui::Ui) {
-        egui::ScrollArea::vertical().show(ui, |ui| {
-            if let Some(root) = self.file_tree.root.clone() {
-                self.show_file_node(ui, &root);
-            } else {
-                ui.label("Drop a folder or image here");
-            }
-        });
+    /// 並び順の設定を画像リストとファイルツリーに反映する
+    fn apply_sort_settings(&mut self) {
+        self.image_viewer.sort = self.config.sort.clone();
+        self.image_viewer.resort(&mut self.tag_cache);
+        self.file_tree.sort = self.config.sort.clone();
+        self.file_tree.resort(&mut self.tag_cache);
     }
 
-    fn show_file_node(&mut self, ui: &mut egui::Ui, node: &FileNode) {
-        if node.is_dir {
-            let is_expanded = self.file_tree.is_expanded(&node.path);
-            let icon = if is_expanded { "📂" } else { "📁" };
+    /// 現在操作対象のフォルダ（表示中の画像のフォルダ、なければツリーのルート）
+    fn current_folder(&self) -> Option<PathBuf> {
+        self.image_viewer
+            .current_image
+            .as_ref()
+            .and_then(|p| p.parent())
+            .map(|p| p.to_path_buf())
+            .or_else(|| self.file_tree.root.as_ref().map(|r| r.path.clone()))
+    }
 
-            let header = egui::CollapsingHeader::new(format!("{} {}", icon, node.name))
-                .open(Some(is_expanded));
+    fn show_sort_menu(&mut self, ui: &mut egui::Ui) {
+        let Some(folder) = self.current_folder() else {
+            ui.label("(No folder)");
+            return;
+        };
+        let current = self.config.sort.order_for(&folder);
+        let seed = match current {
+            SortOrder::Random { seed } => seed,
+            _ => sort_order::new_seed(),
+        };
 
-            let response = header.show(ui, |ui| {
-                for child in &node.children {
-                    self.show_file_node(ui, child);
+        let mut selected = None;
+        for order in [
+            SortOrder::Natural,
+            SortOrder::Modified,
+            SortOrder::DateTaken,
+            SortOrder::FileSize,
+            SortOrder::TagCount,
+            SortOrder::Random { seed },
+        ] {
+            let is_selected = std::mem::discriminant(&order) == std::mem::discriminant(&current);
+            if ui.radio(is_selected, order.label()).clicked() {
+                selected = Some(order);
+            }
+        }
+        if let SortOrder::Random { seed } = current {
+            ui.horizontal(|ui| {
+                let mut seed = seed;
+                ui.label("Seed:");
+                if ui.add(egui::DragValue::new(&mut seed)).changed() {
+                    selected = Some(SortOrder::Random { seed });
+                }
+                if ui.small_button("🔀").on_hover_text("Reshuffle").clicked() {
+                    selected = Some(SortOrder::Random { seed: sort_order::new_seed() });
                 }
             });
+        }
+        ui.separator();
+        if ui.button("Use for all folders").clicked() {
+            self.config.sort.default = current;
+            self.config.sort.per_folder.clear();
+            self.config.save();
+            self.apply_sort_settings();
+            ui.close_menu();
+        }
 
-            if response.header_response.clicked() {
-                self.file_tree.toggle_expanded(&node.path);
+        // 並び順はフォルダごとに記憶する
+        if let Some(order) = selected {
+            self.config.sort.per_folder.insert(folder, order);
+            self.config.save();
+            self.apply_sort_settings();
+            self.status_message = format!("Sorted by {}", order.label());
+        }
+    }
+
+    fn save_tags(&mut self) {
+        if let Some(path) = self.image_viewer.current_image.clone() {
+            let tags = self.current_tags.clone();
+            if self.config.background_writes {
+                // 結果は handle_write_results で受け取る
+                self.tag_cache.set(&path, &tags);
+                self.write_queue.submit(path, tags, self.write_options());
+                self.tags_modified = false;
+                return;
+            }
+            match self.write_tags(&path, &tags) {
+                Err(e) => self.status_message = format!("Error saving tags: {}", e),
+                Ok(problem) => {
+                    self.tags_modified = false;
+                    self.metadata_info = None;
+                    self.status_message = match problem {
+                        Some(problem) => Self::verification_message(&path, &problem),
+                        None => "Tags saved".to_string(),
+                    };
+                }
+            }
+        }
+    }
+
+    fn write_options(&self) -> WriteOptions {
+        WriteOptions {
+            backup: self.config.backup_originals.then_some(self.config.backup_limit),
+            preserve_timestamps: self.config.preserve_timestamps,
+            verify: self.config.verify_writes,
+            previous_tags: hooks::watches_tags(&self.config.hooks),
+        }
+    }
+
+    /// タグをすぐに書き込む。書き込めたが確認で問題が見つかった場合はその内容を返す
+    fn write_tags(&mut self, path: &Path, tags: &[String]) -> std::io::Result<Option<String>> {
+        // 待っている古いタグの書き込みで後から上書きされないよう先に済ませる
+        if self.write_queue.is_queued(path) {
+            self.wait_for_writes();
+        }
+        match write_queue::write_tags(path, tags, self.write_options()) {
+            Ok(written) => {
+                self.note_written(path, tags);
+                self.run_tag_hooks(path, tags, written.previous.as_deref());
+                Ok(written.problem)
+            }
+            Err(e) => {
+                self.run_hooks(HookEvent::SaveFailed, path, tags, None, Some(&e.to_string()));
+                Err(e)
+            }
+        }
+    }
+
+    /// イベントに合うフックを実行する
+    fn run_hooks(&self, event: HookEvent, path: &Path, tags: &[String], tag: Option<&str>, error: Option<&str>) {
+        let context = HookContext { path, tags, tag, error };
+        let timeout = Duration::from_secs(self.config.hook_timeout);
+        self.hooks.fire(&self.config.hooks, event, &context, timeout);
+    }
+
+    /// 書き込みで追加・削除されたタグのフックを実行する
+    fn run_tag_hooks(&self, path: &Path, tags: &[String], previous: Option<&[String]>) {
+        let Some(previous) = previous else {
+            return;
+        };
+        for tag in tags.iter().filter(|t| !previous.contains(t)) {
+            self.run_hooks(HookEvent::TagAdded, path, tags, Some(tag), None);
+        }
+        for tag in previous.iter().filter(|t| !tags.contains(t)) {
+            self.run_hooks(HookEvent::TagRemoved, path, tags, Some(tag), None);
+        }
+    }
+
+    /// 終わったフックの出力をステータスに表示する
+    fn handle_hook_outputs(&mut self) {
+        for output in self.hooks.poll() {
+            self.status_message = match &output.result {
+                Ok(text) => match text.lines().last() {
+                    Some(line) => format!("Hook ({}): {}", output.label, line),
+                    None => format!("Hook ({}) finished", output.label),
+                },
+                Err(e) => format!("Hook ({}) failed: {}", output.label, e.lines().next().unwrap_or_default()),
+            };
+            self.hook_log.insert(0, output);
+            self.hook_log.truncate(MAX_HOOK_LOG);
+        }
+    }
+
+    /// 書き込んだタグをキャッシュと変更監視に反映する
+    fn note_written(&mut self, path: &Path, tags: &[String]) {
+        self.tag_cache.set(path, tags);
+        self.compare.invalidate_tags(path);
+        self.fs_watcher.note_write(path);
+        self.fs_watcher.note_write(&sidecar::sidecar_path(path));
+        if self.metadata_info.as_ref().is_some_and(|(p, _)| p == path) {
+            self.metadata_info = None;
+        }
+        if let Some(api) = &self.api {
+            api.publish("saved", &json!({ "path": path, "tags": tags }));
+        }
+        self.suggester.invalidate();
+    }
+
+    /// バックグラウンドでの書き込みの結果を反映する
+    fn handle_write_results(&mut self, results: Vec<WriteResult>) {
+        for result in results {
+            let WriteResult { path, tags, outcome } = result;
+            let newer_queued = self.write_queue.is_queued(&path);
+            match outcome {
+                Ok(written) => {
+                    if newer_queued {
+                        self.fs_watcher.note_write(&path);
+                        self.fs_watcher.note_write(&sidecar::sidecar_path(&path));
+                    } else {
+                        self.note_written(&path, &tags);
+                    }
+                    self.run_tag_hooks(&path, &tags, written.previous.as_deref());
+                    self.status_message = match written.problem {
+                        Some(problem) => Self::verification_message(&path, &problem),
+                        None => "Tags saved".to_string(),
+                    };
+                }
+                Err(e) => {
+                    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                    self.status_message = format!("Error saving tags: {}: {}", name, e);
+                    self.run_hooks(HookEvent::SaveFailed, &path, &tags, None, Some(&e));
+                    if newer_queued {
+                        continue;
+                    }
+                    // 書き込めなかった編集は保留に戻す（新しい編集があればそちらを優先）
+                    self.tag_cache.invalidate(&path);
+                    if !path.is_file() {
+                        continue;
+                    }
+                    if self.image_viewer.current_image.as_ref() == Some(&path) {
+                        if !self.tags_modified {
+                            self.current_tags = tags;
+                            self.tags_modified = true;
+                        }
+                    } else if !self.pending.contains(&path) {
+                        self.pending.insert(path, tags);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 書き込み待ちをすべて終わらせる（ファイル操作の前や終了時）
+    fn wait_for_writes(&mut self) {
+        let results = self.write_queue.flush();
+        self.handle_write_results(results);
+    }
+
+    /// 書き込み後の確認で問題が見つかったときのステータス表示
+    fn verification_message(path: &Path, problem: &str) -> String {
+        let hint = if safe_write::has_backup(path) {
+            " Use File > Restore Original."
+        } else {
+            ""
+        };
+        format!(
+            "⚠ Tags saved but verification failed: {} (logged to {}).{}",
+            problem,
+            write_check::log_path().display(),
+            hint
+        )
+    }
+
+    /// 表示中の画像をバックアップした元の状態に戻す
+    fn restore_original(&mut self) {
+        let Some(path) = self.image_viewer.current_image.clone() else {
+            return;
+        };
+        self.wait_for_writes();
+        self.tags_modified = false;
+        match safe_write::restore_original(&path) {
+            Ok(()) => {
+                self.fs_watcher.note_write(&path);
+                self.tag_cache.invalidate(&path);
+                self.metadata_info = None;
+                self.open_image(path.clone());
+                self.status_message = format!("Restored original: {}", path.display());
+            }
+            Err(e) => self.status_message = format!("Error restoring original: {}", e),
+        }
+    }
+
+    /// HTTP API を起動する（起動できなければ無効にする）
+    fn start_api(&mut self, ctx: &egui::Context) {
+        self.api = None;
+        let token = if self.config.api_token.is_empty() {
+            http_api::generate_token()
+        } else {
+            Ok(self.config.api_token.clone())
+        };
+        let started = token.and_then(|token| {
+            self.config.api_token = token;
+            ApiServer::start(ctx, self.config.api_port, &self.config.api_token)
+        });
+        match started {
+            Ok(api) => {
+                self.status_message = format!("HTTP API listening on 127.0.0.1:{}", api.port());
+                self.api = Some(api);
+                self.config.api_enabled = true;
+            }
+            Err(e) => {
+                self.status_message = format!("Error starting HTTP API: {}", e);
+                self.config.api_enabled = false;
+            }
+        }
+        self.config.save();
+    }
+
+    /// 表示中の画像の状態（API の応答と current イベント）
+    fn api_state(&self) -> Value {
+        json!({
+            "path": self.image_viewer.current_image,
+            "index": self.image_viewer.current_image.as_ref().map(|_| self.image_viewer.current_index),
+            "count": self.image_viewer.total_images(),
+            "tags": self.current_tags,
+            "modified": self.tags_modified,
+        })
+    }
+
+    /// API からの依頼を実行する
+    fn handle_api_requests(&mut self) {
+        let Some(api) = &self.api else {
+            return;
+        };
+        for request in api.poll() {
+            let result = match &request.command {
+                ApiCommand::Current => Ok(self.api_state()),
+                ApiCommand::Open(path) => self.api_open(path),
+                ApiCommand::AddTags { path, tags } => self.edit_tags(path.as_deref(), tags, true),
+                ApiCommand::RemoveTags { path, tags } => self.edit_tags(path.as_deref(), tags, false),
+                ApiCommand::CurrentFolder => Ok(json!(self.current_folder())),
+                ApiCommand::CachedTags(paths) => Ok(paths.iter().map(|p| json!(self.tag_cache.cached(p))).collect()),
+            };
+            request.respond(result);
+        }
+    }
+
+    fn api_open(&mut self, path: &Path) -> Result<Value, String> {
+        if !(path.is_dir() || (path.is_file() && is_image_file(path))) {
+            return Err(format!("Not found: {}", path.display()));
+        }
+        // 確認ダイアログは出さず、未保存の編集は設定どおりに扱う（確認する設定なら保留にする）
+        self.perform(LeaveAction::OpenPath(path.to_path_buf()));
+        Ok(self.api_state())
+    }
+
+    /// タグを追加・削除して保存する（API・コマンドラインから）
+    /// 画像に未保存の編集があればそれに重ねて保存する（エディタで編集して保存したのと同じ）
+    fn edit_tags(&mut self, path: Option<&Path>, tags: &[String], add: bool) -> Result<Value, String> {
+        let edit = |current: &mut Vec<String>| {
+            for tag in tags {
+                if add {
+                    tag_manager::add_tag(current, tag);
+                } else {
+                    tag_manager::remove_tag(current, tag);
+                }
+            }
+        };
+
+        let current = self.image_viewer.current_image.clone();
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => current.clone().ok_or("No image is open")?,
+        };
+        if current.as_ref() == Some(&path) {
+            edit(&mut self.current_tags);
+            self.tags_modified = true;
+            self.save_tags();
+            return Ok(json!({ "path": path, "tags": self.current_tags }));
+        }
+
+        if !path.is_file() || !is_image_file(&path) {
+            return Err(format!("Not an image: {}", path.display()));
+        }
+        let mut new_tags = match self.pending.take(&path) {
+            Some(tags) => tags,
+            None => match self.write_queue.queued_tags(&path) {
+                Some(tags) => tags.clone(),
+                None => tag_manager::load_tags(&path),
+            },
+        };
+        edit(&mut new_tags);
+        if self.config.background_writes {
+            self.tag_cache.set(&path, &new_tags);
+            self.write_queue
+                .submit(path.clone(), new_tags.clone(), self.write_options());
+        } else if let Err(e) = self.write_tags(&path, &new_tags) {
+            return Err(format!("Error saving tags: {}", e));
+        }
+        Ok(json!({ "path": path, "tags": new_tags }))
+    }
+
+    /// コマンドラインで指定された操作を行う（2つ目の起動から渡されたものも）
+    fn run_invocation(&mut self, invocation: Invocation) {
+        if let Some(path) = &invocation.path {
+            // 確認ダイアログは出さず、未保存の編集は設定どおりに扱う
+            self.perform(LeaveAction::OpenPath(path.clone()));
+        }
+        let target = invocation.path.as_deref();
+        if target.is_some_and(|p| p.is_dir())
+            && !(invocation.add_tags.is_empty() && invocation.remove_tags.is_empty())
+        {
+            self.status_message = "Tags can only be changed on an image".to_string();
+            return;
+        }
+        for (tags, add) in [(&invocation.add_tags, true), (&invocation.remove_tags, false)] {
+            if tags.is_empty() {
+                continue;
+            }
+            self.status_message = match self.edit_tags(target, tags, add) {
+                Ok(_) if add => format!("Added tags: {}", tags.join(", ")),
+                Ok(_) => format!("Removed tags: {}", tags.join(", ")),
+                Err(e) => e,
+            };
+        }
+    }
+
+    /// 2つ目の起動から渡された操作を行ってウィンドウを前面に出す
+    fn handle_instance_requests(&mut self, ctx: &egui::Context) {
+        let Some(instance) = &self.instance else {
+            return;
+        };
+        let invocations = instance.poll();
+        if invocations.is_empty() {
+            return;
+        }
+        for invocation in invocations {
+            self.run_invocation(invocation);
+        }
+        ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+    }
+
+    fn handle_keyboard(&mut self, ctx: &egui::Context) {
+        // キー文字列変換ヘルパー
+        fn key_from_str(s: &str) -> Option<Key> {
+            match s.to_lowercase().as_str() {
+                "0" => Some(Key::Num0), "1" => Some(Key::Num1), "2" => Some(Key::Num2),
+                "3" => Some(Key::Num3), "4" => Some(Key::Num4), "5" => Some(Key::Num5),
+                "6" => Some(Key::Num6), "7" => Some(Key::Num7), "8" => Some(Key::Num8),
+                "9" => Some(Key::Num9),
+                "a" => Some(Key::A), "b" => Some(Key::B), "c" => Some(Key::C), "d" => Some(Key::D),
+                "e" => Some(Key::E), "f" => Some(Key::F), "g" => Some(Key::G), "h" => Some(Key::H),
+                "i" => Some(Key::I), "j" => Some(Key::J), "k" => Some(Key::K), "l" => Some(Key::L),
+                "m" => Some(Key::M), "n" => Some(Key::N), "o" => Some(Key::O), "p" => Some(Key::P),
+                "q" => Some(Key::Q), "r" => Some(Key::R), "s" => Some(Key::S), "t" => Some(Key::T),
+                "u" => Some(Key::U), "v" => Some(Key::V), "w" => Some(Key::W), "x" => Some(Key::X),
+                "y" => Some(Key::Y), "z" => Some(Key::Z),
+                _ => None,
+            }
+        }
+
+        // テキスト入力中（テンプレートや検索条件など）はホットキーを無効にする
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        ctx.input(|i| {
+            // Ctrl+S で保存
+            if i.modifiers.ctrl && i.key_pressed(Key::S) {
+                self.save_tags();
+            }
+
+            // Delete でゴミ箱へ
+            if i.key_pressed(Key::Delete) {
+                self.delete_current_image();
+            }
+
+            // 左右キーで画像移動（比較モード中はフォーカス中のペインを差し替え）
+            if i.key_pressed(Key::ArrowLeft) && !i.modifiers.ctrl {
+                if self.slideshow.is_running {
+                    self.slideshow_prev();
+                } else if self.compare.is_active {
+                    self.compare_step(false);
+                } else {
+                    self.request(LeaveAction::Prev);
+                }
+            }
+            if i.key_pressed(Key::ArrowRight) && !i.modifiers.ctrl {
+                if self.slideshow.is_running {
+                    self.slideshow_next();
+                } else if self.compare.is_active {
+                    self.compare_step(true);
+                } else {
+                    self.request(LeaveAction::Next);
+                }
+            }
+
+            // Ctrl+G で比較モード切り替え
+            if i.modifiers.ctrl && i.key_pressed(Key::G) {
+                self.toggle_compare();
+            }
+            if self.compare.is_active {
+                // Tab でフォーカス中のペインを切り替え
+                if i.key_pressed(Key::Tab) && !self.compare.panes.is_empty() {
+                    let next = (self.compare.focused + 1) % self.compare.panes.len();
+                    self.focus_compare_pane(next);
+                }
+                if i.key_pressed(Key::Escape) {
+                    self.compare.stop();
+                }
+            }
+
+            // スライドショー中は Space で一時停止/再開
+            if self.slideshow.is_running && i.key_pressed(Key::Space) {
+                self.slideshow.toggle_pause();
+            } else if let Some(anim) = &mut self.current_animation {
+                // アニメーション操作（Space で一時停止/再開、, . でコマ送り）
+                if i.key_pressed(Key::Space) {
+                    anim.toggle_pause();
+                }
+                if i.key_pressed(Key::Comma) {
+                    anim.step_back();
+                }
+                if i.key_pressed(Key::Period) {
+                    anim.step_forward();
+                }
+            }
+
+            // Ctrl+F でファイルツリー表示切り替え
+            if i.modifiers.ctrl && i.key_pressed(Key::F) {
+                self.config.show_left_sidebar = !self.config.show_left_sidebar;
+                self.config.save();
+            }
+
+            // Ctrl+T でタグツリー表示切り替え
+            if i.modifiers.ctrl && i.key_pressed(Key::T) {
+                self.config.show_right_sidebar = !self.config.show_right_sidebar;
+                self.config.save();
+            }
+
+            // Ctrl+D で表示中のフォルダをブックマーク
+            if i.modifiers.ctrl && i.key_pressed(Key::D) {
+                self.toggle_bookmark();
+            }
+
+            // Ctrl+1〜9 でブックマーク、Alt+1〜9 で最近のフォルダへ移動
+            const DIGITS: [Key; 9] = [
+                Key::Num1, Key::Num2, Key::Num3, Key::Num4, Key::Num5,
+                Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+            ];
+            for (n, key) in DIGITS.into_iter().enumerate() {
+                if !i.key_pressed(key) {
+                    continue;
+                }
+                let target = if i.modifiers.ctrl {
+                    self.config.bookmarks.get(n)
+                } else if i.modifiers.alt && !i.modifiers.shift {
+                    self.config.recent_folders.get(n)
+                } else {
+                    None
+                };
+                if let Some(path) = target.cloned() {
+                    self.request(LeaveAction::OpenPath(path));
+                }
+            }
+
+            // Ctrl+R でタグなし画像のレビューキューを開始/終了
+            if i.modifiers.ctrl && i.key_pressed(Key::R) && self.review.take().is_none() {
+                self.start_review(ReviewQueue::untagged());
+            }
+
+            // Ctrl+I でメタデータウィンドウ表示切り替え
+            if i.modifiers.ctrl && i.key_pressed(Key::I) {
+                self.config.show_metadata_window = !self.config.show_metadata_window;
+                self.config.save();
+            }
+
+            // Ctrl+Z でファイル操作を元に戻す
+            if i.modifiers.ctrl && i.key_pressed(Key::Z) {
+                self.undo_file_op();
+            }
+
+            // Shift+キーで移動、Shift+Alt+キーでコピー
+            let targets: Vec<_> = self
+                .config
+                .hotkey_move
+                .clone()
+                .into_iter()
+                .map(|(key, dir)| (key, dir, false))
+                .chain(self.config.hotkey_copy.clone().into_iter().map(|(key, dir)| (key, dir, true)))
+                .collect();
+            for (key_str, dir, copy) in targets {
+                if let Some(key) = key_from_str(&key_str) {
+                    if i.key_pressed(key) && i.modifiers.shift && !i.modifiers.ctrl && i.modifiers.alt == copy {
+                        self.transfer_current_image(&dir, copy);
+                    }
+                }
+            }
+
+            // ホットキー処理
+            let hotkeys: Vec<_> = self.config.hotkey_tags.clone().into_iter().collect();
+            for (key_str, tag) in hotkeys {
+                if let Some(key) = key_from_str(&key_str) {
+                    if i.key_pressed(key) && !i.modifiers.ctrl && !i.modifiers.alt && !i.modifiers.shift {
+                        tag_manager::toggle_tag(&mut self.current_tags, &tag);
+                        self.tags_modified = true;
+                        if self.config.auto_save {
+                             self.save_tags();
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn navigate_prev(&mut self) {
+        if self.review.is_some() {
+            let matching = self.review_matching();
+            let current = self.image_viewer.current_index;
+            if !matching.iter().enumerate().any(|(i, &m)| m && i != current) {
+                self.status_message = "No other images in the review queue".to_string();
+                return;
+            }
+            self.leave_image();
+            self.image_viewer.prev_matching(&matching);
+        } else {
+            self.leave_image();
+            self.image_viewer.prev();
+        }
+        self.load_current_tags();
+    }
+
+    fn navigate_next(&mut self) {
+        if self.review.is_some() {
+            let matching = self.review_matching();
+            let current = self.image_viewer.current_index;
+            if !matching.iter().enumerate().any(|(i, &m)| m && i != current) {
+                self.status_message = "No other images in the review queue".to_string();
+                return;
+            }
+            self.leave_image();
+            self.image_viewer.next_matching(&matching);
+        } else {
+            self.leave_image();
+            self.image_viewer.next();
+        }
+        self.load_current_tags();
+    }
+
+    /// 画像リストの各画像がレビューキューの条件に合うか（表示中の画像は未保存の編集で判定）
+    fn review_matching(&mut self) -> Vec<bool> {
+        let Some(review) = &self.review else {
+            return Vec::new();
+        };
+        self.image_viewer
+            .images_in_dir
+            .iter()
+            .map(|path| {
+                if self.image_viewer.current_image.as_ref() == Some(path) {
+                    review.matches(&self.current_tags)
+                } else {
+                    review.matches(self.tag_cache.tags(path))
+                }
+            })
+            .collect()
+    }
+
+    /// レビューキューを開始する（表示中の画像が対象外なら最初の対象へ移動）
+    fn start_review(&mut self, review: ReviewQueue) {
+        self.status_message = format!("Review queue: {}", review.label);
+        self.review = Some(review);
+        let matching = self.review_matching();
+        if !matching.get(self.image_viewer.current_index).copied().unwrap_or(false) {
+            self.navigate_next();
+        }
+    }
+
+    /// レビューキューの残り数を更新する
+    fn update_review(&mut self) {
+        let matching = self.review_matching();
+        let folder = self.current_folder();
+        if let (Some(review), Some(folder)) = (&mut self.review, folder) {
+            review.update(&folder, &matching);
+        }
+    }
+
+    fn delete_current_image(&mut self) {
+        self.wait_for_writes();
+        if let Some(path) = self.image_viewer.current_image.clone() {
+            // ゴミ箱へ移動
+            if let Err(e) = trash::delete(&path) {
+                self.status_message = format!("Error deleting file: {}", e);
+                return;
+            }
+
+            self.status_message = format!("Moved to trash: {}", path.display());
+            self.run_hooks(HookEvent::FileTrashed, &path, &self.current_tags, None, None);
+            if let Some(parent) = path.parent() {
+                self.tag_cache.invalidate_dir(parent);
+            }
+            self.advance_after_removal(&path);
+        }
+    }
+
+    /// 表示中の画像がフォルダからなくなったので次の画像を表示する
+    fn advance_after_removal(&mut self, path: &Path) {
+        // なくなった画像の編集は残さない
+        self.tags_modified = false;
+        self.pending.take(path);
+        // 比較モード中はフォーカス中のペインを次の候補に差し替える
+        if self.compare.is_active {
+            let candidates = self.image_viewer.images_in_dir.clone();
+            let replaced = self
+                .compare
+                .replace_focused(&candidates, true)
+                .or_else(|| self.compare.replace_focused(&candidates, false));
+            if replaced.is_none() {
+                self.compare.remove_pane(self.compare.focused);
+            }
+            self.image_viewer.images_in_dir.retain(|p| p != path);
+
+            if let Some(p) = self.compare.focused_path().cloned() {
+                self.open_image(p);
+            } else {
+                self.image_viewer.close();
+                self.current_texture = None;
+                self.current_texture_path = None;
+                self.current_animation = None;
+                self.current_tags.clear();
+                self.tags_modified = false;
+            }
+            return;
+        }
+
+        // リストから削除して次の画像を表示
+        let mut next_path = None;
+        if let Some(pos) = self.image_viewer.images_in_dir.iter().position(|p| p == path) {
+            self.image_viewer.images_in_dir.remove(pos);
+            
+            if !self.image_viewer.images_in_dir.is_empty() {
+                let next_idx = if pos < self.image_viewer.images_in_dir.len() {
+                    pos
+                } else {
+                    pos - 1
+                };
+                next_path = self.image_viewer.images_in_dir.get(next_idx).cloned();
+            }
+        }
+
+        if let Some(p) = next_path {
+            self.open_image(p);
+        } else {
+            // 画像がなくなった
+            self.image_viewer.close();
+            self.current_texture = None;
+            self.current_texture_path = None;
+            self.current_animation = None;
+            self.current_tags.clear();
+            self.tags_modified = false;
+        }
+    }
+
+    /// 表示中の画像をフォルダに移動/コピーする（移動した場合は次の画像へ進む）
+    fn transfer_current_image(&mut self, dir: &Path, copy: bool) {
+        let Some(path) = self.image_viewer.current_image.clone() else {
+            return;
+        };
+        self.settle_before_file_op();
+
+        let strategy = self.config.collision_strategy;
+        let result = if copy {
+            file_ops::copy_to(&path, dir, strategy)
+        } else {
+            file_ops::move_to(&path, dir, strategy)
+        };
+        let op = match result {
+            Ok(Some(op)) => op,
+            Ok(None) => {
+                self.status_message = format!("Skipped: file already exists in {}", dir.display());
+                return;
+            }
+            Err(e) => {
+                self.status_message = format!("Error transferring file: {}", e);
+                return;
+            }
+        };
+
+        let mut dirs: HashSet<PathBuf> = path.parent().map(Path::to_path_buf).into_iter().collect();
+        dirs.insert(dir.to_path_buf());
+        self.refresh_dirs(&dirs);
+
+        let verb = if copy { "Copied" } else { "Moved" };
+        if !copy {
+            self.advance_after_removal(&path);
+        }
+        self.undo_stack.push(vec![op]);
+        self.status_message = format!("{} to {} (Ctrl+Z to undo)", verb, dir.display());
+    }
+
+    /// 表示中の画像を移動・リネームする前に未保存のタグを扱う
+    /// （ファイルごと操作するので、破棄する設定でなければ確認せずに保存する）
+    fn settle_before_file_op(&mut self) {
+        if self.tags_modified {
+            if self.config.unsaved_policy == UnsavedPolicy::Discard {
+                self.load_current_tags();
+            } else {
+                self.save_tags();
+            }
+        }
+        self.wait_for_writes();
+    }
+
+    /// ライブラリモードを切り替える（タグの読み込み先が変わるので読み直す）
+    fn set_library_mode(&mut self, enabled: bool) {
+        // 待っている書き込みは切り替える前の保存先に書く
+        self.wait_for_writes();
+        self.config.library_mode = enabled;
+        self.config.save();
+        tag_database::set_enabled(enabled);
+        self.reload_all_tags();
+        self.status_message = if enabled {
+            "Library mode: tags are stored in the database, images are not modified".to_string()
+        } else {
+            "Library mode off: tags are embedded in images".to_string()
+        };
+    }
+
+    /// 表示中のフォルダの画像とデータベースの間でタグを同期する
+    fn sync_library(&mut self, push: bool) {
+        self.wait_for_writes();
+        let paths = self.image_viewer.images_in_dir.clone();
+        let result = if push {
+            let options = WriteOptions {
+                previous_tags: false,
+                ..self.write_options()
+            };
+            tag_database::push(&paths, options)
+        } else {
+            tag_database::pull(&paths)
+        };
+        match result {
+            Ok(report) => {
+                if push {
+                    for path in &paths {
+                        self.fs_watcher.note_write(path);
+                        self.fs_watcher.note_write(&sidecar::sidecar_path(path));
+                    }
+                }
+                self.reload_all_tags();
+                let verb = if push { "Pushed tags into" } else { "Pulled tags from" };
+                self.status_message = if report.failed > 0 {
+                    format!("{} {} images ({} failed)", verb, report.changed, report.failed)
+                } else {
+                    format!("{} {} images", verb, report.changed)
+                };
+            }
+            Err(e) => self.status_message = format!("Error syncing tag library: {}", e),
+        }
+    }
+
+    /// キャッシュしたタグを捨てて読み直す
+    fn reload_all_tags(&mut self) {
+        self.tag_cache = TagCache::default();
+        for path in self.compare.panes.clone() {
+            self.compare.invalidate_tags(&path);
+        }
+        self.metadata_info = None;
+        if !self.tags_modified {
+            self.load_current_tags();
+        }
+    }
+
+    /// 最後のファイル操作を元に戻す
+    fn undo_file_op(&mut self) {
+        let Some(ops) = self.undo_stack.pop() else {
+            self.status_message = "Nothing to undo".to_string();
+            return;
+        };
+        self.wait_for_writes();
+
+        let mut dirs = HashSet::new();
+        // 表示中の画像が名前を戻されたら追従する
+        let mut current = self.image_viewer.current_image.clone();
+        let mut error = None;
+        // 後に行った操作から戻す
+        for (i, op) in ops.iter().enumerate().rev() {
+            if let Err(e) = op.undo() {
+                // 戻せなかった操作は残しておく
+                self.undo_stack.push(ops[..=i].to_vec());
+                error = Some(e);
+                break;
+            }
+            match op {
+                FileOp::Moved { from, to } => {
+                    self.pending.rename(to, from);
+                    dirs.extend(from.parent().map(Path::to_path_buf));
+                    dirs.extend(to.parent().map(Path::to_path_buf));
+                    // 1つだけの移動（移動して次へ進んだ場合）は戻した画像を表示する
+                    if current.as_ref() == Some(to) || ops.len() == 1 {
+                        current = Some(from.clone());
+                    }
+                }
+                FileOp::Copied { to } => {
+                    dirs.extend(to.parent().map(Path::to_path_buf));
+                }
+            }
+        }
+        self.refresh_dirs(&dirs);
+
+        if current != self.image_viewer.current_image {
+            if let Some(path) = current.filter(|p| p.exists()) {
+                self.open_image(path);
+            }
+        } else if self.image_viewer.refresh(&mut self.tag_cache) {
+            // 表示中の画像の一覧を読み直す
+            if let Some(path) = self.image_viewer.current_image.clone() {
+                self.open_image(path);
+            }
+        }
+        self.status_message = match error {
+            Some(e) => format!("Error undoing: {}", e),
+            None => "Undone last file operation".to_string(),
+        };
+    }
+
+    /// 一括リネームダイアログを開く（表示中のフォルダの画像。ツリーの絞り込みがあれば合うものを選択）
+    fn open_batch_rename(&mut self) {
+        let Some(folder) = self.current_folder() else {
+            self.status_message = "Open a folder first".to_string();
+            return;
+        };
+        let images = Playlist::Dynamic {
+            query: String::new(),
+            folders: vec![folder.clone()],
+            recursive: false,
+            order: self.config.sort.order_for(&folder),
+        }
+        .resolve();
+        let mut rename = BatchRename::new(images, self.config.rename_template.clone());
+        let query = TagQuery::parse(&self.tree_filter);
+        if !query.is_empty() {
+            rename.select_matching(&query);
+        }
+        self.batch_rename = Some(rename);
+    }
+
+    fn show_batch_rename_dialog(&mut self, ctx: &egui::Context) {
+        let Some(rename) = &mut self.batch_rename else {
+            return;
+        };
+        let mut open = true;
+        let mut execute = false;
+
+        egui::Window::new("Batch Rename")
+            .open(&mut open)
+            .default_size([640.0, 480.0])
+            .show(ctx, |ui| {
+                let mut changed = false;
+                ui.horizontal(|ui| {
+                    ui.label("Template:");
+                    changed |= ui
+                        .add(egui::TextEdit::singleline(&mut rename.template).desired_width(320.0))
+                        .changed();
+                    ui.label("Start:");
+                    changed |= ui.add(egui::DragValue::new(&mut rename.start)).changed();
+                });
+                ui.label(RichText::new(batch_rename::TEMPLATE_HELP).small().weak());
+                ui.horizontal(|ui| {
+                    if ui.button("Select All").clicked() {
+                        rename.items.iter_mut().for_each(|i| i.selected = true);
+                        changed = true;
+                    }
+                    if ui.button("Select None").clicked() {
+                        rename.items.iter_mut().for_each(|i| i.selected = false);
+                        changed = true;
+                    }
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical()
+                    .max_height(ui.available_height() - 40.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("batch_rename_preview")
+                            .striped(true)
+                            .show(ui, |ui| {
+                                ui.label("");
+                                ui.strong("Current name");
+                                ui.strong("New name");
+                                ui.label("");
+                                ui.end_row();
+                                for item in &mut rename.items {
+                                    changed |= ui.checkbox(&mut item.selected, "").changed();
+                                    let name = |p: &Path| {
+                                        p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+                                    };
+                                    ui.label(name(&item.from));
+                                    match &item.to {
+                                        Some(to) if to == &item.from => {
+                                            ui.label(RichText::new("(unchanged)").weak());
+                                        }
+                                        Some(to) => {
+                                            ui.label(name(to));
+                                        }
+                                        None => {
+                                            ui.label("");
+                                        }
+                                    }
+                                    match &item.problem {
+                                        Some(problem) => {
+                                            ui.colored_label(Color32::from_rgb(230, 90, 90), problem);
+                                        }
+                                        None => {
+                                            ui.label("");
+                                        }
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+                if changed {
+                    rename.update_preview();
+                }
+
+                ui.separator();
+                let count = rename.renames().len();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            count > 0 && !rename.has_problems(),
+                            egui::Button::new(format!("Rename {} files", count)),
+                        )
+                        .clicked()
+                    {
+                        execute = true;
+                    }
+                    if rename.has_problems() {
+                        ui.colored_label(Color32::from_rgb(230, 90, 90), "Resolve conflicts first");
+                    }
+                });
+            });
+
+        if execute {
+            self.run_batch_rename();
+        } else if !open {
+            self.batch_rename = None;
+        }
+    }
+
+    /// タグの統計を集計してウィンドウを開く
+    fn open_tag_stats(&mut self, root: Option<PathBuf>, ctx: &egui::Context) {
+        let Some(root) = root.or_else(|| self.current_folder()) else {
+            self.status_message = "Open a folder first".to_string();
+            return;
+        };
+        self.stats_collection = Some(StatsCollection::start(
+            &root,
+            self.config.stats_recursive,
+            self.tag_cache.snapshot(),
+            ctx,
+        ));
+    }
+
+    /// ファイルツリーを検索条件で絞り込む
+    fn filter_tree_by(&mut self, query: String) {
+        self.tree_filter = query;
+        self.config.show_left_sidebar = true;
+    }
+
+    fn show_tag_stats(&mut self, ctx: &egui::Context) {
+        if let Some(stats) = self.stats_collection.as_ref().and_then(StatsCollection::poll) {
+            self.tag_stats = Some(stats);
+            self.stats_collection = None;
+        }
+        let Some(stats) = &self.tag_stats else {
+            if let Some(collection) = &self.stats_collection {
+                let mut open = true;
+                egui::Window::new("Tag Statistics").open(&mut open).show(ctx, |ui| {
+                    ui.label(format!("Folder: {}", collection.root.display()));
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!("Reading tags... {} images", collection.done()));
+                    });
+                });
+                if !open {
+                    self.stats_collection = None;
+                }
+            }
+            return;
+        };
+
+        let mut open = true;
+        let mut recollect = None;
+        // ファイルツリーの絞り込みに使う検索文字列
+        let mut filter: Option<String> = None;
+        let mut export_csv = false;
+        let mut export_json = false;
+        let tree_root = self.file_tree.root.as_ref().map(|r| r.path.clone());
+        let current_folder = self.current_folder();
+
+        egui::Window::new("Tag Statistics")
+            .open(&mut open)
+            .default_size([640.0, 560.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Folder: {}", stats.root.display()));
+                });
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(current_folder.is_some(), egui::Button::new("Current Folder"))
+                        .clicked()
+                    {
+                        recollect = Some(current_folder.clone());
+                    }
+                    if ui.add_enabled(tree_root.is_some(), egui::Button::new("Tree Root")).clicked() {
+                        recollect = Some(tree_root.clone());
+                    }
+                    if ui
+                        .checkbox(&mut self.config.stats_recursive, "Include subfolders")
+                        .changed()
+                    {
+                        self.config.save();
+                        recollect = Some(Some(stats.root.clone()));
+                    }
+                    if ui.button("Refresh").clicked() {
+                        recollect = Some(Some(stats.root.clone()));
+                    }
+                    ui.separator();
+                    if ui.button("Export CSV...").clicked() {
+                        export_csv = true;
+                    }
+                    if ui.button("Export JSON...").clicked() {
+                        export_json = true;
+                    }
+                });
+                ui.separator();
+
+                ui.label(format!(
+                    "{} images, {} tags, {} untagged ({:.1}%)",
+                    stats.images,
+                    stats.frequency.len(),
+                    stats.untagged,
+                    stats.percent(stats.untagged)
+                ));
+                ui.label(RichText::new("Click a tag or a cell to filter the file tree").weak());
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    ui.collapsing("Tag frequency", |ui| {
+                        let max = stats.max_frequency().max(1);
+                        egui::Grid::new("tag_frequency").striped(true).show(ui, |ui| {
+                            for (tag, count) in &stats.frequency {
+                                if ui.link(tag).clicked() {
+                                    filter = Some(TagQuery::term(tag));
+                                }
+                                ui.add(
+                                    egui::ProgressBar::new(*count as f32 / max as f32)
+                                        .desired_width(240.0)
+                                        .text(format!("{} ({:.1}%)", count, stats.percent(*count))),
+                                );
+                                ui.end_row();
+                            }
+                        });
+                    });
+
+                    ui.collapsing("Images per tag count", |ui| {
+                        let max = stats.tags_per_image.iter().copied().max().unwrap_or(0).max(1);
+                        egui::Grid::new("tags_per_image").striped(true).show(ui, |ui| {
+                            for (n, count) in stats.tags_per_image.iter().enumerate() {
+                                let label = if n == 1 { "1 tag".to_string() } else { format!("{} tags", n) };
+                                if n == 0 {
+                                    if ui.link(label).clicked() {
+                                        filter = Some("untagged".to_string());
+                                    }
+                                } else {
+                                    ui.label(label);
+                                }
+                                ui.add(
+                                    egui::ProgressBar::new(*count as f32 / max as f32)
+                                        .desired_width(240.0)
+                                        .text(format!("{} ({:.1}%)", count, stats.percent(*count))),
+                                );
+                                ui.end_row();
+                            }
+                        });
+                    });
+
+                    ui.collapsing("Co-occurrence", |ui| {
+                        let shown = stats.frequency.len().min(MAX_MATRIX_TAGS);
+                        if stats.frequency.len() > shown {
+                            ui.label(format!("Top {} tags (export for all tag pairs)", shown));
+                        }
+                        egui::ScrollArea::horizontal().show(ui, |ui| {
+                            egui::Grid::new("co_occurrence").show(ui, |ui| {
+                                ui.label("");
+                                for (tag, _) in &stats.frequency[..shown] {
+                                    ui.label(RichText::new(tag).small()).on_hover_text(tag);
+                                }
+                                ui.end_row();
+                                for a in 0..shown {
+                                    let tag_a = &stats.frequency[a].0;
+                                    if ui.link(tag_a).clicked() {
+                                        filter = Some(TagQuery::term(tag_a));
+                                    }
+                                    let total = stats.frequency[a].1.max(1);
+                                    for b in 0..shown {
+                                        let count = stats.together(a, b);
+                                        let tag_b = &stats.frequency[b].0;
+                                        // 行のタグを持つ画像のうち列のタグも持つ割合で色を付ける
+                                        let strength = count as f32 / total as f32;
+                                        let fill = Color32::from_rgba_unmultiplied(
+                                            70,
+                                            130,
+                                            220,
+                                            (strength * 200.0) as u8,
+                                        );
+                                        let cell = egui::Button::new(count.to_string())
+                                            .fill(fill)
+                                            .min_size(egui::vec2(36.0, 0.0));
+                                        if ui
+                                            .add(cell)
+                                            .on_hover_text(format!("{} + {}: {}", tag_a, tag_b, count))
+                                            .clicked()
+                                        {
+                                            filter = Some(if a == b {
+                                                TagQuery::term(tag_a)
+                                            } else {
+                                                format!("{} {}", TagQuery::term(tag_a), TagQuery::term(tag_b))
+                                            });
+                                        }
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                        });
+                    });
+                });
+            });
+
+        if export_csv || export_json {
+            let (name, ext) = if export_csv { ("tag_stats.csv", "csv") } else { ("tag_stats.json", "json") };
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter(ext.to_uppercase(), &[ext])
+                .set_file_name(name)
+                .save_file()
+            {
+                let result = if export_csv {
+                    stats.export_csv(&path)
+                } else {
+                    stats.export_json(&path)
+                };
+                self.status_message = match result {
+                    Ok(()) => format!("Exported statistics: {}", path.display()),
+                    Err(e) => format!("Error exporting statistics: {}", e),
+                };
+            }
+        }
+        if let Some(query) = filter {
+            self.filter_tree_by(query);
+        }
+        if let Some(root) = recollect {
+            self.open_tag_stats(root, ctx);
+        }
+        if !open {
+            self.tag_stats = None;
+            self.stats_collection = None;
+        }
+    }
+
+    /// ツリーのルート以下の重複画像の検索を始める
+    fn open_duplicate_finder(&mut self, ctx: &egui::Context) {
+        let Some(root) = self.file_tree.root.as_ref().map(|r| r.path.clone()) else {
+            self.status_message = "Open a folder first".to_string();
+            return;
+        };
+        if let Some(finder) = &self.duplicate_finder {
+            finder.scan.cancel();
+        }
+        self.duplicate_finder = Some(DuplicateFinder::new(&root, ctx));
+    }
+
+    fn show_duplicate_finder(&mut self, ctx: &egui::Context) {
+        let Some(finder) = &mut self.duplicate_finder else {
+            return;
+        };
+        finder.update(self.config.duplicate_hash, self.config.duplicate_threshold);
+
+        let mut open = true;
+        let mut rescan = false;
+        // 余分な画像を削除するグループ
+        let mut trash_groups: Vec<usize> = Vec::new();
+
+        egui::Window::new("Find Duplicates")
+            .open(&mut open)
+            .default_size([720.0, 520.0])
+            .show(ctx, |ui| {
+                let mut changed = false;
+                ui.horizontal(|ui| {
+                    ui.label("Hash:");
+                    for kind in HashKind::ALL {
+                        changed |= ui
+                            .radio_value(&mut self.config.duplicate_hash, kind, kind.label())
+                            .changed();
+                    }
+                    ui.separator();
+                    ui.label("Max distance:");
+                    changed |= ui
+                        .add(egui::Slider::new(&mut self.config.duplicate_threshold, 0..=20))
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    changed |= ui
+                        .checkbox(&mut self.config.duplicate_merge_tags, "Merge group tags onto the kept image")
+                        .changed();
+                    if ui.button("Rescan").clicked() {
+                        rescan = true;
+                    }
+                });
+                if changed {
+                    self.config.save();
+                }
+                ui.separator();
+
+                if finder.scan.is_running() {
+                    ui.horizontal(|ui| {
+                        let progress = finder.scan.done() as f32 / finder.scan.total.max(1) as f32;
+                        ui.add(
+                            egui::ProgressBar::new(progress)
+                                .text(format!("{} / {}", finder.scan.done(), finder.scan.total))
+                                .desired_width(300.0),
+                        );
+                        if ui.button("Cancel").clicked() {
+                            finder.scan.cancel();
+                        }
+                    });
+                    return;
+                }
+
+                let extras: usize = finder.groups.iter().map(|g| g.len() - 1).sum();
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} groups, {} extra files ({} images scanned)",
+                        finder.groups.len(),
+                        extras,
+                        finder.scan.images.len()
+                    ));
+                    if ui
+                        .add_enabled(extras > 0, egui::Button::new("Trash All Extras"))
+                        .clicked()
+                    {
+                        trash_groups = (0..finder.groups.len()).collect();
+                    }
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for g in 0..finder.groups.len() {
+                        let group = finder.groups[g].clone();
+                        ui.group(|ui| {
+                            ui.horizontal(|ui| {
+                                for &i in &group {
+                                    let texture = finder.texture(ctx, i);
+                                    let is_exact = i != finder.keepers[g] && finder.scan.is_exact(i, finder.keepers[g]);
+                                    let image = &finder.scan.images[i];
+                                    ui.vertical(|ui| {
+                                        ui.add(
+                                            egui::Image::new(&texture)
+                                                .max_size(Vec2::splat(140.0))
+                                                .maintain_aspect_ratio(true),
+                                        )
+                                        .on_hover_text(image.path.display().to_string());
+                                        ui.radio_value(&mut finder.keepers[g], i, "Keep");
+                                        ui.label(
+                                            image
+                                                .path
+                                                .file_name()
+                                                .map(|n| n.to_string_lossy().to_string())
+                                                .unwrap_or_default(),
+                                        );
+                                        ui.label(format!(
+                                            "{}×{}  {}",
+                                            image.width,
+                                            image.height,
+                                            metadata_info::format_file_size(image.file_size)
+                                        ));
+                                        if is_exact {
+                                            ui.label(RichText::new("identical").small().weak());
+                                        }
+                                    });
+                                }
+                            });
+                            if ui.button("Trash Extras").clicked() {
+                                trash_groups.push(g);
+                            }
+                        });
+                    }
+                });
+            });
+
+        if rescan {
+            self.open_duplicate_finder(ctx);
+        } else if !open {
+            if let Some(finder) = self.duplicate_finder.take() {
+                finder.scan.cancel();
+            }
+        } else if !trash_groups.is_empty() {
+            self.trash_duplicates(&trash_groups);
+        }
+    }
+
+    /// 重複グループの残す画像以外をゴミ箱へ移動する
+    fn trash_duplicates(&mut self, groups: &[usize]) {
+        self.wait_for_writes();
+        let Some(finder) = &self.duplicate_finder else {
+            return;
+        };
+        // (残す画像, 削除する画像)
+        let targets: Vec<(PathBuf, Vec<PathBuf>)> = groups
+            .iter()
+            .map(|&g| {
+                let keeper = finder.keepers[g];
+                let path = |i: usize| finder.scan.images[i].path.clone();
+                let extras = finder.groups[g].iter().filter(|&&i| i != keeper).map(|&i| path(i)).collect();
+                (path(keeper), extras)
+            })
+            .collect();
+
+        let mut trashed = 0;
+        let mut dirs = HashSet::new();
+        for (keeper, extras) in targets {
+            if self.config.duplicate_merge_tags {
+                self.merge_tags_into(&keeper, &extras);
+            }
+            for extra in extras {
+                let tags = self.tag_cache.tags(&extra).to_vec();
+                if let Err(e) = trash::delete(&extra) {
+                    self.status_message = format!("Error deleting file: {}", e);
+                    continue;
+                }
+                trashed += 1;
+                self.run_hooks(HookEvent::FileTrashed, &extra, &tags, None, None);
+                dirs.extend(extra.parent().map(Path::to_path_buf));
+                if let Some(finder) = &mut self.duplicate_finder {
+                    finder.remove(&extra);
+                }
+                if self.image_viewer.current_image.as_ref() == Some(&extra) {
+                    self.advance_after_removal(&extra);
+                }
+            }
+        }
+        self.refresh_dirs(&dirs);
+        self.status_message = format!("Moved {} duplicates to trash", trashed);
+    }
+
+    /// 他の画像のタグを keeper に追加して保存する
+    fn merge_tags_into(&mut self, keeper: &Path, others: &[PathBuf]) {
+        let is_current = self.image_viewer.current_image.as_deref() == Some(keeper);
+        let mut tags = if is_current {
+            self.current_tags.clone()
+        } else {
+            tag_manager::load_tags(keeper)
+        };
+        let before = tags.len();
+        for other in others {
+            for tag in tag_manager::load_tags(other) {
+                tag_manager::add_tag(&mut tags, &tag);
+            }
+        }
+        if tags.len() == before {
+            return;
+        }
+
+        if is_current {
+            self.current_tags = tags;
+            self.save_tags();
+        } else {
+            match self.write_tags(keeper, &tags) {
+                Err(e) => self.status_message = format!("Error saving tags: {}", e),
+                Ok(Some(problem)) => self.status_message = Self::verification_message(keeper, &problem),
+                Ok(None) => {}
+            }
+        }
+    }
+
+    /// 一括リネームを実行する
+    fn run_batch_rename(&mut self) {
+        let Some(rename) = self.batch_rename.take() else {
+            return;
+        };
+        self.settle_before_file_op();
+        self.config.rename_template = rename.template.clone();
+        self.config.save();
+
+        let (ops, error) = batch_rename::execute(&rename.renames());
+
+        let mut current = self.image_viewer.current_image.clone();
+        let mut dirs = HashSet::new();
+        for op in &ops {
+            if let FileOp::Moved { from, to } = op {
+                self.pending.rename(from, to);
+                dirs.extend(to.parent().map(Path::to_path_buf));
+                if current.as_ref() == Some(from) {
+                    current = Some(to.clone());
+                }
+            }
+        }
+        self.refresh_dirs(&dirs);
+        // 一覧を読み直すため開き直す
+        if let Some(path) = current {
+            self.open_image(path);
+        }
+
+        let count = ops.len() / 2;
+        if !ops.is_empty() {
+            self.undo_stack.push(ops);
+        }
+        self.status_message = match error {
+            Some(e) => format!("Error renaming files: {} (Ctrl+Z to undo)", e),
+            None => format!("Renamed {} files (Ctrl+Z to undo)", count),
+        };
+    }
+
+    /// ファイル操作をしたフォルダをツリーとタグの集計に反映する
+    fn refresh_dirs(&mut self, dirs: &HashSet<PathBuf>) {
+        self.file_tree.refresh(dirs, &mut self.tag_cache);
+        for dir in dirs {
+            self.tag_cache.invalidate_dir(dir);
+        }
+    }
+
+    /// 比較モードの開始/終了
+    fn toggle_compare(&mut self) {
+        if self.compare.is_active {
+            self.compare.stop();
+            return;
+        }
+
+        // 現在の画像から連続する画像を並べる
+        let paths: Vec<PathBuf> = self
+            .image_viewer
+            .images_in_dir
+            .iter()
+            .skip(self.image_viewer.current_index)
+            .take(self.config.compare_pane_count)
+            .cloned()
+            .collect();
+        if paths.len() < 2 {
+            self.status_message = "Not enough images to compare".to_string();
+            return;
+        }
+        self.compare.start(paths);
+        self.focus_compare_pane(0);
+    }
+
+    /// 指定ペインにフォーカスし、その画像をタグ編集の対象にする
+    fn focus_compare_pane(&mut self, index: usize) {
+        let Some(path) = self.compare.panes.get(index).cloned() else {
+            return;
+        };
+        self.compare.focused = index;
+        self.show_compare_image(path);
+    }
+
+    fn show_compare_image(&mut self, path: PathBuf) {
+        if self.image_viewer.current_image.as_ref() == Some(&path) {
+            return;
+        }
+        self.leave_image();
+        if let Some(old) = self.image_viewer.current_image.clone() {
+            self.compare.invalidate_tags(&old);
+        }
+        self.open_image(path);
+    }
+
+    /// フォーカス中のペインを次（前）の候補に差し替える
+    fn compare_step(&mut self, forward: bool) {
+        let candidates = self.image_viewer.images_in_dir.clone();
+        if let Some(path) = self.compare.replace_focused(&candidates, forward) {
+            self.show_compare_image(path);
+        }
+    }
+
+    fn show_left_sidebar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            let clear_width = if self.tree_filter.is_empty() { 0.0 } else { 24.0 };
+            ui.add(
+                egui::TextEdit::singleline(&mut self.tree_filter)
+                    .hint_text("tag query, e.g. cat -dog")
+                    .desired_width(ui.available_width() - clear_width),
+            );
+            if !self.tree_filter.is_empty() && ui.small_button("✕").clicked() {
+                self.tree_filter.clear();
+            }
+        });
+        ui.separator();
+
+        let query = TagQuery::parse(&self.tree_filter);
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if let Some(root) = self.file_tree.root.clone() {
+                self.show_file_node(ui, &root, &query);
+            } else {
+                ui.label("Drop a folder or image here");
+            }
+        });
+    }
+
+    fn show_file_node(&mut self, ui: &mut egui::Ui, node: &FileNode, query: &TagQuery) {
+        if node.is_dir {
+            let is_expanded = self.file_tree.is_expanded(&node.path);
+            let icon = if is_expanded { "📂" } else { "📁" };
+
+            let mut title = format!("{} {}", icon, node.name);
+            if self.config.tree_folder_counts {
+                let tag = &self.config.tree_count_tag;
+                let counts = self.tag_cache.folder_counts(&node.path, tag);
+                if counts.images > 0 {
+                    if tag.is_empty() {
+                        title += &format!("  ({}, {} untagged)", counts.images, counts.untagged);
+                    } else {
+                        title += &format!("  ({}, {} {})", counts.images, counts.with_tag, tag);
+                    }
+                }
+            }
+
+            // 件数で見出しが変わっても開閉状態が変わらないようにパスで識別する
+            let header = egui::CollapsingHeader::new(title)
+                .id_salt(&node.path)
+                .open(Some(is_expanded));
+
+            let response = header.show(ui, |ui| {
+                for child in &node.children {
+                    self.show_file_node(ui, child, query);
+                }
+            });
+
+            if response.header_response.clicked() {
+                self.file_tree.toggle_expanded(&node.path, &mut self.tag_cache);
             }
         } else {
             let is_current = self
@@ -329,14 +2377,67 @@ impl InnerApp {
                 .map(|p| p == &node.path)
                 .unwrap_or(false);
 
+            // 表示中の画像は未保存の編集を反映する
+            let tags = if is_current {
+                self.current_tags.clone()
+            } else {
+                self.tag_cache.tags(&node.path).to_vec()
+            };
+            // 絞り込みに合わない画像は隠す（表示中の画像は残す）
+            if !is_current && !query.is_empty() && !query.matches(&tags) {
+                return;
+            }
+
             let text = if is_current {
                 RichText::new(format!("🖼 {}", node.name)).strong()
             } else {
                 RichText::new(format!("  {}", node.name))
             };
 
-            if ui.selectable_label(is_current, text).clicked() {
-                self.open_image(node.path.clone());
+            let in_compare = self.compare.panes.contains(&node.path);
+            ui.horizontal(|ui| {
+                if ui.selectable_label(is_current || in_compare, text).clicked() {
+                    if ui.input(|i| i.modifiers.ctrl) {
+                        // Ctrl+クリックで比較対象に追加/削除
+                        self.compare.toggle_pane(&node.path);
+                        if let Some(pos) = self.compare.panes.iter().position(|p| p == &node.path) {
+                            self.focus_compare_pane(pos);
+                        }
+                    } else {
+                        self.request(LeaveAction::Open(node.path.clone()));
+                    }
+                }
+                Self::show_tree_tags(ui, &tags, self.config.tree_file_tags);
+            });
+        }
+    }
+
+    /// ファイルツリーのファイル名の横にタグを表示する
+    fn show_tree_tags(ui: &mut egui::Ui, tags: &[String], display: TreeFileTags) {
+        const MAX_CHIPS: usize = 3;
+        match display {
+            TreeFileTags::Hidden => {}
+            TreeFileTags::Marker => {
+                let (mark, color) = if tags.is_empty() {
+                    ("○", Color32::GRAY)
+                } else {
+                    ("●", Color32::from_rgb(100, 200, 100))
+                };
+                ui.label(RichText::new(mark).small().color(color));
+            }
+            TreeFileTags::Chips => {
+                ui.spacing_mut().item_spacing.x = 3.0;
+                for tag in tags.iter().take(MAX_CHIPS) {
+                    ui.label(
+                        RichText::new(tag)
+                            .small()
+                            .color(Color32::WHITE)
+                            .background_color(Color32::from_rgb(60, 80, 110)),
+                    );
+                }
+                if tags.len() > MAX_CHIPS {
+                    ui.label(RichText::new(format!("+{}", tags.len() - MAX_CHIPS)).small().weak());
+                }
             }
         }
     }
@@ -364,6 +2465,11 @@ impl InnerApp {
                         self.save_tags();
                     }
                 }
+
+                if self.config.suggest_tags {
+                    ui.separator();
+                    self.show_tag_suggestions(ui);
+                }
             });
 
         ui.separator();
@@ -372,16 +2478,15 @@ impl InnerApp {
         ui.horizontal(|ui| {
             ui.label("Add:");
             let response = ui.text_edit_singleline(&mut self.new_tag_input);
-            if (response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)))
-                || ui.small_button("+").clicked()
+            if ((response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)))
+                || ui.small_button("+").clicked())
+                && !self.new_tag_input.is_empty()
             {
-                if !self.new_tag_input.is_empty() {
-                    tag_manager::add_tag(&mut self.current_tags, &self.new_tag_input);
-                    self.new_tag_input.clear();
-                    self.tags_modified = true;
-                    if self.config.auto_save {
-                        self.save_tags();
-                    }
+                tag_manager::add_tag(&mut self.current_tags, &self.new_tag_input);
+                self.new_tag_input.clear();
+                self.tags_modified = true;
+                if self.config.auto_save {
+                    self.save_tags();
                 }
             }
         });
@@ -423,12 +2528,170 @@ impl InnerApp {
                      });
                  }
             }
+            for (label, targets) in [("Move", &self.config.hotkey_move), ("Copy", &self.config.hotkey_copy)] {
+                let modifier = if label == "Move" { "Shift" } else { "Shift+Alt" };
+                let mut keys: Vec<_> = targets.keys().collect();
+                keys.sort();
+                for key in keys {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("[{}+{}]:", modifier, key));
+                        ui.label(format!("{} → {}", label, targets[key].display()));
+                    });
+                }
+            }
             ui.separator();
             ui.label("ℹ Edit settings.json to configure hotkeys");
         });
     }
 
+    /// 似ている画像からのタグの提案（クリックで追加）
+    fn show_tag_suggestions(&mut self, ui: &mut egui::Ui) {
+        let Some(path) = self.image_viewer.current_image.clone() else {
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label(RichText::new("💡 Suggestions").strong());
+            if self.suggester.is_indexing() {
+                let (done, total) = self.suggester.progress();
+                ui.spinner();
+                ui.label(RichText::new(format!("{}/{}", done, total)).weak())
+                    .on_hover_text("Reading images in the folder tree");
+            } else if ui.small_button("⟳").on_hover_text("Rescan the folder tree").clicked() {
+                self.suggester.refresh();
+            }
+        });
+
+        let mut accepted = None;
+        let suggestions = self.suggester.suggestions(&path, &mut self.tag_cache);
+        let mut shown = 0;
+        let new_suggestions = suggestions
+            .iter()
+            .filter(|s| !self.current_tags.contains(&s.tag))
+            .take(tag_suggestions::MAX_SUGGESTIONS);
+        for suggestion in new_suggestions {
+            shown += 1;
+            ui.horizontal(|ui| {
+                if ui
+                    .small_button(format!("+ {}", suggestion.tag))
+                    .on_hover_text(format!(
+                        "Found on {} of {} similar images",
+                        suggestion.found, suggestion.neighbours
+                    ))
+                    .clicked()
+                {
+                    accepted = Some(suggestion.tag.clone());
+                }
+                ui.add(
+                    egui::ProgressBar::new(suggestion.confidence)
+                        .desired_width(60.0)
+                        .text(format!("{:.0}%", suggestion.confidence * 100.0)),
+                );
+            });
+        }
+        if shown == 0 && !self.suggester.is_indexing() {
+            ui.label(RichText::new("No similar tagged images").weak());
+        }
+
+        if let Some(tag) = accepted {
+            tag_manager::add_tag(&mut self.current_tags, &tag);
+            self.tags_modified = true;
+            if self.config.auto_save {
+                self.save_tags();
+            }
+        }
+    }
+
+    fn show_metadata_panel(&mut self, ui: &mut egui::Ui) {
+        let Some(path) = self.image_viewer.current_image.clone() else {
+            ui.label("No image");
+            return;
+        };
+
+        // 表示中の画像が変わったら読み直す
+        let stale = match &self.metadata_info {
+            Some((p, _)) => p != &path,
+            None => true,
+        };
+        if stale {
+            self.metadata_info = Some((path.clone(), MetadataInfo::read(&path)));
+        }
+        let Some((_, info)) = &self.metadata_info else {
+            return;
+        };
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.strong(path.file_name().and_then(|n| n.to_str()).unwrap_or(""));
+            ui.separator();
+
+            // 主要項目
+            egui::Grid::new("metadata_summary")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for (label, value) in &info.summary {
+                        ui.label(*label);
+                        ui.label(value);
+                        if ui.small_button("📋").on_hover_text("Copy").clicked() {
+                            ui.ctx().copy_text(value.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+            if info.summary.is_empty() {
+                ui.label("(No metadata)");
+            } else if ui.button("📋 Copy all").clicked() {
+                let text = info
+                    .summary
+                    .iter()
+                    .map(|(label, value)| format!("{}: {}", label, value))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                ui.ctx().copy_text(text);
+            }
+
+            ui.separator();
+
+            // すべてのExifタグ
+            ui.collapsing(format!("Raw tags ({})", info.raw_tags.len()), |ui| {
+                egui::Grid::new("metadata_raw_tags")
+                    .num_columns(4)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for tag in &info.raw_tags {
+                            ui.monospace(format!("0x{:04X}", tag.id));
+                            ui.label(&tag.name);
+                            ui.add(egui::Label::new(&tag.value).truncate());
+                            if ui.small_button("📋").on_hover_text("Copy").clicked() {
+                                ui.ctx().copy_text(tag.value.clone());
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+
+            // タグのエンコード確認用にUserCommentの生バイト列を表示
+            ui.collapsing("UserComment (raw bytes)", |ui| match &info.user_comment {
+                Some(bytes) => {
+                    ui.label(format!("{} bytes", bytes.len()));
+                    let dump = metadata_info::hex_dump(bytes);
+                    if ui.small_button("📋 Copy hex").clicked() {
+                        ui.ctx().copy_text(dump.clone());
+                    }
+                    ui.label(RichText::new(dump).monospace());
+                }
+                None => {
+                    ui.label("(No UserComment)");
+                }
+            });
+        });
+    }
+
     fn show_center_panel(&mut self, ui: &mut egui::Ui) {
+        if self.compare.is_active {
+            self.show_compare_panel(ui);
+            return;
+        }
+
         if let Some(path) = &self.image_viewer.current_image {
             // テクスチャが未ロード、または別画像になっていれば同期で読み込む
             let need_load = match &self.current_texture_path {
@@ -436,44 +2699,213 @@ impl InnerApp {
                 None => true,
             };
 
-            if need_load {
-                match image_crate::open(path) {
-                    Ok(img) => {
-                        let img = img.to_rgba8();
-                        let (w, h) = img.dimensions();
-                        let pixels = img.into_raw();
-                        let color_image = egui::ColorImage::from_rgba_unmultiplied(
-                            [w as usize, h as usize],
-                            &pixels,
-                        );
-                        let ctx = ui.ctx();
-                        // Texture 名にパスを使う（ユニーク）
-                        let tex = ctx.load_texture(path.display().to_string(), color_image, egui::TextureOptions::default());
-                        self.current_texture = Some(tex);
-                        self.current_texture_path = Some(path.clone());
-                    }
-                    Err(_) => {
-                        self.current_texture = None;
-                        self.current_texture_path = None;
-                    }
-                }
+            if need_load {
+                self.load_error = None;
+                // アニメーション画像なら全フレームを読み込む
+                self.current_animation = AnimationPlayer::load(ui.ctx(), path);
+                if self.current_animation.is_some() {
+                    self.current_texture = None;
+                    self.current_texture_path = Some(path.clone());
+                } else {
+                    match image_loader::open(path) {
+                        Ok(img) => {
+                            let img = img.to_rgba8();
+                            let (w, h) = img.dimensions();
+                            let pixels = img.into_raw();
+                            let color_image = egui::ColorImage::from_rgba_unmultiplied(
+                                [w as usize, h as usize],
+                                &pixels,
+                            );
+                            let ctx = ui.ctx();
+                            // Texture 名にパスを使う（ユニーク）
+                            let tex = ctx.load_texture(path.display().to_string(), color_image, egui::TextureOptions::default());
+                            self.current_texture = Some(tex);
+                            self.current_texture_path = Some(path.clone());
+                        }
+                        Err(e) => {
+                            // 毎フレーム読み込み直さないよう、失敗した画像として覚えておく
+                            self.current_texture = None;
+                            self.current_texture_path = Some(path.clone());
+                            self.load_error = Some(e.to_string());
+                        }
+                    }
+                }
+            }
+
+            if let Some(anim) = &mut self.current_animation {
+                if let Some(wait) = anim.update() {
+                    ui.ctx().request_repaint_after(wait);
+                }
+            }
+
+            let tex = match &self.current_animation {
+                Some(anim) => Some(anim.texture().clone()),
+                None => self.current_texture.clone(),
+            };
+
+            if let Some(tex) = tex {
+                let available = ui.available_size();
+                let image = egui::Image::new(&tex).fit_to_exact_size(available);
+                let response = ui.add(image);
+                self.show_hotkey_overlay(ui, response.rect);
+                self.show_animation_controls(ui, response.rect);
+            } else {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(ui.available_height() / 2.0 - 20.0);
+                    ui.heading("🖼 Failed to load image");
+                    if let Some(error) = &self.load_error {
+                        ui.label(RichText::new(error).weak());
+                    }
+                });
+            }
+        } else {
+            ui.centered_and_justified(|ui| {
+                ui.heading("🖼 Drop an image or folder here");
+            });
+        }
+    }
+
+    fn show_compare_panel(&mut self, ui: &mut egui::Ui) {
+        let (cols, rows) = self.compare.grid_size();
+        let full = ui.available_rect_before_wrap();
+        let response = ui.allocate_rect(full, egui::Sense::click_and_drag());
+        let spacing = 4.0;
+        let pane_size = Vec2::new(
+            (full.width() - spacing * (cols - 1) as f32) / cols as f32,
+            (full.height() - spacing * (rows - 1) as f32) / rows as f32,
+        );
+
+        // ズーム（ホイール）と表示位置（ドラッグ）は全ペインで共有する
+        if response.hovered() {
+            let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+            if scroll != 0.0 {
+                self.compare.zoom = (self.compare.zoom * (scroll * 0.005).exp()).clamp(1.0, 20.0);
+            }
+        }
+        if response.dragged() {
+            self.compare.pan += response.drag_delta() / (pane_size * self.compare.zoom);
+        }
+        if response.double_clicked() {
+            self.compare.reset_view();
+        }
+        if self.compare.zoom <= 1.0 {
+            self.compare.pan = Vec2::ZERO;
+        }
+
+        let pointer = response.interact_pointer_pos();
+        let mut clicked_pane = None;
+
+        for (i, path) in self.compare.panes.clone().iter().enumerate() {
+            let col = i % cols;
+            let row = i / cols;
+            let rect = egui::Rect::from_min_size(
+                full.min + Vec2::new(col as f32 * (pane_size.x + spacing), row as f32 * (pane_size.y + spacing)),
+                pane_size,
+            );
+            let painter = ui.painter_at(rect);
+            painter.rect_filled(rect, 0.0, Color32::from_gray(20));
+
+            let mut info = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("")
+                .to_string();
+
+            if let Some(tex) = self.compare.texture(ui.ctx(), path) {
+                let img_size = tex.size_vec2();
+                let fit = (rect.width() / img_size.x).min(rect.height() / img_size.y);
+                let size = img_size * fit * self.compare.zoom;
+                let img_rect = egui::Rect::from_center_size(rect.center() + self.compare.pan * size, size);
+                painter.image(
+                    tex.id(),
+                    img_rect,
+                    egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                    Color32::WHITE,
+                );
+                info = format!("{}  {}x{}", info, img_size.x as u32, img_size.y as u32);
+            } else {
+                painter.text(
+                    rect.center(),
+                    egui::Align2::CENTER_CENTER,
+                    "Failed to load image",
+                    egui::FontId::proportional(16.0),
+                    Color32::GRAY,
+                );
             }
 
-            if let Some(tex) = &self.current_texture {
-                let available = ui.available_size();
-                let image = egui::Image::new(tex).fit_to_exact_size(available);
-                let response = ui.add(image);
-                self.show_hotkey_overlay(ui, response.rect);
+            // タグ（フォーカス中のペインは編集中のタグを表示）
+            let tags = if i == self.compare.focused {
+                self.current_tags.clone()
             } else {
-                ui.centered_and_justified(|ui| {
-                    ui.heading("🖼 Failed to load image");
-                });
+                self.compare.tags(path).to_vec()
+            };
+            if !tags.is_empty() {
+                info = format!("{}\n{}", info, tags.join(", "));
+            }
+
+            let galley = painter.layout_no_wrap(info, egui::FontId::proportional(14.0), Color32::WHITE);
+            let text_pos = rect.left_bottom() + Vec2::new(6.0, -6.0 - galley.size().y);
+            painter.rect_filled(
+                egui::Rect::from_min_size(text_pos - Vec2::splat(3.0), galley.size() + Vec2::splat(6.0)),
+                3.0,
+                Color32::from_rgba_unmultiplied(0, 0, 0, 180),
+            );
+            painter.galley(text_pos, galley, Color32::WHITE);
+
+            let (stroke_width, stroke_color) = if i == self.compare.focused {
+                (3.0, Color32::YELLOW)
+            } else {
+                (1.0, Color32::from_gray(80))
+            };
+            painter.rect_stroke(rect.shrink(stroke_width / 2.0), 0.0, egui::Stroke::new(stroke_width, stroke_color));
+
+            if response.clicked() && pointer.map(|p| rect.contains(p)).unwrap_or(false) {
+                clicked_pane = Some(i);
             }
-        } else {
-            ui.centered_and_justified(|ui| {
-                ui.heading("🖼 Drop an image or folder here");
-            });
         }
+
+        if let Some(i) = clicked_pane {
+            self.focus_compare_pane(i);
+        }
+    }
+
+    fn show_animation_controls(&mut self, ui: &mut egui::Ui, rect: egui::Rect) {
+        let Some(anim) = &mut self.current_animation else {
+            return;
+        };
+
+        let area_rect = egui::Rect::from_center_size(
+            egui::pos2(rect.center().x, rect.max.y - 24.0),
+            Vec2::new(220.0, 32.0),
+        );
+        ui.allocate_new_ui(egui::UiBuilder::new().max_rect(area_rect), |ui| {
+            egui::Frame::none()
+                .fill(Color32::from_rgba_unmultiplied(0, 0, 0, 180))
+                .rounding(4.0)
+                .inner_margin(4.0)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.small_button("⏮").on_hover_text("Previous frame (,)").clicked() {
+                            anim.step_back();
+                        }
+                        let play_label = if anim.paused { "▶" } else { "⏸" };
+                        if ui.small_button(play_label).on_hover_text("Play/Pause (Space)").clicked() {
+                            anim.toggle_pause();
+                        }
+                        if ui.small_button("⏭").on_hover_text("Next frame (.)").clicked() {
+                            anim.step_forward();
+                        }
+                        ui.label(
+                            RichText::new(format!(
+                                "{}/{}",
+                                anim.current_frame() + 1,
+                                anim.frame_count()
+                            ))
+                            .color(Color32::WHITE),
+                        );
+                    });
+                });
+        });
     }
 
     fn show_hotkey_overlay(&self, ui: &mut egui::Ui, rect: egui::Rect) {
@@ -552,82 +2984,606 @@ impl InnerApp {
     fn show_menu_bar(&mut self, ui: &mut egui::Ui) {
         egui::menu::bar(ui, |ui| {
             ui.menu_button("File", |ui| {
-                if ui.button("Open Image...").clicked() {
+                let mut formats = format!("Supported: {}", tag_manager::image_extensions().join(", "));
+                if !cfg!(feature = "heif") {
+                    formats.push_str("\nHEIC/HEIF/AVIF need a build with --features heif (requires libheif)");
+                }
+                if ui.button("Open Image...").on_hover_text(formats).clicked() {
                     if let Some(path) = rfd::FileDialog::new()
-                        .add_filter("Images", &["png", "jpg", "jpeg", "gif", "webp", "bmp"])
+                        .add_filter("Images", tag_manager::image_extensions())
                         .pick_file()
                     {
-                        self.open_image(path);
+                        self.request(LeaveAction::OpenPath(path));
                     }
                     ui.close_menu();
                 }
                 if ui.button("Open Folder...").clicked() {
                     if let Some(path) = rfd::FileDialog::new().pick_folder() {
-                        self.file_tree.set_root(&path);
-                        self.slideshow_dir = Some(path);
+                        self.request(LeaveAction::OpenPath(path));
+                    }
+                    ui.close_menu();
+                }
+                let mut selected = None;
+                ui.menu_button("Recent Folders", |ui| {
+                    if let Some(path) = Self::path_menu(ui, &self.config.recent_folders, "Alt") {
+                        selected = Some(path);
+                    }
+                });
+                ui.menu_button("Recent Images", |ui| {
+                    if let Some(path) = Self::path_menu(ui, &self.config.recent_images, "") {
+                        selected = Some(path);
+                    }
+                });
+                ui.separator();
+                ui.menu_button("Bookmarks", |ui| {
+                    let is_bookmarked = self
+                        .current_folder()
+                        .is_some_and(|f| self.config.bookmarks.contains(&f));
+                    let label = if is_bookmarked {
+                        "Remove Bookmark"
+                    } else {
+                        "Bookmark Current Folder"
+                    };
+                    if ui
+                        .add_enabled(
+                            self.current_folder().is_some(),
+                            egui::Button::new(label).shortcut_text("Ctrl+D"),
+                        )
+                        .clicked()
+                    {
+                        self.toggle_bookmark();
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    if let Some(path) = Self::path_menu(ui, &self.config.bookmarks, "Ctrl") {
+                        selected = Some(path);
+                    }
+                });
+                if let Some(path) = selected {
+                    self.request(LeaveAction::OpenPath(path));
+                }
+                ui.separator();
+                if ui
+                    .checkbox(&mut self.config.restore_session, "Restore last session on start")
+                    .changed()
+                {
+                    self.config.save();
+                }
+                ui.separator();
+                if ui.button("Save Tags (Ctrl+S)").clicked() {
+                    self.save_tags();
+                    ui.close_menu();
+                }
+                let pending_count = self.pending.len() + usize::from(self.tags_modified);
+                ui.add_enabled_ui(pending_count > 0, |ui| {
+                    ui.menu_button(format!("Pending Changes ({})", pending_count), |ui| {
+                        let mut open = None;
+                        for path in self.pending.paths() {
+                            let name = path
+                                .file_name()
+                                .map(|n| n.to_string_lossy().to_string())
+                                .unwrap_or_default();
+                            if ui.button(name).on_hover_text(path.display().to_string()).clicked() {
+                                open = Some(path.clone());
+                            }
+                        }
+                        if let Some(path) = open {
+                            self.request(LeaveAction::Open(path));
+                            ui.close_menu();
+                        }
+                        ui.separator();
+                        if ui.button("Save All").clicked() {
+                            self.flush_pending();
+                            ui.close_menu();
+                        }
+                        if ui.button("Discard All").clicked() {
+                            self.discard_pending();
+                            ui.close_menu();
+                        }
+                    });
+                });
+                let has_backup = self
+                    .image_viewer
+                    .current_image
+                    .as_deref()
+                    .is_some_and(safe_write::has_backup);
+                if ui
+                    .add_enabled(has_backup, egui::Button::new("Restore Original"))
+                    .on_hover_text("Replace the image with the copy backed up before its first tag write")
+                    .clicked()
+                {
+                    self.restore_original();
+                    ui.close_menu();
+                }
+                ui.menu_button("Tag Library", |ui| {
+                    let mut library_mode = self.config.library_mode;
+                    if ui
+                        .checkbox(&mut library_mode, "Library Mode (don't modify images)")
+                        .on_hover_text(format!(
+                            "Store tags in {} keyed by image content instead of in the files",
+                            tag_database::database_path().display()
+                        ))
+                        .changed()
+                    {
+                        self.set_library_mode(library_mode);
+                    }
+                    ui.separator();
+                    let can_sync = self.config.library_mode && !self.image_viewer.images_in_dir.is_empty();
+                    if ui
+                        .add_enabled(can_sync, egui::Button::new("Pull Embedded Tags from Folder"))
+                        .on_hover_text("Add the tags embedded in this folder's images to the library")
+                        .clicked()
+                    {
+                        self.sync_library(false);
+                        ui.close_menu();
+                    }
+                    if ui
+                        .add_enabled(can_sync, egui::Button::new("Push Library Tags into Folder"))
+                        .on_hover_text("Embed the library tags into this folder's images")
+                        .clicked()
+                    {
+                        self.sync_library(true);
+                        ui.close_menu();
+                    }
+                });
+                if ui.button("Batch Rename...").clicked() {
+                    self.open_batch_rename();
+                    ui.close_menu();
+                }
+                if ui.button("Find Duplicates...").clicked() {
+                    self.open_duplicate_finder(ui.ctx());
+                    ui.close_menu();
+                }
+                if ui
+                    .add_enabled(!self.undo_stack.is_empty(), egui::Button::new("Undo File Operation (Ctrl+Z)"))
+                    .clicked()
+                {
+                    self.undo_file_op();
+                    ui.close_menu();
+                }
+            });
+
+            ui.menu_button("View", |ui| {
+                let compare_label = if self.compare.is_active {
+                    "Exit Compare Mode (Ctrl+G)"
+                } else {
+                    "Compare Mode (Ctrl+G)"
+                };
+                if ui.button(compare_label).clicked() {
+                    self.toggle_compare();
+                    ui.close_menu();
+                }
+                ui.separator();
+                ui.menu_button("Sort by", |ui| {
+                    self.show_sort_menu(ui);
+                });
+                if ui.button("Tag Statistics...").clicked() {
+                    self.open_tag_stats(None, ui.ctx());
+                    ui.close_menu();
+                }
+                ui.menu_button("Review Queue", |ui| {
+                    if ui
+                        .add(egui::Button::new("Untagged Images").shortcut_text("Ctrl+R"))
+                        .clicked()
+                    {
+                        self.start_review(ReviewQueue::untagged());
+                        ui.close_menu();
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Missing all of:");
+                        if ui
+                            .add(
+                                egui::TextEdit::singleline(&mut self.config.review_tags)
+                                    .hint_text("tag1, tag2")
+                                    .desired_width(140.0),
+                            )
+                            .changed()
+                        {
+                            self.config.save();
+                        }
+                    });
+                    let tags: Vec<String> = self
+                        .config
+                        .review_tags
+                        .split(',')
+                        .map(|t| t.trim().to_string())
+                        .filter(|t| !t.is_empty())
+                        .collect();
+                    if ui
+                        .add_enabled(!tags.is_empty(), egui::Button::new("Start Missing-Tags Review"))
+                        .clicked()
+                    {
+                        self.start_review(ReviewQueue::missing_all(&tags));
+                        ui.close_menu();
+                    }
+                    if self.review.is_some() {
+                        ui.separator();
+                        if ui.button("Stop Review").clicked() {
+                            self.review = None;
+                            ui.close_menu();
+                        }
+                    }
+                });
+                ui.menu_button("File Tree", |ui| {
+                    let mut changed = ui
+                        .checkbox(&mut self.config.tree_folder_counts, "Folder image counts")
+                        .changed();
+                    ui.horizontal(|ui| {
+                        ui.label("Count tag:");
+                        changed |= ui
+                            .add(
+                                egui::TextEdit::singleline(&mut self.config.tree_count_tag)
+                                    .hint_text("(untagged)")
+                                    .desired_width(100.0),
+                            )
+                            .changed();
+                    });
+                    ui.separator();
+                    for display in TreeFileTags::ALL {
+                        changed |= ui
+                            .radio_value(&mut self.config.tree_file_tags, display, display.label())
+                            .changed();
+                    }
+                    if changed {
+                        self.config.save();
+                    }
+                });
+                ui.separator();
+                if ui
+                    .checkbox(&mut self.config.show_left_sidebar, "Files Window (Ctrl+F)")
+                    .changed()
+                {
+                    self.config.save();
+                }
+                if ui
+                    .checkbox(&mut self.config.show_right_sidebar, "Tags Window (Ctrl+T)")
+                    .changed()
+                {
+                    self.config.save();
+                }
+                if ui
+                    .checkbox(&mut self.config.show_metadata_window, "Metadata Window (Ctrl+I)")
+                    .changed()
+                {
+                    self.config.save();
+                }
+            });
+
+            ui.menu_button("Slideshow", |ui| {
+                if ui.button("Start Slideshow...").clicked() {
+                    self.slideshow_dialog_open = true;
+                    ui.close_menu();
+                }
+                if self.slideshow.is_running && ui.button("Stop Slideshow").clicked() {
+                    self.slideshow.stop();
+                    ui.close_menu();
+                }
+                ui.separator();
+                if ui.button("Open Playlist...").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Playlist", &["json", "m3u", "m3u8"])
+                        .pick_file()
+                    {
+                        self.open_playlist(path);
+                    }
+                    ui.close_menu();
+                }
+                ui.add_enabled_ui(!self.config.recent_playlists.is_empty(), |ui| {
+                    ui.menu_button("Recent Playlists", |ui| {
+                        for path in self.config.recent_playlists.clone() {
+                            let name = path
+                                .file_name()
+                                .map(|n| n.to_string_lossy().to_string())
+                                .unwrap_or_default();
+                            if ui
+                                .button(name)
+                                .on_hover_text(path.display().to_string())
+                                .clicked()
+                            {
+                                self.open_playlist(path);
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                });
+                ui.separator();
+                let has_images = !self.slideshow.images.is_empty();
+                if ui
+                    .add_enabled(has_images, egui::Button::new("Export Slideshow to Folder..."))
+                    .clicked()
+                {
+                    self.export_slideshow(false);
+                    ui.close_menu();
+                }
+                if ui
+                    .add_enabled(has_images, egui::Button::new("Export Slideshow to Zip..."))
+                    .clicked()
+                {
+                    self.export_slideshow(true);
+                    ui.close_menu();
+                }
+            });
+
+            ui.menu_button("Settings", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Compare panes:");
+                    if ui
+                        .add(egui::DragValue::new(&mut self.config.compare_pane_count).range(2..=compare::MAX_PANES))
+                        .changed()
+                    {
+                        self.config.save();
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Slideshow interval (sec):");
+                    if ui
+                        .add(egui::DragValue::new(&mut self.config.slideshow_interval).range(0.5..=60.0))
+                        .changed()
+                    {
+                        self.config.save();
+                    }
+                });
+                if ui
+                    .checkbox(&mut self.config.slideshow_loop, "Loop slideshow")
+                    .changed()
+                {
+                    self.config.save();
+                }
+                if ui
+                    .checkbox(
+                        &mut self.config.slideshow_wait_animation,
+                        "Wait for animations to finish",
+                    )
+                    .changed()
+                {
+                    self.config.save();
+                }
+                ui.separator();
+                ui.menu_button("When Leaving Unsaved Tags", |ui| {
+                    for policy in UnsavedPolicy::ALL {
+                        if ui
+                            .radio_value(&mut self.config.unsaved_policy, policy, policy.label())
+                            .changed()
+                        {
+                            self.config.save();
+                        }
+                    }
+                });
+                ui.menu_button("When Move/Copy Target Exists", |ui| {
+                    for strategy in CollisionStrategy::ALL {
+                        if ui
+                            .radio_value(&mut self.config.collision_strategy, strategy, strategy.label())
+                            .changed()
+                        {
+                            self.config.save();
+                        }
                     }
+                });
+                ui.separator();
+                if ui
+                    .checkbox(&mut self.config.watch_files, "Watch folders for changes")
+                    .on_hover_text("Refresh the file list when files are added, renamed or removed")
+                    .changed()
+                {
+                    self.config.save();
+                }
+                if ui
+                    .checkbox(&mut self.config.auto_save, "Auto-save on hotkey")
+                    .changed()
+                {
+                    self.config.save();
+                }
+                if ui
+                    .checkbox(&mut self.config.single_instance, "Open files in the running window")
+                    .on_hover_text("Launching the editor again forwards the file to this window (takes effect on next start)")
+                    .changed()
+                {
+                    self.config.save();
+                }
+                if ui.button("Hooks...").clicked() {
+                    self.hooks_dialog_open = true;
                     ui.close_menu();
                 }
-                ui.separator();
-                if ui.button("Save Tags (Ctrl+S)").clicked() {
-                    self.save_tags();
-                    ui.close_menu();
+                if ui
+                    .checkbox(&mut self.config.suggest_tags, "Suggest tags from similar images")
+                    .on_hover_text(
+                        "Compares colours and perceptual hashes with tagged images in the folder tree (offline). \
+                         Reads every image in the tree in the background.",
+                    )
+                    .changed()
+                {
+                    if !self.config.suggest_tags {
+                        self.suggester.stop();
+                    }
+                    self.config.save();
+                }
+                ui.menu_button("HTTP API", |ui| {
+                    let mut enabled = self.api.is_some();
+                    if ui
+                        .checkbox(&mut enabled, "Enable local HTTP API")
+                        .on_hover_text("Lets scripts on this computer read and edit tags (127.0.0.1 only)")
+                        .changed()
+                    {
+                        if enabled {
+                            self.start_api(ui.ctx());
+                        } else {
+                            self.api = None;
+                            self.config.api_enabled = false;
+                            self.config.save();
+                        }
+                    }
+                    ui.add_enabled_ui(self.api.is_none(), |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Port:");
+                            if ui
+                                .add(egui::DragValue::new(&mut self.config.api_port).range(1024..=65535))
+                                .changed()
+                            {
+                                self.config.save();
+                            }
+                        });
+                    });
+                    ui.separator();
+                    ui.label("Token:");
+                    ui.label(RichText::new(&self.config.api_token).monospace());
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(!self.config.api_token.is_empty(), egui::Button::new("Copy"))
+                            .clicked()
+                        {
+                            ui.ctx().copy_text(self.config.api_token.clone());
+                            ui.close_menu();
+                        }
+                        if ui.button("New Token").clicked() {
+                            match http_api::generate_token() {
+                                Ok(token) => {
+                                    self.config.api_token = token;
+                                    self.config.save();
+                                    // 古いトークンを使えないよう起動し直す
+                                    if self.api.is_some() {
+                                        self.start_api(ui.ctx());
+                                    }
+                                }
+                                Err(e) => self.status_message = format!("Error generating token: {}", e),
+                            }
+                        }
+                    });
+                });
+                ui.separator();
+                if ui
+                    .checkbox(&mut self.config.background_writes, "Write tags in the background")
+                    .on_hover_text("Keeps the UI responsive on slow or network drives; failed writes are retried")
+                    .changed()
+                {
+                    self.config.save();
+                }
+                if ui
+                    .checkbox(&mut self.config.preserve_timestamps, "Keep file dates when writing tags")
+                    .on_hover_text("Restore the modified and accessed times after embedding tags")
+                    .changed()
+                {
+                    self.config.save();
                 }
-            });
-
-            ui.menu_button("View", |ui| {
                 if ui
-                    .checkbox(&mut self.config.show_left_sidebar, "Files Window (Ctrl+F)")
+                    .checkbox(&mut self.config.verify_writes, "Verify images after writing tags")
+                    .on_hover_text("Check that the image still decodes and its image data is unchanged")
                     .changed()
                 {
                     self.config.save();
                 }
                 if ui
-                    .checkbox(&mut self.config.show_right_sidebar, "Tags Window (Ctrl+T)")
+                    .checkbox(&mut self.config.backup_originals, "Back up originals before writing tags")
+                    .on_hover_text(format!("Keeps a copy in {} next to the image", safe_write::BACKUP_DIR))
                     .changed()
                 {
                     self.config.save();
                 }
+                ui.add_enabled_ui(self.config.backup_originals, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Backups per folder (0 = unlimited):");
+                        if ui
+                            .add(egui::DragValue::new(&mut self.config.backup_limit).range(0..=100_000))
+                            .changed()
+                        {
+                            self.config.save();
+                        }
+                    });
+                });
             });
+        });
+    }
 
-            ui.menu_button("Slideshow", |ui| {
-                if ui.button("Start Slideshow...").clicked() {
-                    self.slideshow_dialog_open = true;
-                    ui.close_menu();
+    fn show_hooks_dialog(&mut self, ctx: &egui::Context) {
+        let mut open = self.hooks_dialog_open;
+
+        egui::Window::new("Hooks")
+            .open(&mut open)
+            .default_width(640.0)
+            .show(ctx, |ui| {
+                ui.label("Run a shell command when something happens. These values are replaced (escaped, so quoting them is optional):");
+                ui.label(RichText::new(hooks::PLACEHOLDERS).monospace());
+                if cfg!(windows) {
+                    ui.label("Commands run without cmd.exe; use \"cmd /C ...\" explicitly for shell features.");
                 }
-                if self.slideshow.is_running {
-                    if ui.button("Stop Slideshow").clicked() {
-                        self.slideshow.stop();
-                        ui.close_menu();
+                ui.separator();
+
+                let mut changed = false;
+                let mut remove = None;
+                egui::Grid::new("hooks").striped(true).show(ui, |ui| {
+                    ui.label("");
+                    ui.label("Event");
+                    ui.label("Tag");
+                    ui.label("Command");
+                    ui.end_row();
+                    for (i, hook) in self.config.hooks.iter_mut().enumerate() {
+                        changed |= ui.checkbox(&mut hook.enabled, "").changed();
+                        egui::ComboBox::from_id_salt(("hook_event", i))
+                            .selected_text(hook.event.label())
+                            .show_ui(ui, |ui| {
+                                for event in HookEvent::ALL {
+                                    changed |= ui.selectable_value(&mut hook.event, event, event.label()).changed();
+                                }
+                            });
+                        ui.add_enabled_ui(hook.event.has_tag(), |ui| {
+                            changed |= ui
+                                .add(egui::TextEdit::singleline(&mut hook.tag).hint_text("any").desired_width(100.0))
+                                .changed();
+                        });
+                        ui.horizontal(|ui| {
+                            changed |= ui
+                                .add(
+                                    egui::TextEdit::singleline(&mut hook.command)
+                                        .hint_text("cp {path} ~/export/")
+                                        .desired_width(320.0)
+                                        .font(egui::TextStyle::Monospace),
+                                )
+                                .changed();
+                            if ui.small_button("✕").on_hover_text("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                        ui.end_row();
                     }
+                });
+                if let Some(i) = remove {
+                    self.config.hooks.remove(i);
+                    changed = true;
                 }
-            });
 
-            ui.menu_button("Settings", |ui| {
                 ui.horizontal(|ui| {
-                    ui.label("Slideshow interval (sec):");
-                    if ui
-                        .add(egui::DragValue::new(&mut self.config.slideshow_interval).range(0.5..=60.0))
-                        .changed()
-                    {
-                        self.config.save();
+                    if ui.button("Add Hook").clicked() {
+                        self.config.hooks.push(Default::default());
+                        changed = true;
                     }
+                    ui.separator();
+                    ui.label("Timeout (seconds):");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut self.config.hook_timeout).range(1..=3600))
+                        .changed();
                 });
-                if ui
-                    .checkbox(&mut self.config.slideshow_loop, "Loop slideshow")
-                    .changed()
-                {
+                if changed {
                     self.config.save();
                 }
+
                 ui.separator();
-                if ui
-                    .checkbox(&mut self.config.auto_save, "Auto-save on hotkey")
-                    .changed()
-                {
-                    self.config.save();
-                }
+                ui.label(format!("Recent output ({} running)", self.hooks.running()));
+                egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                    for output in &self.hook_log {
+                        let (color, text) = match &output.result {
+                            Ok(text) => (Color32::LIGHT_GREEN, text),
+                            Err(e) => (Color32::RED, e),
+                        };
+                        ui.label(RichText::new(&output.label).color(color))
+                            .on_hover_text(&output.command);
+                        if !text.is_empty() {
+                            ui.label(RichText::new(text).monospace().small());
+                        }
+                    }
+                });
             });
-        });
+
+        self.hooks_dialog_open = open;
     }
 
     fn show_slideshow_dialog(&mut self, ctx: &egui::Context) {
@@ -639,8 +3595,66 @@ impl InnerApp {
             .collapsible(false)
             .show(ctx, |ui| {
                 ui.horizontal(|ui| {
-                    ui.label("Filter by tag:");
-                    ui.text_edit_singleline(&mut self.slideshow_tag);
+                    if self.slideshow_query {
+                        ui.label("Filter by query:");
+                        ui.text_edit_singleline(&mut self.slideshow_tag).on_hover_text(
+                            "Space-separated tags must all match.\n\
+                             -tag: exclude, a|b: either, \"two words\": tag with spaces,\n\
+                             untagged: images without tags",
+                        );
+                    } else {
+                        ui.label("Filter by tag:");
+                        ui.text_edit_singleline(&mut self.slideshow_tag)
+                            .on_hover_text("Images with exactly this tag (empty: all images)");
+                    }
+                });
+                ui.checkbox(&mut self.slideshow_query, "Use tag query syntax");
+                ui.checkbox(&mut self.slideshow_recursive, "Include subfolders");
+
+                if ui
+                    .checkbox(&mut self.config.slideshow_shuffle, "Shuffle")
+                    .changed()
+                {
+                    self.config.save();
+                }
+
+                ui.collapsing("Presentation", |ui| {
+                    let mut changed = ui
+                        .checkbox(&mut self.config.slideshow_fullscreen, "Fullscreen presentation")
+                        .changed();
+                    ui.horizontal(|ui| {
+                        ui.label("Transition:");
+                        egui::ComboBox::from_id_salt("slideshow_transition")
+                            .selected_text(self.config.slideshow_transition.label())
+                            .show_ui(ui, |ui| {
+                                for transition in Transition::ALL {
+                                    changed |= ui
+                                        .selectable_value(
+                                            &mut self.config.slideshow_transition,
+                                            transition,
+                                            transition.label(),
+                                        )
+                                        .changed();
+                                }
+                            });
+                        changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut self.config.slideshow_transition_secs)
+                                    .range(0.0..=5.0)
+                                    .speed(0.05)
+                                    .suffix(" s"),
+                            )
+                            .changed();
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Caption:");
+                        changed |= ui.checkbox(&mut self.config.slideshow_caption.filename, "File name").changed();
+                        changed |= ui.checkbox(&mut self.config.slideshow_caption.date, "Date").changed();
+                        changed |= ui.checkbox(&mut self.config.slideshow_caption.tags, "Tags").changed();
+                    });
+                    if changed {
+                        self.config.save();
+                    }
                 });
 
                 ui.label(format!(
@@ -655,24 +3669,8 @@ impl InnerApp {
 
                 ui.horizontal(|ui| {
                     if ui.button("Start").clicked() {
-                        if let Some(dir) = &self.slideshow_dir {
-                            let images = if self.slideshow_tag.is_empty() {
-                                // すべての画像
-                                self.image_viewer.images_in_dir.clone()
-                            } else {
-                                // タグでフィルタ
-                                find_images_with_tag(dir, &self.slideshow_tag)
-                            };
-
-                            if !images.is_empty() {
-                                self.slideshow.start(images);
-                                if let Some(path) = self.slideshow.current_image().cloned() {
-                                    self.open_image(path);
-                                }
-                                self.status_message = "Slideshow started".to_string();
-                            } else {
-                                self.status_message = "No images found for slideshow".to_string();
-                            }
+                        if let Some(playlist) = self.dialog_playlist() {
+                            self.start_playlist(&playlist);
                         }
                         self.slideshow_dialog_open = false;
                     }
@@ -680,15 +3678,139 @@ impl InnerApp {
                         self.slideshow_dialog_open = false;
                     }
                 });
+
+                ui.horizontal(|ui| {
+                    let playlist = self.dialog_playlist();
+                    ui.add_enabled_ui(playlist.is_some(), |ui| {
+                        if ui
+                            .button("Save Static Playlist...")
+                            .on_hover_text("Save the current matches as a fixed list of files")
+                            .clicked()
+                        {
+                            if let Some(playlist) = &playlist {
+                                let paths = playlist.resolve();
+                                self.save_playlist(&Playlist::Static { paths });
+                            }
+                        }
+                        if ui
+                            .button("Save Dynamic Playlist...")
+                            .on_hover_text("Save the query and folder; matches are collected when opened")
+                            .clicked()
+                        {
+                            if let Some(playlist) = &playlist {
+                                self.save_playlist(playlist);
+                            }
+                        }
+                    });
+                });
             });
 
         self.slideshow_dialog_open = open;
     }
 
+    /// スライドショーダイアログの設定から動的プレイリストを作る
+    fn dialog_playlist(&self) -> Option<Playlist> {
+        let dir = self.slideshow_dir.as_ref()?;
+        Some(Playlist::Dynamic {
+            query: if self.slideshow_query || self.slideshow_tag.trim().is_empty() {
+                self.slideshow_tag.clone()
+            } else {
+                // 1つのタグ名として扱う（スペースや先頭の - を含んでいても検索条件にしない）
+                TagQuery::term(self.slideshow_tag.trim())
+            },
+            folders: vec![dir.clone()],
+            recursive: self.slideshow_recursive,
+            order: self.config.sort.order_for(dir),
+        })
+    }
+
+    /// プレイリストの画像でスライドショーを開始（未保存の編集があれば先に確認）
+    fn start_playlist(&mut self, playlist: &Playlist) {
+        self.request(LeaveAction::StartSlideshow(playlist.clone()));
+    }
+
+    fn run_playlist(&mut self, playlist: &Playlist) {
+        let images = playlist.resolve();
+        if images.is_empty() {
+            self.status_message = "No images found for slideshow".to_string();
+            return;
+        }
+        self.slideshow.start(images, self.config.slideshow_shuffle);
+        if let Some(path) = self.slideshow.current_image().cloned() {
+            self.open_image(path);
+        }
+        self.status_message = "Slideshow started".to_string();
+    }
+
+    /// プレイリストを読み込んでスライドショーを開始
+    fn open_playlist(&mut self, path: PathBuf) {
+        match Playlist::load(&path) {
+            Ok(playlist) => {
+                self.config.add_recent_playlist(path);
+                self.config.save();
+                self.start_playlist(&playlist);
+            }
+            Err(e) => {
+                self.status_message = format!("Error loading playlist: {}", e);
+            }
+        }
+    }
+
+    /// プレイリストを保存する（保存先はダイアログで選ぶ）
+    fn save_playlist(&mut self, playlist: &Playlist) {
+        let mut dialog = rfd::FileDialog::new().add_filter("Playlist", &["json"]);
+        if matches!(playlist, Playlist::Static { .. }) {
+            dialog = dialog.add_filter("M3U", &["m3u", "m3u8"]);
+        }
+        let Some(path) = dialog.set_file_name("playlist.json").save_file() else {
+            return;
+        };
+        match playlist.save(&path) {
+            Ok(()) => {
+                self.status_message = format!("Playlist saved: {}", path.display());
+                self.config.add_recent_playlist(path);
+                self.config.save();
+            }
+            Err(e) => {
+                self.status_message = format!("Error saving playlist: {}", e);
+            }
+        }
+    }
+
+    /// スライドショーの画像をフォルダかzipに書き出す
+    fn export_slideshow(&mut self, to_zip: bool) {
+        let images = self.slideshow.images.clone();
+        let result = if to_zip {
+            rfd::FileDialog::new()
+                .add_filter("Zip", &["zip"])
+                .set_file_name("slideshow.zip")
+                .save_file()
+                .map(|dest| playlist::export_to_zip(&images, &dest))
+        } else {
+            rfd::FileDialog::new()
+                .pick_folder()
+                .map(|dest| playlist::export_to_folder(&images, &dest))
+        };
+        match result {
+            Some(Ok(count)) => self.status_message = format!("Exported {} images", count),
+            Some(Err(e)) => self.status_message = format!("Error exporting slideshow: {}", e),
+            None => {}
+        }
+    }
+
     fn update_slideshow(&mut self) {
+        // アニメーションの1周目が終わるまで切り替えを待つ
+        let hold = self.config.slideshow_wait_animation
+            && self
+                .current_animation
+                .as_ref()
+                .map(|anim| !anim.completed_once)
+                .unwrap_or(false);
+
         if let Some(path) = self.slideshow.update(
             self.config.slideshow_interval,
             self.config.slideshow_loop,
+            hold,
         ) {
             self.open_image(path);
         }
@@ -699,6 +3821,96 @@ impl InnerApp {
         }
     }
 
+    /// 全画面スライドショーの描画
+    fn show_presentation(&mut self, ctx: &egui::Context) {
+        let texture = match &self.current_animation {
+            Some(anim) => Some(anim.texture().clone()),
+            None => self.current_texture.clone(),
+        };
+        if let (Some(path), Some(texture)) = (&self.current_texture_path, texture) {
+            self.presentation
+                .set_image(path, &texture, self.config.slideshow_caption.date);
+        }
+
+        let display_secs = self.slideshow.current_duration(self.config.slideshow_interval);
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none().fill(Color32::BLACK))
+            .show(ctx, |ui| {
+                let screen = ui.max_rect();
+                let painter = ui.painter();
+                self.presentation.paint(
+                    painter,
+                    screen,
+                    self.config.slideshow_transition,
+                    self.config.slideshow_transition_secs,
+                    display_secs,
+                );
+                self.presentation.paint_caption(
+                    painter,
+                    screen,
+                    self.config.slideshow_caption,
+                    &self.current_tags,
+                );
+            });
+
+        self.presentation.update_cursor(ctx);
+    }
+
+    fn slideshow_next(&mut self) {
+        if let Some(path) = self.slideshow.next(self.config.slideshow_loop) {
+            self.open_image(path);
+        }
+    }
+
+    fn slideshow_prev(&mut self) {
+        if let Some(path) = self.slideshow.prev(self.config.slideshow_loop) {
+            self.open_image(path);
+        }
+    }
+
+    /// スライドショー操作バー（前後移動・一時停止・シーク）
+    fn show_slideshow_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("⏮").on_hover_text("Previous (←)").clicked() {
+                self.slideshow_prev();
+            }
+            let pause_label = if self.slideshow.is_paused { "▶" } else { "⏸" };
+            if ui.button(pause_label).on_hover_text("Pause/Resume (Space)").clicked() {
+                self.slideshow.toggle_pause();
+            }
+            if ui.button("⏭").on_hover_text("Next (→)").clicked() {
+                self.slideshow_next();
+            }
+            if ui.button("⏹").on_hover_text("Stop").clicked() {
+                self.slideshow.stop();
+            }
+
+            ui.separator();
+
+            // 表示中の画像の残り時間
+            let duration = self.slideshow.current_duration(self.config.slideshow_interval);
+            ui.add(
+                egui::ProgressBar::new((self.slideshow.elapsed() / duration).min(1.0))
+                    .desired_width(80.0)
+                    .text(format!("{:.1}s", duration)),
+            );
+
+            // シーク（再生順の位置にジャンプ）
+            let total = self.slideshow.total_images();
+            let mut position = self.slideshow.current_index + 1;
+            ui.spacing_mut().slider_width = (ui.available_width() - 80.0).max(100.0);
+            let response = ui.add(
+                egui::Slider::new(&mut position, 1..=total.max(1))
+                    .suffix(format!(" / {}", total)),
+            );
+            if response.changed() {
+                if let Some(path) = self.slideshow.goto(position - 1) {
+                    self.open_image(path);
+                }
+            }
+        });
+    }
+
     fn show_status_bar(&self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             // 現在の画像情報
@@ -717,13 +3929,70 @@ impl InnerApp {
 
             // スライドショー状態
             if self.slideshow.is_running {
-                ui.label(RichText::new("▶ Slideshow").color(Color32::GREEN));
+                if self.slideshow.is_paused {
+                    ui.label(RichText::new("⏸ Slideshow").color(Color32::YELLOW));
+                } else {
+                    ui.label(RichText::new("▶ Slideshow").color(Color32::GREEN));
+                }
+            }
+
+            // レビューキューの進み具合
+            if let Some(review) = &self.review {
+                let text = if review.remaining == 0 {
+                    format!("☑ Review ({}): done", review.label)
+                } else {
+                    format!("☑ Review ({}): {} left", review.label, review.remaining)
+                };
+                ui.label(RichText::new(text).color(Color32::LIGHT_BLUE));
+                ui.add(
+                    egui::ProgressBar::new(review.progress())
+                        .desired_width(120.0)
+                        .text(format!("{}/{}", review.total - review.remaining, review.total)),
+                );
+                ui.separator();
+            }
+
+            if let Some(api) = &self.api {
+                ui.label(format!("🔌 API :{}", api.port()))
+                    .on_hover_text(format!("http://127.0.0.1:{}/api/", api.port()));
+            }
+
+            if self.config.library_mode {
+                ui.label("📚 Library").on_hover_text("Tags are stored in the tag database");
             }
 
             // 変更状態
             if self.tags_modified {
                 ui.label(RichText::new("● Modified").color(Color32::YELLOW));
             }
+            if self.write_queue.pending_count() > 0 {
+                ui.label(
+                    RichText::new(format!("⟳ {} saving", self.write_queue.pending_count()))
+                        .color(Color32::LIGHT_BLUE),
+                );
+            }
+            if !self.write_queue.failed.is_empty() {
+                let errors: Vec<String> = self
+                    .write_queue
+                    .failed
+                    .iter()
+                    .map(|(path, e)| format!("{}: {}", path.display(), e))
+                    .collect();
+                ui.label(
+                    RichText::new(format!("✖ {} failed", self.write_queue.failed.len())).color(Color32::RED),
+                )
+                .on_hover_text(errors.join("\n"));
+            }
+            if self.hooks.running() > 0 {
+                ui.label(RichText::new(format!("⚙ {} hooks running", self.hooks.running())).color(Color32::LIGHT_BLUE));
+            }
+            if !self.pending.is_empty() {
+                let names: Vec<String> = self.pending.paths().map(|p| p.display().to_string()).collect();
+                ui.label(
+                    RichText::new(format!("✎ {} pending", self.pending.len())).color(Color32::YELLOW),
+                )
+                .on_hover_text(names.join("\n"));
+            }
 
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                 ui.label(&self.status_message);
@@ -738,9 +4007,58 @@ impl eframe::App for TagEditorApp {
         {
             let mut inner = self.inner.borrow_mut();
 
+            // 未保存の編集があれば終了前に確認
+            if ctx.input(|i| i.viewport().close_requested()) {
+                inner.handle_close_request(ctx);
+            }
+
+            // バックグラウンドでの書き込みの結果
+            let results = inner.write_queue.poll();
+            inner.handle_write_results(results);
+
+            // ライブラリモードでまとめて保存するデータベース
+            if let Err(e) = tag_database::save_pending(false) {
+                inner.status_message = format!("Error saving tag database: {}", e);
+            }
+            if tag_database::has_unsaved() {
+                ctx.request_repaint_after(tag_database::SAVE_INTERVAL);
+            }
+
+            // 外部ツールからの操作
+            inner.handle_api_requests();
+            inner.handle_instance_requests(ctx);
+
+            // フックの実行結果
+            inner.handle_hook_outputs();
+
+            // タグの提案に使う画像の読み込み
+            if inner.config.suggest_tags {
+                let root = inner
+                    .file_tree
+                    .root
+                    .as_ref()
+                    .map(|r| r.path.clone())
+                    .or_else(|| inner.current_folder());
+                inner.suggester.set_root(root.as_deref());
+                inner.suggester.poll();
+            }
+
             // ドロップファイル処理
             inner.handle_dropped_files(ctx);
 
+            // フォルダの変更を反映
+            inner.update_fs_watcher();
+
+            // 表示状態を記録
+            if let Some(wait) = inner.save_session(false) {
+                ctx.request_repaint_after(wait);
+            }
+
+            // レビューキューの残り数
+            if inner.review.is_some() {
+                inner.update_review();
+            }
+
             // キーボード処理 (メインウィンドウ)
             inner.handle_keyboard(ctx);
 
@@ -762,6 +4080,13 @@ impl eframe::App for TagEditorApp {
                 inner.show_status_bar(ui);
             });
 
+            // スライドショー操作バー
+            if inner.slideshow.is_running {
+                egui::TopBottomPanel::bottom("slideshow_bar").show(ctx, |ui| {
+                    inner.show_slideshow_bar(ui);
+                });
+            }
+
             // 中央パネル（画像表示）
             egui::CentralPanel::default().show(ctx, |ui| {
                 inner.show_center_panel(ui);
@@ -771,32 +4096,60 @@ impl eframe::App for TagEditorApp {
             if inner.slideshow_dialog_open {
                 inner.show_slideshow_dialog(ctx);
             }
+
+            // 一括リネームダイアログ
+            inner.show_batch_rename_dialog(ctx);
+
+            // 重複画像ウィンドウ
+            inner.show_duplicate_finder(ctx);
+
+            // タグの統計ウィンドウ
+            inner.show_tag_stats(ctx);
+
+            // フック設定ウィンドウ
+            if inner.hooks_dialog_open {
+                inner.show_hooks_dialog(ctx);
+            }
+
+            // 未保存の編集の確認
+            inner.show_leave_prompt(ctx);
+
+            // 表示中の画像が変わったら API の購読者に知らせる
+            if inner.api.is_some() {
+                let state = inner.api_state();
+                if let Some(api) = &mut inner.api {
+                    api.publish_state(state);
+                }
+            }
         } // ここで inner の借用が解放される
 
         // 2. サブウィンドウの表示判定とメインウィンドウ情報の取得
         let (
             show_left,
             show_right,
+            show_metadata,
             was_left_open,
             was_right_open,
+            was_metadata_open,
             left_size_config,
             right_size_config,
+            metadata_size_config,
             main_rect,
-            screen_rect,
         ) = {
             let inner = self.inner.borrow();
             (
                 inner.config.show_left_sidebar,
                 inner.config.show_right_sidebar,
+                inner.config.show_metadata_window,
                 inner.was_left_sidebar_open,
                 inner.was_right_sidebar_open,
+                inner.was_metadata_window_open,
                 inner.config.left_window_size,
                 inner.config.right_window_size,
+                inner.config.metadata_window_size,
                 // 現在のメインウィンドウの位置とサイズを取得
                 ctx.input(|i| i.viewport().outer_rect)
                     .unwrap_or_else(|| ctx.input(|i| i.screen_rect())),
-                // 画面全体の領域を取得（はみ出し防止用）
-                ctx.input(|i| i.screen_rect()),
             )
         };
 
@@ -904,15 +4257,116 @@ impl eframe::App for TagEditorApp {
             );
         }
         
+        // 5. メタデータウィンドウ (OS Window)
+        if show_metadata {
+            let is_opening = !was_metadata_open;
+            let mut builder = egui::ViewportBuilder::default()
+                .with_title("Metadata")
+                .with_min_inner_size([200.0, 150.0]);
+
+            // 初回表示時のみ位置・サイズを設定
+            if is_opening {
+                // サイズ決定 (設定 or デフォルト)
+                let (width, height) = if let Some(size) = metadata_size_config {
+                    (size[0], size[1])
+                } else {
+                    (350.0, 500.0)
+                };
+
+                // 位置決定 (メインウィンドウの右下あたり)
+                let x = main_rect.max.x + 10.0;
+                let y = main_rect.min.y + 60.0;
+
+                builder = builder
+                    .with_position([x, y])
+                    .with_inner_size([width, height]);
+            }
+
+            let inner_shared = self.inner.clone();
+            ctx.show_viewport_immediate(
+                egui::ViewportId::from_hash_of("metadata_window"),
+                builder,
+                move |ctx, _class| {
+                    let mut inner = inner_shared.borrow_mut();
+
+                    // キーボード処理
+                    inner.handle_keyboard(ctx);
+
+                    egui::CentralPanel::default().show(ctx, |ui| {
+                        inner.show_metadata_panel(ui);
+                    });
+
+                    // サイズのみ保存
+                    if let Some(rect) = ctx.input(|i| i.viewport().inner_rect) {
+                        inner.config.metadata_window_size = Some([rect.width(), rect.height()]);
+                    }
+
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        inner.config.show_metadata_window = false;
+                        inner.config.save();
+                    }
+                },
+            );
+        }
+
+        // 6. 全画面スライドショー (OS Window)
+        let show_presentation = {
+            let mut inner = self.inner.borrow_mut();
+            let show = inner.slideshow.is_running && inner.config.slideshow_fullscreen;
+            if !show {
+                inner.presentation.clear();
+            }
+            show
+        };
+        if show_presentation {
+            let builder = egui::ViewportBuilder::default()
+                .with_title("Slideshow")
+                .with_fullscreen(true)
+                .with_decorations(false);
+
+            let inner_shared = self.inner.clone();
+            ctx.show_viewport_immediate(
+                egui::ViewportId::from_hash_of("presentation"),
+                builder,
+                move |ctx, _class| {
+                    let mut inner = inner_shared.borrow_mut();
+
+                    // キーボード処理（←→で前後、Spaceで一時停止）
+                    inner.handle_keyboard(ctx);
+
+                    inner.show_presentation(ctx);
+
+                    // Escape またはウィンドウを閉じたらスライドショー終了
+                    if ctx.input(|i| i.key_pressed(Key::Escape) || i.viewport().close_requested()) {
+                        inner.slideshow.stop();
+                        inner.status_message = "Slideshow stopped".to_string();
+                    }
+                },
+            );
+        }
+
         // 状態更新
         {
             let mut inner = self.inner.borrow_mut();
             inner.was_left_sidebar_open = show_left;
             inner.was_right_sidebar_open = show_right;
+            inner.was_metadata_window_open = show_metadata;
         }
     }
 
     fn save(&mut self, _storage: &mut dyn eframe::Storage) {
         self.inner.borrow_mut().config.save();
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // 書き込み待ちのタグを書き終えてから終了する
+        // （閉じる前に handle_close_request で待って失敗を確認しているので、ここで残るのは
+        // その後に依頼された書き込みだけ。失敗は write_tags が記録する）
+        let mut inner = self.inner.borrow_mut();
+        inner.write_queue.flush();
+        let _ = tag_database::save_pending(true);
+        inner.save_session(true);
+        // 次の起動が新しいウィンドウを開けるようソケットを片付ける
+        inner.instance = None;
+    }
 }
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::file_ops::{self, FileOp};
+use crate::metadata_info;
+use crate::sort_order;
+use crate::tag_manager;
+use crate::tag_query::TagQuery;
+
+/// テンプレートの書き方（ダイアログに表示する）
+pub const TEMPLATE_HELP: &str = "\
+{date} date taken (2024-05-01)   {time} time taken (153000)
+{tags} all tags   {tags:2} first 2 tags   {tag:animal} value of an animal:xxx tag
+{counter} counter   {counter:4} zero-padded counter   {name} original name
+{hash} content hash   {hash:8} first 8 hex digits
+The original extension is kept.";
+
+/// テンプレートの展開に使うファイルの情報（一度だけ読み込む）
+struct FileInfo {
+    stem: String,
+    ext: Option<String>,
+    tags: Vec<String>,
+    /// Exifの撮影日時（なければ更新日時）"YYYY:MM:DD HH:MM:SS"
+    date: String,
+    /// 必要になるまで計算しない
+    hash: Option<u64>,
+}
+
+impl FileInfo {
+    fn read(path: &Path) -> Self {
+        let date = metadata_info::date_taken(path).unwrap_or_else(|| {
+            std::fs::metadata(path)
+                .and_then(|m| m.modified())
+                .map(sort_order::format_system_time)
+                .unwrap_or_default()
+        });
+        Self {
+            stem: path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            ext: path.extension().map(|e| e.to_string_lossy().to_string()),
+            tags: tag_manager::load_tags(path),
+            date,
+            hash: None,
+        }
+    }
+}
+
+/// 一括リネームの1ファイル分
+pub struct RenameItem {
+    pub from: PathBuf,
+    /// リネーム対象に含めるか
+    pub selected: bool,
+    /// 新しいパス（対象外なら None）
+    pub to: Option<PathBuf>,
+    /// 名前の衝突など、リネームできない理由
+    pub problem: Option<String>,
+    info: FileInfo,
+}
+
+/// テンプレートによる一括リネームの状態（プレビュー付き）
+pub struct BatchRename {
+    pub template: String,
+    /// 連番の開始値
+    pub start: usize,
+    pub items: Vec<RenameItem>,
+}
+
+impl BatchRename {
+    pub fn new(paths: Vec<PathBuf>, template: String) -> Self {
+        let items = paths
+            .into_iter()
+            .map(|from| RenameItem {
+                info: FileInfo::read(&from),
+                from,
+                selected: true,
+                to: None,
+                problem: None,
+            })
+            .collect();
+        let mut rename = Self {
+            template,
+            start: 1,
+            items,
+        };
+        rename.update_preview();
+        rename
+    }
+
+    /// テンプレートや選択が変わったら新しい名前と衝突を計算し直す
+    pub fn update_preview(&mut self) {
+        let needs_hash = self.template.contains("{hash");
+        let mut counter = self.start;
+        for item in &mut self.items {
+            item.problem = None;
+            item.to = None;
+            if !item.selected {
+                continue;
+            }
+            if needs_hash && item.info.hash.is_none() {
+                item.info.hash = file_ops::content_hash(&item.from).ok();
+            }
+            let stem = render(&self.template, &item.info, counter);
+            counter += 1;
+            if stem.trim().is_empty() {
+                item.problem = Some("Empty name".to_string());
+                continue;
+            }
+            let name = match &item.info.ext {
+                Some(ext) => format!("{}.{}", stem, ext),
+                None => stem,
+            };
+            item.to = item.from.parent().map(|dir| dir.join(name));
+        }
+
+        // 同じ名前になるもの、リネームしない既存ファイルと重なるものは衝突
+        let sources: Vec<PathBuf> = self
+            .items
+            .iter()
+            .filter(|i| i.to.is_some())
+            .map(|i| i.from.clone())
+            .collect();
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for item in &self.items {
+            if let Some(to) = &item.to {
+                *seen.entry(to.to_string_lossy().to_lowercase()).or_default() += 1;
+            }
+        }
+        for item in &mut self.items {
+            let Some(to) = &item.to else {
+                continue;
+            };
+            if seen[&to.to_string_lossy().to_lowercase()] > 1 {
+                item.problem = Some("Duplicate name".to_string());
+            } else if !same_name(to, &item.from) && to.exists() && !sources.contains(to) {
+                item.problem = Some("File exists".to_string());
+            }
+        }
+    }
+
+    /// タグの検索条件に合う画像だけを選択する
+    pub fn select_matching(&mut self, query: &TagQuery) {
+        for item in &mut self.items {
+            item.selected = query.matches(&item.info.tags);
+        }
+        self.update_preview();
+    }
+
+    pub fn has_problems(&self) -> bool {
+        self.items.iter().any(|i| i.problem.is_some())
+    }
+
+    /// 実際に名前が変わるもの（元のパス, 新しいパス）
+    pub fn renames(&self) -> Vec<(PathBuf, PathBuf)> {
+        self.items
+            .iter()
+            .filter_map(|i| i.to.as_ref().map(|to| (i.from.clone(), to.clone())))
+            .filter(|(from, to)| from != to)
+            .collect()
+    }
+}
+
+/// テンプレートを展開する（未知のプレースホルダはそのまま残す）
+fn render(template: &str, info: &FileInfo, counter: usize) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('}') else {
+            rest = &rest[open..];
+            break;
+        };
+        let placeholder = &rest[open + 1..open + close];
+        let (key, arg) = match placeholder.split_once(':') {
+            Some((key, arg)) => (key, Some(arg)),
+            None => (placeholder, None),
+        };
+        let number = arg.and_then(|a| a.parse::<usize>().ok());
+        let value = match key {
+            "date" => Some(info.date.get(..10).unwrap_or("").replace(':', "-")),
+            "time" => Some(info.date.get(11..).unwrap_or("").replace(':', "")),
+            "tags" => {
+                let n = number.unwrap_or(info.tags.len());
+                Some(info.tags.iter().take(n).cloned().collect::<Vec<_>>().join("_"))
+            }
+            "tag" => arg.map(|ns| {
+                let prefix = format!("{}:", ns);
+                info.tags
+                    .iter()
+                    .find_map(|t| t.strip_prefix(&prefix))
+                    .unwrap_or("")
+                    .to_string()
+            }),
+            "counter" => Some(format!("{:0width$}", counter, width = number.unwrap_or(1))),
+            "name" => Some(info.stem.clone()),
+            "hash" => info.hash.map(|h| {
+                let hex = format!("{:016x}", h);
+                hex[..number.unwrap_or(16).clamp(1, 16)].to_string()
+            }),
+            _ => None,
+        };
+        match value {
+            Some(value) => out.push_str(&value),
+            None => out.push_str(&rest[open..=open + close]),
+        }
+        rest = &rest[open + close + 1..];
+    }
+    out.push_str(rest);
+    sanitize(&out)
+}
+
+/// 大文字小文字だけの違いか（大文字小文字を区別しないファイルシステム向け）
+fn same_name(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+/// ファイル名に使えない文字を _ にする
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// リネームを実行する
+/// 入れ替え（a→b, b→a）でも衝突しないよう、一度仮の名前にしてから新しい名前にする
+/// 行った操作を返す（途中で失敗した場合はそこまでの操作とエラー）
+pub fn execute(renames: &[(PathBuf, PathBuf)]) -> (Vec<FileOp>, Option<io::Error>) {
+    let mut ops = Vec::new();
+    let mut temps = Vec::new();
+    for (i, (from, to)) in renames.iter().enumerate() {
+        let temp = from.with_file_name(format!(
+            ".{}.renaming-{}",
+            from.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(),
+            i
+        ));
+        if let Err(e) = file_ops::move_with_sidecar(from, &temp) {
+            return (ops, Some(e));
+        }
+        ops.push(FileOp::Moved {
+            from: from.clone(),
+            to: temp.clone(),
+        });
+        temps.push((temp, to));
+    }
+    for (temp, to) in temps {
+        if let Err(e) = file_ops::move_with_sidecar(&temp, to) {
+            return (ops, Some(e));
+        }
+        ops.push(FileOp::Moved {
+            from: temp,
+            to: to.clone(),
+        });
+    }
+    (ops, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(stem: &str, tags: &[&str]) -> FileInfo {
+        FileInfo {
+            stem: stem.to_string(),
+            ext: Some("jpg".to_string()),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            date: "2024:05:01 15:30:00".to_string(),
+            hash: Some(0x0123456789abcdef),
+        }
+    }
+
+    /// ファイルを読まずに作る（from のファイルは存在しなくてよい）
+    fn batch(dir: &Path, stems: &[&str], template: &str) -> BatchRename {
+        let mut rename = BatchRename {
+            template: template.to_string(),
+            start: 1,
+            items: stems
+                .iter()
+                .map(|stem| RenameItem {
+                    from: dir.join(format!("{}.jpg", stem)),
+                    selected: true,
+                    to: None,
+                    problem: None,
+                    info: info(stem, &[]),
+                })
+                .collect(),
+        };
+        rename.update_preview();
+        rename
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tag_editor_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn render_placeholders() {
+        let info = info("IMG_0001", &["cat", "animal:dog", "outdoor"]);
+        assert_eq!(render("{date}_{time}", &info, 1), "2024-05-01_153000");
+        assert_eq!(render("{tags}", &info, 1), "cat_animal_dog_outdoor");
+        assert_eq!(render("{tags:1}-{tag:animal}", &info, 1), "cat-dog");
+        assert_eq!(render("{tag:place}x", &info, 1), "x");
+        assert_eq!(render("{counter}/{counter:4}", &info, 7), "7_0007");
+        assert_eq!(render("{name}", &info, 1), "IMG_0001");
+        assert_eq!(render("{hash}", &info, 1), "0123456789abcdef");
+        assert_eq!(render("{hash:4}", &info, 1), "0123");
+        assert_eq!(render("{hash:0}{hash:99}", &info, 1), "00123456789abcdef");
+    }
+
+    #[test]
+    fn render_keeps_unknown_and_unclosed_placeholders() {
+        let info = info("a", &[]);
+        assert_eq!(render("{unknown}_{name}", &info, 1), "{unknown}_a");
+        assert_eq!(render("{tag}", &info, 1), "{tag}");
+        assert_eq!(render("{name", &info, 1), "{name");
+        assert_eq!(render("x{}", &info, 1), "x{}");
+        assert_eq!(render("{tags}", &info, 1), "");
+    }
+
+    #[test]
+    fn render_handles_missing_date_and_hash() {
+        let mut info = info("a", &[]);
+        info.date = String::new();
+        info.hash = None;
+        assert_eq!(render("{date}{time}", &info, 1), "");
+        assert_eq!(render("{hash:8}", &info, 1), "{hash_8}");
+    }
+
+    #[test]
+    fn preview_numbers_only_selected_items() {
+        let dir = Path::new("/nonexistent");
+        let mut rename = batch(dir, &["a", "b", "c"], "p{counter}");
+        rename.items[1].selected = false;
+        rename.update_preview();
+        assert_eq!(rename.items[0].to, Some(dir.join("p1.jpg")));
+        assert_eq!(rename.items[1].to, None);
+        assert_eq!(rename.items[2].to, Some(dir.join("p2.jpg")));
+        assert!(!rename.has_problems());
+    }
+
+    #[test]
+    fn preview_detects_duplicate_and_empty_names() {
+        let dir = Path::new("/nonexistent");
+        let rename = batch(dir, &["a", "b"], "same");
+        assert!(rename.items.iter().all(|i| i.problem.as_deref() == Some("Duplicate name")));
+
+        // 大文字小文字だけの違いも衝突
+        let mut rename = batch(dir, &["a", "b"], "{name}");
+        rename.items[0].info.stem = "X".to_string();
+        rename.items[1].info.stem = "x".to_string();
+        rename.update_preview();
+        assert!(rename.items.iter().all(|i| i.problem.as_deref() == Some("Duplicate name")));
+
+        let rename = batch(dir, &["a"], "{tags}");
+        assert_eq!(rename.items[0].problem.as_deref(), Some("Empty name"));
+        assert!(rename.renames().is_empty());
+    }
+
+    #[test]
+    fn preview_detects_existing_files() {
+        let dir = temp_dir("batch_rename");
+        std::fs::write(dir.join("taken.jpg"), b"").unwrap();
+        std::fs::write(dir.join("b.jpg"), b"").unwrap();
+
+        // リネームしない既存ファイルとは衝突する
+        let rename = batch(&dir, &["a"], "taken");
+        assert_eq!(rename.items[0].problem.as_deref(), Some("File exists"));
+
+        // 同時にリネームされるファイルの名前なら使える（入れ替え）
+        let mut rename = batch(&dir, &["a", "b"], "{name}");
+        rename.items[0].info.stem = "b".to_string();
+        rename.items[1].info.stem = "a".to_string();
+        rename.update_preview();
+        assert!(!rename.has_problems());
+        assert_eq!(rename.renames().len(), 2);
+
+        // 名前が変わらないものは問題なし、リネーム対象にも入らない
+        let rename = batch(&dir, &["b"], "{name}");
+        assert!(!rename.has_problems());
+        assert!(rename.renames().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
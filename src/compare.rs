@@ -0,0 +1,180 @@
+use eframe::egui;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::image_loader;
+use crate::tag_manager;
+
+/// 同時に比較できる画像の最大数
+pub const MAX_PANES: usize = 4;
+
+/// 比較表示（連写の中からベストを選ぶ用）
+pub struct CompareView {
+    /// 比較モード中かどうか
+    pub is_active: bool,
+    /// 各ペインに表示中の画像
+    pub panes: Vec<PathBuf>,
+    /// フォーカス中のペイン（タグ・削除の対象）
+    pub focused: usize,
+    /// 全ペイン共通の拡大率（1.0 = 全体表示）
+    pub zoom: f32,
+    /// 全ペイン共通の表示位置（表示サイズに対する割合）
+    pub pan: egui::Vec2,
+    /// 読み込み済みテクスチャ
+    textures: HashMap<PathBuf, Option<egui::TextureHandle>>,
+    /// フォーカス外のペインのタグ
+    tags: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Default for CompareView {
+    fn default() -> Self {
+        Self {
+            is_active: false,
+            panes: Vec::new(),
+            focused: 0,
+            zoom: 1.0,
+            pan: egui::Vec2::ZERO,
+            textures: HashMap::new(),
+            tags: HashMap::new(),
+        }
+    }
+}
+
+impl CompareView {
+    /// 比較モードを開始
+    pub fn start(&mut self, mut paths: Vec<PathBuf>) {
+        paths.truncate(MAX_PANES);
+        self.panes = paths;
+        self.focused = 0;
+        self.is_active = !self.panes.is_empty();
+        self.reset_view();
+    }
+
+    /// 比較モードを終了
+    pub fn stop(&mut self) {
+        self.is_active = false;
+        self.panes.clear();
+        self.textures.clear();
+        self.tags.clear();
+    }
+
+    /// 拡大率と表示位置を初期状態に戻す
+    pub fn reset_view(&mut self) {
+        self.zoom = 1.0;
+        self.pan = egui::Vec2::ZERO;
+    }
+
+    /// フォーカス中のペインの画像
+    pub fn focused_path(&self) -> Option<&PathBuf> {
+        self.panes.get(self.focused)
+    }
+
+    /// ペインに画像を追加（既にあれば取り除く）
+    pub fn toggle_pane(&mut self, path: &Path) {
+        if let Some(pos) = self.panes.iter().position(|p| p == path) {
+            self.remove_pane(pos);
+        } else if self.panes.len() < MAX_PANES {
+            self.panes.push(path.to_path_buf());
+            self.focused = self.panes.len() - 1;
+            self.is_active = true;
+        }
+    }
+
+    /// ペインを取り除く
+    pub fn remove_pane(&mut self, index: usize) {
+        if index >= self.panes.len() {
+            return;
+        }
+        let path = self.panes.remove(index);
+        self.textures.remove(&path);
+        self.tags.remove(&path);
+        if self.focused >= self.panes.len() && !self.panes.is_empty() {
+            self.focused = self.panes.len() - 1;
+        }
+        if self.panes.is_empty() {
+            self.is_active = false;
+        }
+    }
+
+    /// フォーカス中のペインを候補リストの次（前）の画像に差し替える
+    /// 既に他のペインに表示中の画像は飛ばす。差し替えた画像のパスを返す
+    pub fn replace_focused(&mut self, candidates: &[PathBuf], forward: bool) -> Option<PathBuf> {
+        let current = self.focused_path()?;
+        let start = candidates.iter().position(|p| p == current)?;
+
+        let next = if forward {
+            candidates[start + 1..]
+                .iter()
+                .find(|p| !self.panes.contains(p))
+        } else {
+            candidates[..start]
+                .iter()
+                .rev()
+                .find(|p| !self.panes.contains(p))
+        }?
+        .clone();
+
+        let old = std::mem::replace(&mut self.panes[self.focused], next.clone());
+        self.textures.remove(&old);
+        self.tags.remove(&old);
+        Some(next)
+    }
+
+    /// フォーカス中のペインを指定した画像に差し替える
+    pub fn replace_focused_with(&mut self, path: &Path) {
+        let Some(pane) = self.panes.get_mut(self.focused) else {
+            return;
+        };
+        let old = std::mem::replace(pane, path.to_path_buf());
+        self.textures.remove(&old);
+        self.tags.remove(&old);
+    }
+
+    /// フォーカス外になったペインのタグを再読み込みさせる
+    pub fn invalidate_tags(&mut self, path: &Path) {
+        self.tags.remove(path);
+    }
+
+    /// 外部で変更されたファイルのテクスチャとタグを読み直させる
+    pub fn invalidate(&mut self, path: &Path) {
+        self.textures.remove(path);
+        self.tags.remove(path);
+    }
+
+    /// ペインのタグ（未読み込みなら読み込む）
+    pub fn tags(&mut self, path: &Path) -> &[String] {
+        self.tags
+            .entry(path.to_path_buf())
+            .or_insert_with(|| tag_manager::load_tags(path))
+    }
+
+    /// ペインのテクスチャ（未読み込みなら同期で読み込む）
+    pub fn texture(&mut self, ctx: &egui::Context, path: &Path) -> Option<egui::TextureHandle> {
+        self.textures
+            .entry(path.to_path_buf())
+            .or_insert_with(|| {
+                let img = image_loader::open(path).ok()?.to_rgba8();
+                let (w, h) = img.dimensions();
+                let color_image = egui::ColorImage::from_rgba_unmultiplied(
+                    [w as usize, h as usize],
+                    img.as_raw(),
+                );
+                Some(ctx.load_texture(
+                    format!("compare:{}", path.display()),
+                    color_image,
+                    egui::TextureOptions::default(),
+                ))
+            })
+            .clone()
+    }
+
+    /// ペイン数に応じたグリッドの列数と行数
+    pub fn grid_size(&self) -> (usize, usize) {
+        match self.panes.len() {
+            0 | 1 => (1, 1),
+            2 => (2, 1),
+            3 => (3, 1),
+            _ => (2, 2),
+        }
+    }
+}
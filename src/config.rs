@@ -3,37 +3,175 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
+use crate::duplicates::HashKind;
+use crate::file_ops::CollisionStrategy;
+use crate::file_tree::TreeFileTags;
+use crate::hooks::Hook;
+use crate::pending_changes::UnsavedPolicy;
+use crate::presentation::{CaptionOptions, Transition};
+use crate::session::{self, Session};
+use crate::sort_order::SortSettings;
+
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     /// ホットキー（キー文字列）に対応するタグ
     pub hotkey_tags: HashMap<String, String>,
+    /// Shift+キーで画像を移動するフォルダ
+    pub hotkey_move: HashMap<String, PathBuf>,
+    /// Shift+Alt+キーで画像をコピーするフォルダ
+    pub hotkey_copy: HashMap<String, PathBuf>,
+    /// 移動/コピー先に同名のファイルがあるときの扱い
+    pub collision_strategy: CollisionStrategy,
+    /// 一括リネームのテンプレート
+    pub rename_template: String,
+    /// 重複画像の判定に使うハッシュ
+    pub duplicate_hash: HashKind,
+    /// 重複とみなすハッシュの距離（ビット数）
+    pub duplicate_threshold: u32,
+    /// 重複を削除するとき、グループのタグを残す画像にまとめるか
+    pub duplicate_merge_tags: bool,
     /// オートセーブの有効/無効
     pub auto_save: bool,
     /// スライドショーの切り替え間隔（秒）
     pub slideshow_interval: f32,
     /// スライドショーをループするか
     pub slideshow_loop: bool,
+    /// スライドショーをシャッフル再生するか
+    pub slideshow_shuffle: bool,
+    /// 全画面でスライドショーを表示するか
+    pub slideshow_fullscreen: bool,
+    /// スライドショーの切り替え効果
+    pub slideshow_transition: Transition,
+    /// 切り替え効果の長さ（秒）
+    pub slideshow_transition_secs: f32,
+    /// スライドショーのキャプション
+    pub slideshow_caption: CaptionOptions,
+    /// アニメーション画像は最後まで再生してから次へ進むか
+    pub slideshow_wait_animation: bool,
+    /// 最近開いたプレイリスト（新しい順）
+    pub recent_playlists: Vec<PathBuf>,
+    /// 最近開いたフォルダ（新しい順）
+    pub recent_folders: Vec<PathBuf>,
+    /// 最近開いた画像（新しい順）
+    pub recent_images: Vec<PathBuf>,
+    /// ブックマークしたフォルダ（Ctrl+1〜9 で移動）
+    pub bookmarks: Vec<PathBuf>,
+    /// 起動時に前回の状態を復元するか
+    pub restore_session: bool,
+    /// 前回の表示状態
+    pub session: Session,
+    /// 画像リストの並び順（フォルダごと）
+    pub sort: SortSettings,
+    /// ファイルツリーのフォルダに画像数を表示するか
+    pub tree_folder_counts: bool,
+    /// フォルダごとに数えるタグ（空ならタグなしの画像を数える）
+    pub tree_count_tag: String,
+    /// ファイルツリーでのファイルごとのタグ表示
+    pub tree_file_tags: TreeFileTags,
+    /// レビューキューで「どれも持っていない」を調べるタグ（カンマ区切り）
+    pub review_tags: String,
+    /// タグを埋め込む前に元の画像をバックアップするか
+    pub backup_originals: bool,
+    /// フォルダごとに残すバックアップの数（0 なら無制限）
+    pub backup_limit: usize,
+    /// 似ている画像からタグを提案するか
+    pub suggest_tags: bool,
+    /// タグの統計にサブフォルダを含めるか
+    pub stats_recursive: bool,
+    /// 画像を書き換えず、設定フォルダのデータベースにタグを保存するか
+    pub library_mode: bool,
+    /// タグを編集した画像から離れるときの扱い
+    pub unsaved_policy: UnsavedPolicy,
+    /// タグの書き込みをバックグラウンドで行うか（ネットワークドライブで操作が止まらないように）
+    pub background_writes: bool,
+    /// タグを書き込んでも更新日時・アクセス日時を変えないか
+    pub preserve_timestamps: bool,
+    /// タグを書き込んだ後に画像が壊れていないか確認するか
+    pub verify_writes: bool,
+    /// 開いているフォルダの変更を監視して反映するか
+    pub watch_files: bool,
+    /// 2つ目の起動では新しいウィンドウを開かず、開いているウィンドウで開くか
+    pub single_instance: bool,
+    /// イベントで実行するコマンド
+    pub hooks: Vec<Hook>,
+    /// フックのコマンドを止めるまでの秒数
+    pub hook_timeout: u64,
+    /// 外部ツール向けの HTTP API を有効にするか
+    pub api_enabled: bool,
+    /// HTTP API のポート（127.0.0.1 のみで待ち受ける）
+    pub api_port: u16,
+    /// HTTP API の認証トークン（空なら有効にしたときに作る）
+    pub api_token: String,
+    /// 比較モードで並べる画像の数
+    pub compare_pane_count: usize,
     /// 左サイドバーの表示
     pub show_left_sidebar: bool,
     /// 右サイドバーの表示
     pub show_right_sidebar: bool,
+    /// メタデータウィンドウの表示
+    pub show_metadata_window: bool,
     
     // ウィンドウサイズ (width, height)
     pub left_window_size: Option<[f32; 2]>,
     pub right_window_size: Option<[f32; 2]>,
+    pub metadata_window_size: Option<[f32; 2]>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             hotkey_tags: HashMap::new(),
+            hotkey_move: HashMap::new(),
+            hotkey_copy: HashMap::new(),
+            collision_strategy: CollisionStrategy::default(),
+            rename_template: "{date}_{tags:1}_{counter:4}".to_string(),
+            duplicate_hash: HashKind::default(),
+            duplicate_threshold: 6,
+            duplicate_merge_tags: false,
             auto_save: false,
             slideshow_interval: 3.0,
             slideshow_loop: true,
+            slideshow_shuffle: false,
+            slideshow_fullscreen: false,
+            slideshow_transition: Transition::default(),
+            slideshow_transition_secs: 0.8,
+            slideshow_caption: CaptionOptions::default(),
+            slideshow_wait_animation: false,
+            recent_playlists: Vec::new(),
+            recent_folders: Vec::new(),
+            recent_images: Vec::new(),
+            bookmarks: Vec::new(),
+            restore_session: true,
+            session: Session::default(),
+            sort: SortSettings::default(),
+            tree_folder_counts: true,
+            tree_count_tag: String::new(),
+            tree_file_tags: TreeFileTags::default(),
+            review_tags: String::new(),
+            backup_originals: false,
+            backup_limit: 500,
+            suggest_tags: false,
+            stats_recursive: true,
+            library_mode: false,
+            unsaved_policy: UnsavedPolicy::default(),
+            background_writes: true,
+            preserve_timestamps: false,
+            verify_writes: true,
+            watch_files: true,
+            single_instance: true,
+            hooks: Vec::new(),
+            hook_timeout: 30,
+            api_enabled: false,
+            api_port: 47800,
+            api_token: String::new(),
+            compare_pane_count: 2,
             show_left_sidebar: false,
             show_right_sidebar: false,
+            show_metadata_window: false,
             left_window_size: None,
             right_window_size: None,
+            metadata_window_size: None,
         }
     }
 }
@@ -74,6 +212,19 @@ impl Config {
                                     }
                                 }
                             }
+                            for (name, targets) in [
+                                ("hotkey_move", &mut config.hotkey_move),
+                                ("hotkey_copy", &mut config.hotkey_copy),
+                            ] {
+                                if let Some(hotkeys) = settings.get(name).and_then(|v| v.as_object()) {
+                                    targets.clear();
+                                    for (k, v) in hotkeys {
+                                        if let Some(dir) = v.as_str() {
+                                            targets.insert(k.clone(), PathBuf::from(dir));
+                                        }
+                                    }
+                                }
+                            }
                         }
                     }
                 }
@@ -83,6 +234,32 @@ impl Config {
         config
     }
 
+    /// 最近開いたプレイリストに追加する
+    pub fn add_recent_playlist(&mut self, path: PathBuf) {
+        session::push_recent(&mut self.recent_playlists, path);
+    }
+
+    /// 最近開いたフォルダに追加する
+    pub fn add_recent_folder(&mut self, path: PathBuf) {
+        session::push_recent(&mut self.recent_folders, path);
+    }
+
+    /// 最近開いた画像に追加する
+    pub fn add_recent_image(&mut self, path: PathBuf) {
+        session::push_recent(&mut self.recent_images, path);
+    }
+
+    /// フォルダのブックマークを追加/解除する。追加したら true を返す
+    pub fn toggle_bookmark(&mut self, path: PathBuf) -> bool {
+        if let Some(pos) = self.bookmarks.iter().position(|p| p == &path) {
+            self.bookmarks.remove(pos);
+            false
+        } else {
+            self.bookmarks.push(path);
+            true
+        }
+    }
+
     pub fn save(&self) {
         let path = Self::config_path();
         if let Some(parent) = path.parent() {
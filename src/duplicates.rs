@@ -0,0 +1,434 @@
+use eframe::egui;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use walkdir::WalkDir;
+
+use crate::file_ops;
+use crate::image_loader;
+use crate::safe_write;
+use crate::tag_manager::is_image_file;
+
+/// グループ表示用のサムネイルの大きさ
+const THUMBNAIL_SIZE: u32 = 160;
+
+/// 類似判定に使う知覚ハッシュ
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashKind {
+    /// 平均輝度との比較（aHash）
+    Average,
+    /// 隣の画素との比較（dHash）
+    #[default]
+    Difference,
+    /// DCTの低周波成分（pHash）
+    Perceptual,
+}
+
+impl HashKind {
+    pub const ALL: [HashKind; 3] = [HashKind::Average, HashKind::Difference, HashKind::Perceptual];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HashKind::Average => "aHash",
+            HashKind::Difference => "dHash",
+            HashKind::Perceptual => "pHash",
+        }
+    }
+}
+
+/// 1枚の画像のハッシュ
+#[derive(Clone, Copy)]
+pub struct ImageHashes {
+    /// ファイル内容のハッシュ（完全一致の判定用）
+    pub content: u64,
+    pub average: u64,
+    pub difference: u64,
+    pub perceptual: u64,
+}
+
+impl ImageHashes {
+    fn get(&self, kind: HashKind) -> u64 {
+        match kind {
+            HashKind::Average => self.average,
+            HashKind::Difference => self.difference,
+            HashKind::Perceptual => self.perceptual,
+        }
+    }
+}
+
+/// 読み込んだ画像の情報
+pub struct ScannedImage {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub file_size: u64,
+    pub hashes: ImageHashes,
+    pub thumbnail: egui::ColorImage,
+}
+
+/// 画像を読み込んでハッシュを計算する
+fn scan_image(path: &Path) -> Option<ScannedImage> {
+    let content = file_ops::content_hash(path).ok()?;
+    let file_size = std::fs::metadata(path).ok()?.len();
+    let img = image_loader::open(path).ok()?;
+    let (width, height) = (img.width(), img.height());
+
+    // ハッシュは縮小した画像から計算する（大きな画像でも速く、細部の違いに左右されない）
+    let small = img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let hashes = ImageHashes {
+        content,
+        average: average_hash(&small),
+        difference: difference_hash(&small),
+        perceptual: perceptual_hash(&small),
+    };
+
+    let rgba = small.to_rgba8();
+    let thumbnail = egui::ColorImage::from_rgba_unmultiplied(
+        [rgba.width() as usize, rgba.height() as usize],
+        rgba.as_raw(),
+    );
+
+    Some(ScannedImage {
+        path: path.to_path_buf(),
+        width,
+        height,
+        file_size,
+        hashes,
+        thumbnail,
+    })
+}
+
+fn grayscale(img: &DynamicImage, width: u32, height: u32) -> Vec<f32> {
+    img.resize_exact(width, height, FilterType::Triangle)
+        .to_luma8()
+        .pixels()
+        .map(|p| p.0[0] as f32)
+        .collect()
+}
+
+/// 8x8 に縮小して平均より明るい画素を 1 にする
+fn average_hash(img: &DynamicImage) -> u64 {
+    let pixels = grayscale(img, 8, 8);
+    let mean = pixels.iter().sum::<f32>() / pixels.len() as f32;
+    bits(pixels.iter().map(|&p| p > mean))
+}
+
+/// 9x8 に縮小して右隣より明るい画素を 1 にする
+pub fn difference_hash(img: &DynamicImage) -> u64 {
+    let pixels = grayscale(img, 9, 8);
+    bits((0..8).flat_map(|y| {
+        let row = &pixels[y * 9..y * 9 + 9];
+        (0..8).map(move |x| row[x] > row[x + 1])
+    }))
+}
+
+/// 32x32 のDCTの左上 8x8（直流成分を除く）を中央値と比べる
+pub fn perceptual_hash(img: &DynamicImage) -> u64 {
+    const N: usize = 32;
+    let pixels = grayscale(img, N as u32, N as u32);
+    let cos: Vec<Vec<f32>> = (0..8)
+        .map(|u| {
+            (0..N)
+                .map(|x| ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / (2 * N) as f32).cos())
+                .collect()
+        })
+        .collect();
+
+    let mut coefficients = Vec::with_capacity(64);
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..N {
+                for x in 0..N {
+                    sum += pixels[y * N + x] * cos[u][x] * cos[v][y];
+                }
+            }
+            coefficients.push(sum);
+        }
+    }
+
+    let mut sorted: Vec<f32> = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    bits(coefficients.iter().map(|&c| c > median))
+}
+
+fn bits(values: impl Iterator<Item = bool>) -> u64 {
+    values
+        .take(64)
+        .enumerate()
+        .fold(0, |hash, (i, bit)| if bit { hash | (1 << i) } else { hash })
+}
+
+/// 重複画像の検索（読み込みはバックグラウンドで行う）
+pub struct DuplicateScan {
+    rx: Receiver<ScannedImage>,
+    /// 読み込んだ画像
+    pub images: Vec<ScannedImage>,
+    /// 対象の画像数
+    pub total: usize,
+    /// 処理済みの画像数（読み込めなかったものを含む）
+    done: Arc<AtomicUsize>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl DuplicateScan {
+    /// フォルダ以下の画像の読み込みを開始する
+    pub fn start(root: &Path, ctx: &egui::Context) -> Self {
+        let paths: Vec<PathBuf> = WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|e| !safe_write::is_backup_dir(e.path()))
+            .flatten()
+            .map(|e| e.into_path())
+            .filter(|p| is_image_file(p))
+            .collect();
+        let total = paths.len();
+        let (tx, rx) = mpsc::channel();
+        let done = Arc::new(AtomicUsize::new(0));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let ctx = ctx.clone();
+        let thread_done = done.clone();
+        let thread_cancel = cancel.clone();
+        std::thread::spawn(move || {
+            for path in paths {
+                if thread_cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+                if let Some(image) = scan_image(&path) {
+                    if tx.send(image).is_err() {
+                        break;
+                    }
+                }
+                thread_done.fetch_add(1, Ordering::Relaxed);
+                ctx.request_repaint();
+            }
+        });
+
+        Self {
+            rx,
+            images: Vec::new(),
+            total,
+            done,
+            cancel,
+        }
+    }
+
+    /// 読み込んだ画像を受け取る
+    fn poll(&mut self) {
+        self.images.extend(self.rx.try_iter());
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.done() < self.total && !self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// ハッシュの距離が threshold 以下の画像をまとめる（内容が同じものは常に同じグループ）
+    /// 各グループは解像度・ファイルサイズの大きい順（先頭が残す候補）
+    pub fn groups(&self, kind: HashKind, threshold: u32) -> Vec<Vec<usize>> {
+        let n = self.images.len();
+        let mut parent: Vec<usize> = (0..n).collect();
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        fn union(parent: &mut [usize], a: usize, b: usize) {
+            let (ra, rb) = (find(parent, a), find(parent, b));
+            if ra != rb {
+                parent[rb] = ra;
+            }
+        }
+
+        // 全組み合わせを比べるとしきい値を動かすたびに遅くなるので、BK木で近いハッシュだけを探す
+        let mut tree = BkTree::default();
+        for (i, image) in self.images.iter().enumerate() {
+            tree.insert(image.hashes.get(kind), i);
+        }
+        for node in &tree.nodes {
+            for &i in &node.items[1..] {
+                union(&mut parent, node.items[0], i);
+            }
+        }
+        for (i, image) in self.images.iter().enumerate() {
+            tree.find(image.hashes.get(kind), threshold, |j| union(&mut parent, i, j));
+        }
+        let mut by_content: HashMap<u64, usize> = HashMap::new();
+        for (i, image) in self.images.iter().enumerate() {
+            match by_content.get(&image.hashes.content) {
+                Some(&first) => union(&mut parent, first, i),
+                None => {
+                    by_content.insert(image.hashes.content, i);
+                }
+            }
+        }
+
+        let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+        for i in 0..n {
+            let root = find(&mut parent, i);
+            groups.entry(root).or_default().push(i);
+        }
+        let mut groups: Vec<Vec<usize>> = groups.into_values().filter(|g| g.len() > 1).collect();
+        for group in &mut groups {
+            group.sort_by_key(|&i| {
+                let image = &self.images[i];
+                std::cmp::Reverse((image.width as u64 * image.height as u64, image.file_size))
+            });
+        }
+        groups.sort_by_key(|g| self.images[g[0]].path.clone());
+        groups
+    }
+
+    /// 画像が削除されたら一覧から取り除く
+    fn remove(&mut self, path: &Path) {
+        self.images.retain(|i| i.path != path);
+    }
+
+    /// 2枚の画像の内容が同じか
+    pub fn is_exact(&self, a: usize, b: usize) -> bool {
+        self.images[a].hashes.content == self.images[b].hashes.content
+    }
+}
+
+/// ハミング距離で近いハッシュを探す BK木
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    /// 同じハッシュの画像
+    items: Vec<usize>,
+    /// 距離ごとの子ノード
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, item: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push(BkNode {
+                hash,
+                items: vec![item],
+                children: Vec::new(),
+            });
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = (self.nodes[current].hash ^ hash).count_ones();
+            if distance == 0 {
+                self.nodes[current].items.push(item);
+                return;
+            }
+            match self.nodes[current].children.iter().find(|(d, _)| *d == distance) {
+                Some(&(_, child)) => current = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push(BkNode {
+                        hash,
+                        items: vec![item],
+                        children: Vec::new(),
+                    });
+                    self.nodes[current].children.push((distance, index));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// 距離が threshold 以下のノードの先頭の画像を渡す（同じノードの画像はまとめておくこと）
+    fn find(&self, hash: u64, threshold: u32, mut found: impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let distance = (node.hash ^ hash).count_ones();
+            if distance <= threshold {
+                found(node.items[0]);
+            }
+            for &(d, child) in &node.children {
+                if d + threshold >= distance && d <= distance + threshold {
+                    stack.push(child);
+                }
+            }
+        }
+    }
+}
+
+/// 重複画像ウィンドウの状態
+pub struct DuplicateFinder {
+    pub scan: DuplicateScan,
+    /// 似ている画像のグループ（scan.images のインデックス）
+    pub groups: Vec<Vec<usize>>,
+    /// グループごとに残す画像
+    pub keepers: Vec<usize>,
+    /// groups を作ったときの条件（変わったら作り直す）
+    grouped_with: Option<(HashKind, u32, usize)>,
+    textures: HashMap<PathBuf, egui::TextureHandle>,
+}
+
+impl DuplicateFinder {
+    pub fn new(root: &Path, ctx: &egui::Context) -> Self {
+        Self {
+            scan: DuplicateScan::start(root, ctx),
+            groups: Vec::new(),
+            keepers: Vec::new(),
+            grouped_with: None,
+            textures: HashMap::new(),
+        }
+    }
+
+    /// 読み込みが終わっていればグループを作る（条件が変わったときだけ）
+    pub fn update(&mut self, kind: HashKind, threshold: u32) {
+        self.scan.poll();
+        if self.scan.is_running() {
+            return;
+        }
+        let key = Some((kind, threshold, self.scan.images.len()));
+        if self.grouped_with != key {
+            self.groups = self.scan.groups(kind, threshold);
+            self.keepers = self.groups.iter().map(|g| g[0]).collect();
+            self.grouped_with = key;
+        }
+    }
+
+    /// サムネイルのテクスチャ
+    pub fn texture(&mut self, ctx: &egui::Context, index: usize) -> egui::TextureHandle {
+        let image = &self.scan.images[index];
+        self.textures
+            .entry(image.path.clone())
+            .or_insert_with(|| {
+                ctx.load_texture(
+                    format!("duplicate:{}", image.path.display()),
+                    image.thumbnail.clone(),
+                    egui::TextureOptions::default(),
+                )
+            })
+            .clone()
+    }
+
+    /// 削除した画像を取り除く（グループは作り直す）
+    pub fn remove(&mut self, path: &Path) {
+        self.scan.remove(path);
+        self.textures.remove(path);
+        self.grouped_with = None;
+    }
+}
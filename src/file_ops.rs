@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::fnv::Fnv;
+use crate::safe_write;
+use crate::sidecar;
+use crate::tag_database;
+
+/// 移動/コピー先に同名のファイルがあるときの扱い
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CollisionStrategy {
+    /// name_1.jpg のように番号を付ける
+    #[default]
+    NumberSuffix,
+    /// name (1).jpg のように番号を付ける
+    Parenthesized,
+    /// 移動/コピーしない
+    Skip,
+}
+
+impl CollisionStrategy {
+    pub const ALL: [CollisionStrategy; 3] = [
+        CollisionStrategy::NumberSuffix,
+        CollisionStrategy::Parenthesized,
+        CollisionStrategy::Skip,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CollisionStrategy::NumberSuffix => "Rename (name_1)",
+            CollisionStrategy::Parenthesized => "Rename (name (1))",
+            CollisionStrategy::Skip => "Skip",
+        }
+    }
+}
+
+/// 元に戻すためのファイル操作の記録（サイドカーも一緒に扱う）
+#[derive(Clone, Debug)]
+pub enum FileOp {
+    /// 移動（名前の変更を含む）
+    Moved { from: PathBuf, to: PathBuf },
+    /// コピー
+    Copied { to: PathBuf },
+}
+
+impl FileOp {
+    /// 操作を取り消す
+    pub fn undo(&self) -> io::Result<()> {
+        match self {
+            FileOp::Moved { from, to } => {
+                if from.exists() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", from.display()),
+                    ));
+                }
+                move_with_sidecar(to, from)
+            }
+            FileOp::Copied { to } => {
+                fs::remove_file(to)?;
+                let to_sidecar = sidecar::sidecar_path(to);
+                if to_sidecar.is_file() {
+                    fs::remove_file(to_sidecar)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// フォルダ内で使えるファイル名を決める（Skip で衝突した場合は None）
+pub fn destination(dir: &Path, file_name: &str, strategy: CollisionStrategy) -> Option<PathBuf> {
+    let is_free = |p: &Path| !p.exists() && !sidecar::has_sidecar(p);
+    let path = dir.join(file_name);
+    if is_free(&path) {
+        return Some(path);
+    }
+
+    let name = Path::new(file_name);
+    let stem = name.file_stem().and_then(|s| s.to_str()).unwrap_or(file_name);
+    let ext = name.extension().and_then(|s| s.to_str());
+    let candidate = |n: usize| {
+        let base = match strategy {
+            CollisionStrategy::Parenthesized => format!("{} ({})", stem, n),
+            _ => format!("{}_{}", stem, n),
+        };
+        match ext {
+            Some(ext) => dir.join(format!("{}.{}", base, ext)),
+            None => dir.join(base),
+        }
+    };
+    match strategy {
+        CollisionStrategy::Skip => None,
+        _ => (1..).map(candidate).find(|p| is_free(p)),
+    }
+}
+
+/// 画像をフォルダに移動する（衝突して移動しなかった場合は None）
+pub fn move_to(path: &Path, dir: &Path, strategy: CollisionStrategy) -> io::Result<Option<FileOp>> {
+    let Some(to) = destination_for(path, dir, strategy)? else {
+        return Ok(None);
+    };
+    move_with_sidecar(path, &to)?;
+    Ok(Some(FileOp::Moved {
+        from: path.to_path_buf(),
+        to,
+    }))
+}
+
+/// 画像をフォルダにコピーする（衝突してコピーしなかった場合は None）
+pub fn copy_to(path: &Path, dir: &Path, strategy: CollisionStrategy) -> io::Result<Option<FileOp>> {
+    let Some(to) = destination_for(path, dir, strategy)? else {
+        return Ok(None);
+    };
+    fs::copy(path, &to)?;
+    let from_sidecar = sidecar::sidecar_path(path);
+    if from_sidecar.is_file() {
+        fs::copy(from_sidecar, sidecar::sidecar_path(&to))?;
+    }
+    Ok(Some(FileOp::Copied { to }))
+}
+
+fn destination_for(path: &Path, dir: &Path, strategy: CollisionStrategy) -> io::Result<Option<PathBuf>> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?;
+    fs::create_dir_all(dir)?;
+    // 同じフォルダへの移動は何もしない
+    if path.parent() == Some(dir) {
+        return Ok(None);
+    }
+    Ok(destination(dir, file_name, strategy))
+}
+
+/// ファイルをサイドカー・元画像のバックアップごと移動する（ライブラリモードの計算済みハッシュも付け替える）
+pub fn move_with_sidecar(from: &Path, to: &Path) -> io::Result<()> {
+    rename_or_copy(from, to)?;
+    tag_database::rename(from, to);
+    let from_sidecar = sidecar::sidecar_path(from);
+    if from_sidecar.is_file() {
+        rename_or_copy(&from_sidecar, &sidecar::sidecar_path(to))?;
+    }
+    let (from_backup, to_backup) = (safe_write::backup_path(from), safe_write::backup_path(to));
+    if from_backup.is_file() && !to_backup.exists() {
+        if let Some(dir) = to_backup.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        rename_or_copy(&from_backup, &to_backup)?;
+        if let Some(dir) = from_backup.parent() {
+            // 空になったバックアップフォルダは残さない
+            let _ = fs::remove_dir(dir);
+        }
+    }
+    Ok(())
+}
+
+/// 名前を変更する（別のドライブならコピーしてから削除）
+fn rename_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// ファイル内容のハッシュ
+pub fn content_hash(path: &Path) -> io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    let mut hash = Fnv::default();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash.finish());
+        }
+        hash.write(&buf[..n]);
+    }
+}
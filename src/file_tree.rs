@@ -1,7 +1,35 @@
+use crate::safe_write;
+use crate::sort_order::{self, DateCache, SortContext, SortSettings};
+use crate::tag_cache::TagCache;
 use crate::tag_manager::is_image_file;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
+/// ファイルツリーでのファイルごとのタグ表示
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TreeFileTags {
+    /// 表示しない
+    Hidden,
+    /// タグ付き/タグなしの印
+    Marker,
+    /// タグ名のチップ
+    #[default]
+    Chips,
+}
+
+impl TreeFileTags {
+    pub const ALL: [TreeFileTags; 3] = [TreeFileTags::Hidden, TreeFileTags::Marker, TreeFileTags::Chips];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TreeFileTags::Hidden => "Hidden",
+            TreeFileTags::Marker => "Tagged marker",
+            TreeFileTags::Chips => "Tag chips",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FileNode {
     pub path: PathBuf,
@@ -28,7 +56,7 @@ impl FileNode {
     }
 
     /// ディレクトリの子要素を読み込む
-    pub fn load_children(&mut self) {
+    pub fn load_children(&mut self, sort: &mut SortContext) {
         if !self.is_dir {
             return;
         }
@@ -41,58 +69,103 @@ impl FileNode {
             for entry in entries.flatten() {
                 let path = entry.path();
                 if path.is_dir() {
+                    if safe_write::is_backup_dir(&path) {
+                        continue;
+                    }
                     dirs.push(FileNode::new(path));
                 } else if is_image_file(&path) {
                     files.push(FileNode::new(path));
                 }
             }
 
-            // ディレクトリを先に、その後ファイルをソートして追加
-            dirs.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-            files.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-
             self.children.extend(dirs);
             self.children.extend(files);
+            self.sort_children(sort);
+        }
+    }
+
+    /// 子要素を読み直す（読み込み済みのサブフォルダの中身は保持する）
+    pub fn reload_children(&mut self, sort: &mut SortContext) {
+        let mut old: HashMap<PathBuf, FileNode> = std::mem::take(&mut self.children)
+            .into_iter()
+            .map(|c| (c.path.clone(), c))
+            .collect();
+        self.load_children(sort);
+        for child in &mut self.children {
+            if let Some(old) = old.remove(&child.path) {
+                child.children = old.children;
+            }
+        }
+    }
+
+    /// 子要素を並べ替える（ディレクトリは名前順で先に、ファイルは設定された並び順）
+    pub fn sort_children(&mut self, sort: &mut SortContext) {
+        let (mut dirs, mut files): (Vec<FileNode>, Vec<FileNode>) =
+            std::mem::take(&mut self.children).into_iter().partition(|c| c.is_dir);
+
+        dirs.sort_by(|a, b| sort_order::natural_cmp(&a.name.to_lowercase(), &b.name.to_lowercase()));
+
+        let mut paths: Vec<PathBuf> = files.iter().map(|f| f.path.clone()).collect();
+        sort.sort(&self.path, &mut paths);
+        let rank: HashMap<PathBuf, usize> = paths.into_iter().enumerate().map(|(i, p)| (p, i)).collect();
+        files.sort_by_key(|f| rank.get(&f.path).copied().unwrap_or(usize::MAX));
+
+        self.children = dirs;
+        self.children.extend(files);
+
+        for child in &mut self.children {
+            if child.is_dir && !child.children.is_empty() {
+                child.sort_children(sort);
+            }
         }
     }
 }
 
+#[derive(Default)]
 pub struct FileTree {
     pub root: Option<FileNode>,
     pub expanded: HashSet<PathBuf>,
+    /// 並び順の設定
+    pub sort: SortSettings,
+    /// 読み込んだフォルダの撮影日時（ルートを変えたら捨てる）
+    dates: DateCache,
 }
 
-impl Default for FileTree {
-    fn default() -> Self {
-        Self {
-            root: None,
-            expanded: HashSet::new(),
+impl FileTree {
+    /// 並べ替えに使うもの
+    fn sorter<'a>(sort: &'a SortSettings, dates: &'a mut DateCache, tags: &'a mut TagCache) -> SortContext<'a> {
+        SortContext {
+            settings: sort,
+            dates,
+            tags,
         }
     }
-}
 
-impl FileTree {
-    pub fn set_root(&mut self, path: &Path) {
-        if path.is_dir() {
-            let mut root = FileNode::new(path.to_path_buf());
-            root.load_children();
-            self.expanded.insert(path.to_path_buf());
-            self.root = Some(root);
-        } else if let Some(parent) = path.parent() {
-            let mut root = FileNode::new(parent.to_path_buf());
-            root.load_children();
-            self.expanded.insert(parent.to_path_buf());
+    pub fn set_root(&mut self, path: &Path, tags: &mut TagCache) {
+        let dir = if path.is_dir() { Some(path) } else { path.parent() };
+        if let Some(dir) = dir {
+            self.dates.clear();
+            let mut root = FileNode::new(dir.to_path_buf());
+            root.load_children(&mut Self::sorter(&self.sort, &mut self.dates, tags));
+            self.expanded.insert(dir.to_path_buf());
             self.root = Some(root);
         }
     }
 
-    pub fn toggle_expanded(&mut self, path: &Path) {
+    pub fn toggle_expanded(&mut self, path: &Path, tags: &mut TagCache) {
         if self.expanded.contains(path) {
             self.expanded.remove(path);
         } else {
             self.expanded.insert(path.to_path_buf());
             // 子要素を読み込む
-            self.load_children_for_path(path);
+            self.load_children_for_path(path, tags);
+        }
+    }
+
+    /// フォルダを展開する（親から順に呼ぶ）
+    pub fn expand(&mut self, path: &Path, tags: &mut TagCache) {
+        if self.expanded.insert(path.to_path_buf()) {
+            self.load_children_for_path(path, tags);
         }
     }
 
@@ -100,20 +173,51 @@ impl FileTree {
         self.expanded.contains(path)
     }
 
-    fn load_children_for_path(&mut self, target: &Path) {
+    /// 現在の並び順設定でツリー全体を並べ直す
+    pub fn resort(&mut self, tags: &mut TagCache) {
+        if let Some(ref mut root) = self.root {
+            root.sort_children(&mut Self::sorter(&self.sort, &mut self.dates, tags));
+        }
+    }
+
+    /// 変更のあったフォルダを読み直す
+    pub fn refresh(&mut self, dirs: &HashSet<PathBuf>, tags: &mut TagCache) {
+        let Some(root) = &mut self.root else {
+            return;
+        };
+        if !root.path.is_dir() {
+            self.root = None;
+            return;
+        }
+        Self::refresh_recursive(root, dirs, &mut Self::sorter(&self.sort, &mut self.dates, tags));
+        self.expanded.retain(|p| p.is_dir());
+    }
+
+    fn refresh_recursive(node: &mut FileNode, dirs: &HashSet<PathBuf>, sort: &mut SortContext) {
+        if dirs.contains(&node.path) {
+            node.reload_children(sort);
+        }
+        for child in &mut node.children {
+            if child.is_dir && dirs.iter().any(|d| d.starts_with(&child.path)) {
+                Self::refresh_recursive(child, dirs, sort);
+            }
+        }
+    }
+
+    fn load_children_for_path(&mut self, target: &Path, tags: &mut TagCache) {
         if let Some(ref mut root) = self.root {
-            Self::load_children_recursive(root, target);
+            Self::load_children_recursive(root, target, &mut Self::sorter(&self.sort, &mut self.dates, tags));
         }
     }
 
-    fn load_children_recursive(node: &mut FileNode, target: &Path) {
+    fn load_children_recursive(node: &mut FileNode, target: &Path, sort: &mut SortContext) {
         if node.path == target {
-            node.load_children();
+            node.load_children(sort);
             return;
         }
         for child in &mut node.children {
             if target.starts_with(&child.path) {
-                Self::load_children_recursive(child, target);
+                Self::load_children_recursive(child, target, sort);
             }
         }
     }
@@ -0,0 +1,164 @@
+use eframe::egui;
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant, SystemTime};
+
+/// 最後のイベントからこの時間が経ったら変更をまとめて通知する（書き込み途中の読み込みを避ける）
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// ファイルシステムの変更内容
+#[derive(Default)]
+pub struct FsChanges {
+    /// 子要素が増減したディレクトリ
+    pub dirs: HashSet<PathBuf>,
+    /// 内容が変わったファイル
+    pub modified: HashSet<PathBuf>,
+    /// 名前が変わったファイル（変更前, 変更後）
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl FsChanges {
+    fn is_empty(&self) -> bool {
+        self.dirs.is_empty() && self.modified.is_empty() && self.renamed.is_empty()
+    }
+
+    fn add(&mut self, event: Event) {
+        let parents = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .filter_map(|p| p.parent().map(|d| d.to_path_buf()))
+                .collect::<Vec<_>>()
+        };
+        match event.kind {
+            EventKind::Create(_) | EventKind::Remove(_) => {
+                self.dirs.extend(parents(&event.paths));
+            }
+            EventKind::Modify(ModifyKind::Name(mode)) => {
+                if mode == RenameMode::Both && event.paths.len() == 2 {
+                    self.renamed
+                        .push((event.paths[0].clone(), event.paths[1].clone()));
+                }
+                self.dirs.extend(parents(&event.paths));
+            }
+            EventKind::Modify(ModifyKind::Metadata(_)) | EventKind::Access(_) => {}
+            EventKind::Modify(_) => {
+                self.modified.extend(event.paths);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 開いているフォルダを監視して変更を集める
+pub struct FsWatcher {
+    watcher: Option<RecommendedWatcher>,
+    rx: Receiver<notify::Result<Event>>,
+    /// 監視中のパス
+    watched: Vec<(PathBuf, RecursiveMode)>,
+    /// まだ通知していない変更
+    pending: FsChanges,
+    last_event: Instant,
+    /// 自分で書き込んだファイルと書き込み後の更新日時（自分の保存で再読み込みしないため）
+    own_writes: HashMap<PathBuf, SystemTime>,
+}
+
+impl FsWatcher {
+    /// 変更があれば ctx の再描画を要求する
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+            ctx.request_repaint_after(DEBOUNCE);
+        })
+        .ok();
+
+        Self {
+            watcher,
+            rx,
+            watched: Vec::new(),
+            pending: FsChanges::default(),
+            last_event: Instant::now(),
+            own_writes: HashMap::new(),
+        }
+    }
+
+    /// 監視対象を設定する（ツリーのルートはサブフォルダも、表示中の画像のフォルダはそのフォルダのみ）
+    /// 前回と同じなら何もしない
+    pub fn watch(&mut self, root: Option<&Path>, current_dir: Option<&Path>) {
+        let mut targets = Vec::new();
+        if let Some(root) = root {
+            targets.push((root.to_path_buf(), RecursiveMode::Recursive));
+        }
+        if let Some(dir) = current_dir {
+            if !root.is_some_and(|root| dir.starts_with(root)) {
+                targets.push((dir.to_path_buf(), RecursiveMode::NonRecursive));
+            }
+        }
+        if targets == self.watched {
+            return;
+        }
+
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        for (path, _) in &self.watched {
+            let _ = watcher.unwatch(path);
+        }
+        self.watched.clear();
+        for (path, mode) in targets {
+            // 監視できなくても（ネットワークドライブなど）表示はそのまま続ける
+            let _ = watcher.watch(&path, mode);
+            self.watched.push((path, mode));
+        }
+        self.pending = FsChanges::default();
+    }
+
+    /// 監視をやめる
+    pub fn unwatch_all(&mut self) {
+        if let Some(watcher) = &mut self.watcher {
+            for (path, _) in &self.watched {
+                let _ = watcher.unwatch(path);
+            }
+        }
+        self.watched.clear();
+        self.pending = FsChanges::default();
+    }
+
+    /// 自分で書き込んだファイルを記録する（書き込み直後に呼ぶ）
+    pub fn note_write(&mut self, path: &Path) {
+        if let Some(modified) = modified_time(path) {
+            self.own_writes.insert(path.to_path_buf(), modified);
+        }
+    }
+
+    /// 変更が落ち着いていれば、たまった変更を返す
+    pub fn poll(&mut self) -> Option<FsChanges> {
+        while let Ok(event) = self.rx.try_recv() {
+            if let Ok(event) = event {
+                self.pending.add(event);
+                self.last_event = Instant::now();
+            }
+        }
+        if self.pending.is_empty() || self.last_event.elapsed() < DEBOUNCE {
+            return None;
+        }
+
+        let mut changes = std::mem::take(&mut self.pending);
+        // 自分の保存による変更は除く（その後さらに外部で変更されていれば残す）
+        changes.modified.retain(|path| {
+            match self.own_writes.remove(path) {
+                Some(written) => modified_time(path) != Some(written),
+                None => true,
+            }
+        });
+        (!changes.is_empty()).then_some(changes)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,419 @@
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// ユーザー定義のフック：イベントが起きたらシェルコマンドを実行する
+// コマンド中の {path} などは1つの文字列として扱われるようエスケープした値に置き換える
+// 置き換えの前後の引用符に合わせるので、'{path}' や "{path}" と書いても二重に囲まれない
+// （Windows ではシェルを通さない）
+
+/// コマンドで使える置き換え
+pub const PLACEHOLDERS: &str = "{path} {name} {stem} {ext} {dir} {tag} {tags} {error}";
+
+/// フックを実行するきっかけ
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HookEvent {
+    /// タグを追加して保存した
+    #[default]
+    TagAdded,
+    /// タグを削除して保存した
+    TagRemoved,
+    ImageOpened,
+    /// ファイルをゴミ箱に移動した
+    FileTrashed,
+    /// タグを保存できなかった
+    SaveFailed,
+}
+
+impl HookEvent {
+    pub const ALL: [HookEvent; 5] = [
+        HookEvent::TagAdded,
+        HookEvent::TagRemoved,
+        HookEvent::ImageOpened,
+        HookEvent::FileTrashed,
+        HookEvent::SaveFailed,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HookEvent::TagAdded => "Tag added",
+            HookEvent::TagRemoved => "Tag removed",
+            HookEvent::ImageOpened => "Image opened",
+            HookEvent::FileTrashed => "File trashed",
+            HookEvent::SaveFailed => "Save failed",
+        }
+    }
+
+    /// タグで絞り込めるイベントか
+    pub fn has_tag(&self) -> bool {
+        matches!(self, HookEvent::TagAdded | HookEvent::TagRemoved)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hook {
+    pub enabled: bool,
+    pub event: HookEvent,
+    /// タグの追加・削除で対象にするタグ（空ならすべて）
+    pub tag: String,
+    pub command: String,
+}
+
+impl Default for Hook {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            event: HookEvent::default(),
+            tag: String::new(),
+            command: String::new(),
+        }
+    }
+}
+
+impl Hook {
+    fn matches(&self, event: HookEvent, tag: Option<&str>) -> bool {
+        self.enabled
+            && self.event == event
+            && !self.command.trim().is_empty()
+            && (!event.has_tag() || self.tag.is_empty() || tag == Some(self.tag.as_str()))
+    }
+}
+
+/// タグの追加・削除のフックがあるか（書き込む前のタグを調べる必要があるか）
+pub fn watches_tags(hooks: &[Hook]) -> bool {
+    hooks
+        .iter()
+        .any(|h| h.enabled && h.event.has_tag() && !h.command.trim().is_empty())
+}
+
+/// フックに渡す内容
+pub struct HookContext<'a> {
+    pub path: &'a Path,
+    pub tags: &'a [String],
+    /// 追加・削除されたタグ
+    pub tag: Option<&'a str>,
+    pub error: Option<&'a str>,
+}
+
+/// 置き換える位置の引用符の状態
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+/// コマンド中の置き換えを展開する（値はその位置の引用符に合わせて quote で変換して埋め込む）
+fn expand(command: &str, context: &HookContext, quote: fn(&str, Quote) -> String) -> String {
+    let text = |s: Option<&std::ffi::OsStr>| s.map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let values = [
+        ("{path}", context.path.display().to_string()),
+        ("{name}", text(context.path.file_name())),
+        ("{stem}", text(context.path.file_stem())),
+        ("{ext}", text(context.path.extension())),
+        ("{dir}", text(context.path.parent().map(Path::as_os_str))),
+        ("{tag}", context.tag.unwrap_or_default().to_string()),
+        ("{tags}", context.tags.join(", ")),
+        ("{error}", context.error.unwrap_or_default().to_string()),
+    ];
+    let mut result = String::new();
+    let mut state = Quote::None;
+    let mut escaped = false;
+    let mut rest = command;
+    while let Some(c) = rest.chars().next() {
+        if !escaped {
+            if let Some((key, value)) = values.iter().find(|(key, _)| rest.starts_with(key)) {
+                result.push_str(&quote(value, state));
+                rest = &rest[key.len()..];
+                continue;
+            }
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        // sh と同じ規則で引用符の中かどうかを追う
+        match (state, c) {
+            (Quote::Single, '\'') => state = Quote::None,
+            (Quote::Single, _) => {}
+            (_, '\\') => escaped = true,
+            (Quote::None, '\'') => state = Quote::Single,
+            (Quote::None, '"') => state = Quote::Double,
+            (Quote::Double, '"') => state = Quote::None,
+            _ => {}
+        }
+    }
+    result
+}
+
+/// sh が1つの文字列として扱うよう値をエスケープする
+fn quote(value: &str, state: Quote) -> String {
+    match state {
+        Quote::None => format!("'{}'", value.replace('\'', "'\\''")),
+        Quote::Single => value.replace('\'', "'\\''"),
+        Quote::Double => {
+            let mut escaped = String::new();
+            for c in value.chars() {
+                if matches!(c, '$' | '`' | '"' | '\\') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        }
+    }
+}
+
+/// 実行するプログラムと引数
+///
+/// Windows の cmd.exe は %VAR% や & などを引用符の中でも解釈して安全に埋め込めないので、
+/// シェルを通さずにコマンドを空白で区切って（"..." は1つの引数として）直接実行する。
+/// 置き換えた値はそのまま1つの引数の一部になる
+fn command_line(command: &str, context: &HookContext) -> Vec<String> {
+    if cfg!(windows) {
+        split_words(command)
+            .iter()
+            .map(|word| expand(word, context, |value, _| value.to_string()))
+            .collect()
+    } else {
+        vec!["sh".to_string(), "-c".to_string(), expand(command, context, quote)]
+    }
+}
+
+/// 空白で区切る（"..." の中の空白では区切らない）
+fn split_words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    for c in command.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// フックの実行結果
+pub struct HookOutput {
+    /// イベントとタグ
+    pub label: String,
+    pub command: String,
+    /// 成功したら出力、失敗したら終了コードや出力
+    pub result: Result<String, String>,
+}
+
+/// フックをバックグラウンドで実行する
+pub struct HookRunner {
+    ctx: egui::Context,
+    tx: Sender<HookOutput>,
+    rx: Receiver<HookOutput>,
+    /// 実行中のコマンドの数
+    running: Arc<AtomicUsize>,
+}
+
+impl HookRunner {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            ctx: ctx.clone(),
+            tx,
+            rx,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// イベントに合うフックを登録順に実行する
+    pub fn fire(&self, hooks: &[Hook], event: HookEvent, context: &HookContext, timeout: Duration) {
+        let commands: Vec<Vec<String>> = hooks
+            .iter()
+            .filter(|h| h.matches(event, context.tag))
+            .map(|h| command_line(&h.command, context))
+            .filter(|args| !args.is_empty())
+            .collect();
+        if commands.is_empty() {
+            return;
+        }
+        let label = match context.tag {
+            Some(tag) => format!("{}: {}", event.label(), tag),
+            None => event.label().to_string(),
+        };
+        let tx = self.tx.clone();
+        let ctx = self.ctx.clone();
+        let running = Arc::clone(&self.running);
+        running.fetch_add(commands.len(), Ordering::Relaxed);
+        std::thread::spawn(move || {
+            for args in commands {
+                let result = run_command(&args, timeout);
+                running.fetch_sub(1, Ordering::Relaxed);
+                let _ = tx.send(HookOutput {
+                    label: label.clone(),
+                    command: if cfg!(windows) { args.join(" ") } else { args[2].clone() },
+                    result,
+                });
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    pub fn poll(&self) -> Vec<HookOutput> {
+        self.rx.try_iter().collect()
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+}
+
+/// コマンドが終わった後に出力を待つ時間
+/// （バックグラウンドで動き続ける子プロセスがパイプを持ったままのことがある）
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// コマンドを実行して出力を返す（timeout を過ぎたら止める）
+fn run_command(args: &[String], timeout: Duration) -> Result<String, String> {
+    let mut command = Command::new(&args[0]);
+    command
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // sh から起動されたプロセスもまとめて止められるよう、新しいプロセスグループで実行する
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.spawn().map_err(|e| e.to_string())?;
+    // 出力が多いとパイプが詰まって終わらないので別スレッドで読む
+    // 読み終わるのを待たずに諦めても、それまでの出力は残るよう少しずつ溜める
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        let bytes = Arc::new(Mutex::new(Vec::new()));
+        let (tx, rx) = mpsc::channel();
+        let buffer = Arc::clone(&bytes);
+        std::thread::spawn(move || {
+            if let Some(mut pipe) = pipe {
+                let mut chunk = [0u8; 4096];
+                while let Ok(n @ 1..) = pipe.read(&mut chunk) {
+                    buffer.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(&chunk[..n]);
+                }
+            }
+            let _ = tx.send(());
+        });
+        (bytes, rx)
+    };
+    let stdout = read(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+    let stderr = read(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() >= timeout => {
+                kill(&mut child);
+                let _ = child.wait();
+                return Err(format!("Timed out after {}s", timeout.as_secs()));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+    // 読み終わらなくても OUTPUT_GRACE で諦める
+    let deadline = Instant::now() + OUTPUT_GRACE;
+    let collect = |(bytes, done): (Arc<Mutex<Vec<u8>>>, Receiver<()>)| {
+        let _ = done.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        let bytes = bytes.lock().unwrap_or_else(|e| e.into_inner());
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    let output = [collect(stdout), collect(stderr)]
+        .iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if status.success() {
+        Ok(output)
+    } else if output.is_empty() {
+        Err(status.to_string())
+    } else {
+        Err(format!("{}: {}", status, output))
+    }
+}
+
+/// コマンドと、そこから起動されたプロセスを止める
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        // プロセスグループの ID はコマンドのプロセス ID と同じ
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn echo(command: &str, path: &str) -> String {
+        let context = HookContext {
+            path: Path::new(path),
+            tags: &[],
+            tag: None,
+            error: None,
+        };
+        run_command(&command_line(command, &context), Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn placeholders_are_one_word_with_or_without_quotes() {
+        let path = "/tmp/it's a \"$HOME\" `x` \\ file.png";
+        assert_eq!(echo("printf %s {path}", path), path);
+        assert_eq!(echo("printf %s '{path}'", path), path);
+        assert_eq!(echo("printf %s \"{path}\"", path), path);
+        assert_eq!(echo("printf %s \"name: {name}\"", path), "name: it's a \"$HOME\" `x` \\ file.png");
+    }
+
+    #[test]
+    fn timeout_stops_background_processes() {
+        let started = Instant::now();
+        let result = run_command(
+            &["sh".to_string(), "-c".to_string(), "sleep 30 & sleep 30".to_string()],
+            Duration::from_millis(200),
+        );
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn background_process_does_not_hold_the_output() {
+        let started = Instant::now();
+        let result = run_command(
+            &["sh".to_string(), "-c".to_string(), "echo done; sleep 30 &".to_string()],
+            Duration::from_secs(10),
+        );
+        assert_eq!(result, Ok("done".to_string()));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
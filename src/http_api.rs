@@ -0,0 +1,507 @@
+use eframe::egui;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::image_loader;
+use crate::tag_manager::{self, is_image_file};
+use crate::tag_query::TagQuery;
+
+// 外部ツール向けの HTTP/JSON API（127.0.0.1 のみで待ち受け、トークンで認証する）
+//
+//   GET  /api/current                      表示中の画像とタグ
+//   POST /api/open         {"path"}        画像かフォルダを開く
+//   POST /api/tags/add     {"tags", "path"?} タグを追加して保存する（path がなければ表示中の画像）
+//   POST /api/tags/remove  {"tags", "path"?} タグを削除して保存する
+//   GET  /api/query?q=&folder=&recursive=  検索条件に合う画像
+//   GET  /api/thumbnail?path=&size=        サムネイル（PNG）
+//   GET  /api/events                       変更の通知（Server-Sent Events）
+//
+// トークンは Authorization: Bearer ヘッダか token クエリで渡す（EventSource はヘッダを付けられないため）
+
+/// UIスレッドの応答を待つ最大時間
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// 接続が止まったときに諦めるまでの時間
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// イベントがないときに接続維持のコメントを送る間隔
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// 受け付けるリクエスト行とヘッダの合計の最大サイズ
+const MAX_HEADER: u64 = 16 << 10;
+/// 受け付けるリクエスト本文の最大サイズ
+const MAX_BODY: usize = 1 << 20;
+/// 同時に処理する接続の最大数（超えたら 503 を返してすぐ閉じる）
+const MAX_CONNECTIONS: usize = 32;
+const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+const MAX_THUMBNAIL_SIZE: u32 = 2048;
+
+/// UIスレッドで実行する操作
+pub enum ApiCommand {
+    Current,
+    Open(PathBuf),
+    AddTags { path: Option<PathBuf>, tags: Vec<String> },
+    RemoveTags { path: Option<PathBuf>, tags: Vec<String> },
+    /// 表示中のフォルダ（なければ null）
+    CurrentFolder,
+    /// 読み込み済みのタグ（paths と同じ並びで、読み込んでいなければ null）
+    CachedTags(Vec<PathBuf>),
+}
+
+/// UIスレッドへの依頼（結果は respond で返す）
+pub struct ApiRequest {
+    pub command: ApiCommand,
+    reply: Sender<Result<Value, String>>,
+}
+
+impl ApiRequest {
+    pub fn respond(self, result: Result<Value, String>) {
+        let _ = self.reply.send(result);
+    }
+}
+
+/// 接続スレッドと共有する状態
+struct Shared {
+    token: String,
+    ctx: egui::Context,
+    requests: Mutex<Sender<ApiRequest>>,
+    /// イベントを受け取る接続
+    subscribers: Mutex<Vec<Sender<String>>>,
+    /// 最後に通知した表示中の画像の状態（接続直後に送る）
+    current: Mutex<Option<String>>,
+    stop: AtomicBool,
+    /// 処理中の接続の数
+    connections: AtomicUsize,
+}
+
+/// 待ち受け中のサーバー（破棄すると止まる）
+pub struct ApiServer {
+    port: u16,
+    shared: Arc<Shared>,
+    requests: Receiver<ApiRequest>,
+    /// 待ち受けスレッド
+    listener: Option<JoinHandle<()>>,
+    /// 最後に通知した表示中の画像の状態
+    last_state: Option<Value>,
+}
+
+/// 推測されにくいトークンを作る（OS の乱数を使う）
+pub fn generate_token() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).map_err(io::Error::other)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl ApiServer {
+    pub fn start(ctx: &egui::Context, port: u16, token: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let port = listener.local_addr()?.port();
+        let (tx, requests) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            token: token.to_string(),
+            ctx: ctx.clone(),
+            requests: Mutex::new(tx),
+            subscribers: Mutex::new(Vec::new()),
+            current: Mutex::new(None),
+            stop: AtomicBool::new(false),
+            connections: AtomicUsize::new(0),
+        });
+        let accept_shared = Arc::clone(&shared);
+        let listener = std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_shared.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                // 認証前の接続でスレッドを使い切られないよう数を制限する
+                if accept_shared.connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                    accept_shared.connections.fetch_sub(1, Ordering::Relaxed);
+                    let _ = write_json(&stream, Err((503, "Too many connections".to_string())));
+                    continue;
+                }
+                let shared = Arc::clone(&accept_shared);
+                std::thread::spawn(move || {
+                    let _ = handle_connection(stream, &shared);
+                    shared.connections.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        });
+        Ok(Self {
+            port,
+            shared,
+            requests,
+            listener: Some(listener),
+            last_state: None,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// 届いた依頼を受け取る
+    pub fn poll(&self) -> Vec<ApiRequest> {
+        self.requests.try_iter().collect()
+    }
+
+    /// イベントを通知する
+    pub fn publish(&self, event: &str, data: &Value) {
+        self.send(event_message(event, data));
+    }
+
+    fn send(&self, message: String) {
+        let mut subscribers = self.shared.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|tx| tx.send(message.clone()).is_ok());
+    }
+
+    /// 表示中の画像の状態が変わっていれば current イベントを通知する
+    pub fn publish_state(&mut self, state: Value) {
+        if self.last_state.as_ref() != Some(&state) {
+            let message = event_message("current", &state);
+            *self.shared.current.lock().unwrap_or_else(|e| e.into_inner()) = Some(message.clone());
+            self.send(message);
+            self.last_state = Some(state);
+        }
+    }
+}
+
+fn event_message(event: &str, data: &Value) -> String {
+    format!("event: {}\ndata: {}\n\n", event, data)
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.shared.subscribers.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        // accept で止まっている待ち受けスレッドを起こし、すぐに同じポートで起動し直せるよう終わるのを待つ
+        if TcpStream::connect((Ipv4Addr::LOCALHOST, self.port)).is_ok() {
+            if let Some(listener) = self.listener.take() {
+                let _ = listener.join();
+            }
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Bad request");
+    // 改行のない巨大な行で際限なく読み込まないよう、読む量を制限する
+    let mut reader = BufReader::new(stream.take(MAX_HEADER));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(invalid)?.to_string();
+    let target = parts.next().ok_or_else(invalid)?.to_string();
+
+    let mut authorization = None;
+    let mut content_length = 0;
+    // 空行まで読めなければ（ヘッダが多すぎる・大きすぎる）受け付けない
+    let mut complete = false;
+    for _ in 0..100 {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            break;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            complete = true;
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("authorization") {
+            authorization = value.strip_prefix("Bearer ").map(|t| t.trim().to_string());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| invalid())?;
+        }
+    }
+    if !complete || content_length > MAX_BODY {
+        return Err(invalid());
+    }
+    reader.get_mut().set_limit(content_length as u64);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query
+        .split('&')
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect();
+    Ok(Request {
+        method,
+        path: percent_decode(path),
+        query,
+        authorization,
+        body,
+    })
+}
+
+/// URL のエンコードを戻す（+ は空白）
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = |b: u8| (b as char).to_digit(16);
+                match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        out.push((high * 16 + low) as u8);
+                        i += 2;
+                    }
+                    _ => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// 長さから中身を推測されないよう全体を比べる
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn write_response(mut stream: &TcpStream, status: u16, content_type: &str, body: &[u8]) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Headers: Authorization, Content-Type\r\n\
+         Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn write_json(stream: &TcpStream, result: Result<Value, (u16, String)>) -> io::Result<()> {
+    let (status, value) = match result {
+        Ok(value) => (200, value),
+        Err((status, message)) => (status, json!({ "error": message })),
+    };
+    write_response(stream, status, "application/json", value.to_string().as_bytes())
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(e) => return write_json(&stream, Err((400, e.to_string()))),
+    };
+    if shared.stop.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    // ブラウザの事前確認には認証なしで答える
+    if request.method == "OPTIONS" {
+        return write_response(&stream, 204, "text/plain", b"");
+    }
+    let token = request.authorization.as_deref().or(request.param("token"));
+    if !token.is_some_and(|t| token_matches(t, &shared.token)) {
+        return write_json(&stream, Err((401, "Missing or invalid token".to_string())));
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/api/events") => stream_events(stream, shared),
+        ("GET", "/api/thumbnail") => match thumbnail(&request) {
+            Ok(png) => write_response(&stream, 200, "image/png", &png),
+            Err(e) => write_json(&stream, Err(e)),
+        },
+        ("GET", "/api/query") => write_json(&stream, query_images(&request, shared)),
+        (method, path) => {
+            let result = parse_command(method, path, &request).and_then(|command| run_on_ui(shared, command));
+            write_json(&stream, result)
+        }
+    }
+}
+
+fn parse_command(method: &str, path: &str, request: &Request) -> Result<ApiCommand, (u16, String)> {
+    let body = || -> Result<Value, (u16, String)> {
+        if request.body.is_empty() {
+            return Ok(json!({}));
+        }
+        serde_json::from_slice(&request.body).map_err(|e| (400, format!("Invalid JSON: {}", e)))
+    };
+    let path_field = |value: &Value| value.get("path").and_then(Value::as_str).map(PathBuf::from);
+    let tags_field = |value: &Value| -> Result<Vec<String>, (u16, String)> {
+        let tags: Vec<String> = match (value.get("tags"), value.get("tag")) {
+            (Some(Value::Array(tags)), _) => tags.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+            (_, Some(Value::String(tag))) => vec![tag.clone()],
+            _ => Vec::new(),
+        };
+        let tags: Vec<String> = tags.into_iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+        if tags.is_empty() {
+            return Err((400, "No tags given".to_string()));
+        }
+        Ok(tags)
+    };
+
+    match (method, path) {
+        ("GET", "/api/current") => Ok(ApiCommand::Current),
+        ("POST", "/api/open") => {
+            let body = body()?;
+            let path = path_field(&body).ok_or_else(|| (400, "Missing path".to_string()))?;
+            Ok(ApiCommand::Open(path))
+        }
+        ("POST", "/api/tags/add") | ("POST", "/api/tags/remove") => {
+            let body = body()?;
+            let tags = tags_field(&body)?;
+            let target = path_field(&body);
+            Ok(if path.ends_with("/add") {
+                ApiCommand::AddTags { path: target, tags }
+            } else {
+                ApiCommand::RemoveTags { path: target, tags }
+            })
+        }
+        (_, "/api/current" | "/api/open" | "/api/tags/add" | "/api/tags/remove" | "/api/query") => {
+            Err((405, "Method not allowed".to_string()))
+        }
+        _ => Err((404, "Not found".to_string())),
+    }
+}
+
+/// UIスレッドに依頼して結果を待つ
+fn run_on_ui(shared: &Shared, command: ApiCommand) -> Result<Value, (u16, String)> {
+    let (reply, rx) = mpsc::channel();
+    let sent = shared
+        .requests
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .send(ApiRequest { command, reply });
+    if sent.is_err() {
+        return Err((503, "Editor is shutting down".to_string()));
+    }
+    shared.ctx.request_repaint();
+    match rx.recv_timeout(REPLY_TIMEOUT) {
+        Ok(result) => result.map_err(|e| (400, e)),
+        Err(_) => Err((503, "Editor did not respond".to_string())),
+    }
+}
+
+/// 検索条件に合う画像（フォルダを指定しなければ表示中のフォルダ）
+/// フォルダの走査やタグの読み込みで UIスレッドを止めないよう接続のスレッドで探し、
+/// UIスレッドには表示中のフォルダと読み込み済みのタグだけを問い合わせる
+fn query_images(request: &Request, shared: &Shared) -> Result<Value, (u16, String)> {
+    let folder = match request.param("folder") {
+        Some(folder) => PathBuf::from(folder),
+        None => run_on_ui(shared, ApiCommand::CurrentFolder)?
+            .as_str()
+            .map(PathBuf::from)
+            .ok_or_else(|| (400, "No folder is open".to_string()))?,
+    };
+    if !folder.is_dir() {
+        return Err((400, format!("Not a folder: {}", folder.display())));
+    }
+    let recursive = matches!(request.param("recursive"), Some("1" | "true"));
+    let query = TagQuery::parse(request.param("q").unwrap_or_default());
+
+    let paths = tag_manager::find_images(&folder, recursive);
+    let cached = run_on_ui(shared, ApiCommand::CachedTags(paths.clone()))?;
+    let cached = cached.as_array().map(Vec::as_slice).unwrap_or_default();
+    let images: Vec<Value> = paths
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, path)| {
+            let tags: Vec<String> = match cached.get(i).and_then(|t| serde_json::from_value(t.clone()).ok()) {
+                Some(tags) => tags,
+                None => tag_manager::load_tags(&path),
+            };
+            query
+                .matches(&tags)
+                .then(|| json!({ "path": path, "tags": tags }))
+        })
+        .collect();
+    Ok(json!({ "folder": folder, "count": images.len(), "images": images }))
+}
+
+/// サムネイルは UIスレッドを使わずに作る
+fn thumbnail(request: &Request) -> Result<Vec<u8>, (u16, String)> {
+    let path = PathBuf::from(request.param("path").ok_or_else(|| (400, "Missing path".to_string()))?);
+    if !path.is_file() || !is_image_file(&path) {
+        return Err((404, format!("Not an image: {}", path.display())));
+    }
+    let size = request
+        .param("size")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_THUMBNAIL_SIZE)
+        .clamp(16, MAX_THUMBNAIL_SIZE);
+    let image = image_loader::open(&path).map_err(|e| (400, e.to_string()))?;
+    let mut png = Vec::new();
+    image
+        .thumbnail(size, size)
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| (400, e.to_string()))?;
+    Ok(png)
+}
+
+/// Server-Sent Events で変更を送り続ける（切断されるかサーバーが止まるまで）
+fn stream_events(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    // 接続直後に現在の状態を送る
+    if let Some(current) = shared.current.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+        let _ = tx.send(current);
+    }
+    shared.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(tx);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: keep-alive\r\n\r\n: connected\n\n"
+    )?;
+    stream.flush()?;
+    loop {
+        match rx.recv_timeout(KEEP_ALIVE) {
+            Ok(message) => stream.write_all(message.as_bytes())?,
+            Err(RecvTimeoutError::Timeout) => stream.write_all(b": ping\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
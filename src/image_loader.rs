@@ -0,0 +1,74 @@
+use image::{DynamicImage, ImageResult};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// 画像ファイルをデコードする
+/// imageクレートが扱えないフォーマット（JPEG XL, HEIC/AVIF）は専用デコーダを使う
+pub fn open(path: &Path) -> ImageResult<DynamicImage> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+
+    match ext.as_str() {
+        "jxl" => open_jxl(path),
+        #[cfg(feature = "heif")]
+        "heic" | "heif" | "avif" => open_heif(path),
+        // image クレートの AVIF デコードも使えないので、何が足りないかを伝える
+        #[cfg(not(feature = "heif"))]
+        "heic" | "heif" | "avif" => Err(image::ImageError::IoError(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "HEIC/AVIF support is not included in this build (build with --features heif)",
+        ))),
+        _ => image::open(path),
+    }
+}
+
+fn open_jxl(path: &Path) -> ImageResult<DynamicImage> {
+    let reader = BufReader::new(File::open(path)?);
+    let decoder = jxl_oxide::integration::JxlDecoder::new(reader)?;
+    DynamicImage::from_decoder(decoder)
+}
+
+#[cfg(feature = "heif")]
+fn open_heif(path: &Path) -> ImageResult<DynamicImage> {
+    use image::ImageError;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let to_error = |e: libheif_rs::HeifError| ImageError::IoError(std::io::Error::other(e.to_string()));
+
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| ImageError::IoError(std::io::Error::other("Invalid path")))?;
+    let lib_heif = LibHeif::new();
+    let ctx = HeifContext::read_from_file(path_str).map_err(to_error)?;
+    let handle = ctx.primary_image_handle().map_err(to_error)?;
+    let decoded = lib_heif
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(to_error)?;
+
+    let planes = decoded.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| ImageError::IoError(std::io::Error::other("No interleaved plane")))?;
+
+    // 行末にパディングがあるので stride を考慮してコピーする
+    let row_len = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    image::RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| ImageError::IoError(std::io::Error::other("Invalid image buffer")))
+}
+
+/// 画像の幅と高さを取得する（可能ならヘッダのみ読む）
+pub fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    image::image_dimensions(path)
+        .ok()
+        .or_else(|| open(path).ok().map(|img| (img.width(), img.height())))
+}
@@ -1,7 +1,10 @@
 use std::path::{Path, PathBuf};
 
+use crate::sort_order::{self, DateCache, SortSettings};
+use crate::tag_cache::TagCache;
 use crate::tag_manager::is_image_file;
 
+#[derive(Default)]
 pub struct ImageViewer {
     /// 現在表示中の画像パス
     pub current_image: Option<PathBuf>,
@@ -9,72 +12,30 @@ pub struct ImageViewer {
     pub images_in_dir: Vec<PathBuf>,
     /// 現在の画像のインデックス
     pub current_index: usize,
-    /// 画像のテクスチャハンドル（egui用）
-    texture_uri: Option<String>,
-}
-
-impl Default for ImageViewer {
-    fn default() -> Self {
-        Self {
-            current_image: None,
-            images_in_dir: Vec::new(),
-            current_index: 0,
-            texture_uri: None,
-        }
-    }
+    /// 並び順の設定
+    pub sort: SortSettings,
+    /// 表示中のフォルダの撮影日時
+    dates: DateCache,
 }
 
 impl ImageViewer {
-    /// パスをfile:// URIに変換（Windows対応）
-    fn path_to_uri(path: &Path) -> String {
-        // 絶対パスに正規化
-        let abs_path = if path.is_absolute() {
-            path.to_path_buf()
-        } else {
-            std::env::current_dir()
-                .map(|cwd| cwd.join(path))
-                .unwrap_or_else(|_| path.to_path_buf())
-        };
-        
-        // canonicalizeでパスを正規化（シンボリックリンク解決など）
-        let canonical = abs_path.canonicalize().unwrap_or(abs_path);
-        
-        // Windowsパスを正規化してスラッシュに変換
-        let path_str = canonical.to_string_lossy();
-        
-        // Windows UNCパス形式の接頭辞を除去 (\\?\)
-        let cleaned = if path_str.starts_with(r"\\?\") {
-            &path_str[4..]
-        } else {
-            &path_str
-        };
-        
-        // バックスラッシュをスラッシュに変換
-        let normalized = cleaned.replace('\\', "/");
-        
-        // file:/// で始まる URI を生成（Windowsではスラッシュ3つ必要）
-        format!("file:///{}", normalized)
-    }
-
     pub fn close(&mut self) {
         self.current_image = None;
-        self.texture_uri = None;
         self.current_index = 0;
         // images_in_dir は保持してもクリアしてもよいが、ここでは保持する（ディレクトリ移動ではないため）
     }
 
     /// 画像を開く
-    pub fn open(&mut self, path: &Path) {
+    pub fn open(&mut self, path: &Path, tags: &mut TagCache) {
         if !path.exists() || !is_image_file(path) {
             return;
         }
 
         self.current_image = Some(path.to_path_buf());
-        self.texture_uri = Some(Self::path_to_uri(path));
 
         // 同じディレクトリ内の画像リストを更新
         if let Some(parent) = path.parent() {
-            self.load_directory_images(parent);
+            self.load_directory_images(parent, tags);
             // 現在の画像のインデックスを見つける
             self.current_index = self
                 .images_in_dir
@@ -84,8 +45,20 @@ impl ImageViewer {
         }
     }
 
+    /// フォルダの index 番目の画像を開く（範囲外なら最後の画像）
+    pub fn open_index_in(&mut self, dir: &Path, index: usize, tags: &mut TagCache) {
+        self.load_directory_images(dir, tags);
+        let Some(last) = self.images_in_dir.len().checked_sub(1) else {
+            return;
+        };
+        self.current_index = index.min(last);
+        if let Some(path) = self.images_in_dir.get(self.current_index).cloned() {
+            self.current_image = Some(path);
+        }
+    }
+
     /// ディレクトリ内の画像を読み込む
-    fn load_directory_images(&mut self, dir: &Path) {
+    fn load_directory_images(&mut self, dir: &Path, tags: &mut TagCache) {
         self.images_in_dir.clear();
         if let Ok(entries) = std::fs::read_dir(dir) {
             for entry in entries.flatten() {
@@ -95,7 +68,54 @@ impl ImageViewer {
                 }
             }
         }
-        self.images_in_dir.sort();
+        sort_order::sort_paths(&mut self.images_in_dir, self.sort.order_for(dir), &mut self.dates, tags);
+        self.dates.retain(&self.images_in_dir);
+    }
+
+    /// 現在の並び順設定で並べ直す（表示中の画像はそのまま）
+    pub fn resort(&mut self, tags: &mut TagCache) {
+        let Some(dir) = self.current_image.as_ref().and_then(|p| p.parent()).map(|p| p.to_path_buf()) else {
+            return;
+        };
+        sort_order::sort_paths(&mut self.images_in_dir, self.sort.order_for(&dir), &mut self.dates, tags);
+        if let Some(current) = &self.current_image {
+            if let Some(pos) = self.images_in_dir.iter().position(|p| p == current) {
+                self.current_index = pos;
+            }
+        }
+    }
+
+    /// 表示中の画像の名前が変わったら追従する
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        if self.current_image.as_deref() == Some(from) && is_image_file(to) {
+            self.current_image = Some(to.to_path_buf());
+        }
+    }
+
+    /// 画像リストを読み直す（表示中の画像が消えていたら同じ位置の画像に移る）
+    /// 表示する画像が変わったら true を返す
+    pub fn refresh(&mut self, tags: &mut TagCache) -> bool {
+        let Some(current) = self.current_image.clone() else {
+            return false;
+        };
+        let Some(dir) = current.parent() else {
+            return false;
+        };
+        self.load_directory_images(dir, tags);
+        if let Some(pos) = self.images_in_dir.iter().position(|p| p == &current) {
+            self.current_index = pos;
+            return false;
+        }
+
+        if self.images_in_dir.is_empty() {
+            self.close();
+            return true;
+        }
+        self.current_index = self.current_index.min(self.images_in_dir.len() - 1);
+        if let Some(path) = self.images_in_dir.get(self.current_index).cloned() {
+            self.current_image = Some(path);
+        }
+        true
     }
 
     /// 前の画像に移動
@@ -109,8 +129,7 @@ impl ImageViewer {
             self.current_index = self.images_in_dir.len() - 1;
         }
         if let Some(path) = self.images_in_dir.get(self.current_index).cloned() {
-            self.current_image = Some(path.clone());
-            self.texture_uri = Some(Self::path_to_uri(&path));
+            self.current_image = Some(path);
         }
     }
 
@@ -121,28 +140,48 @@ impl ImageViewer {
         }
         self.current_index = (self.current_index + 1) % self.images_in_dir.len();
         if let Some(path) = self.images_in_dir.get(self.current_index).cloned() {
-            self.current_image = Some(path.clone());
-            self.texture_uri = Some(Self::path_to_uri(&path));
+            self.current_image = Some(path);
         }
     }
 
+    /// matching が true の次の画像に移動（images_in_dir と同じ並び）。移動したら true
+    pub fn next_matching(&mut self, matching: &[bool]) -> bool {
+        let len = self.images_in_dir.len();
+        let found = (1..len)
+            .map(|offset| (self.current_index + offset) % len)
+            .find(|&i| matching.get(i).copied().unwrap_or(false));
+        self.jump(found)
+    }
+
+    /// matching が true の前の画像に移動。移動したら true
+    pub fn prev_matching(&mut self, matching: &[bool]) -> bool {
+        let len = self.images_in_dir.len();
+        let found = (1..len)
+            .map(|offset| (self.current_index + len - offset) % len)
+            .find(|&i| matching.get(i).copied().unwrap_or(false));
+        self.jump(found)
+    }
+
+    fn jump(&mut self, index: Option<usize>) -> bool {
+        let Some((index, path)) = index.and_then(|i| Some((i, self.images_in_dir.get(i)?.clone()))) else {
+            return false;
+        };
+        self.current_index = index;
+        self.current_image = Some(path);
+        true
+    }
+
     /// 指定インデックスの画像に移動
     #[allow(dead_code)]
     pub fn goto(&mut self, index: usize) {
         if index < self.images_in_dir.len() {
             self.current_index = index;
             if let Some(path) = self.images_in_dir.get(self.current_index).cloned() {
-                self.current_image = Some(path.clone());
-                self.texture_uri = Some(Self::path_to_uri(&path));
+                self.current_image = Some(path);
             }
         }
     }
 
-    /// テクスチャURIを取得
-    pub fn get_texture_uri(&self) -> Option<&str> {
-        self.texture_uri.as_deref()
-    }
-
     /// 画像の総数を取得
     pub fn total_images(&self) -> usize {
         self.images_in_dir.len()
@@ -1,22 +1,56 @@
 #![windows_subsystem = "windows"]
 
+mod animation;
 mod app;
+mod batch_rename;
+mod compare;
 mod config;
+mod duplicates;
+mod file_ops;
 mod file_tree;
+mod fnv;
+mod fs_watcher;
+mod hooks;
+mod http_api;
+mod image_loader;
 mod image_viewer;
+mod metadata_info;
+mod pending_changes;
+mod playlist;
+mod presentation;
+mod review_queue;
+mod safe_write;
+mod session;
+mod single_instance;
+mod sidecar;
 mod slideshow;
+mod sort_order;
+mod tag_cache;
+mod tag_database;
 mod tag_manager;
+mod tag_query;
+mod tag_stats;
+mod tag_suggestions;
+mod write_check;
+mod write_queue;
 
 use app::TagEditorApp;
+use config::Config;
 use eframe::egui;
-use std::path::PathBuf;
+use single_instance::{InstanceSocket, Invocation};
 
 fn main() -> eframe::Result<()> {
     // コマンドライン引数から初期パスを取得（exeへのD&Dで渡される）
-    let initial_path: Option<PathBuf> = std::env::args()
-        .nth(1)
-        .map(PathBuf::from)
-        .filter(|p| p.exists());
+    let invocation = Invocation::from_args(std::env::args().skip(1));
+
+    // 既にウィンドウが開いていればそちらで開く
+    let mut socket = None;
+    if Config::load().single_instance && !invocation.new_instance {
+        if single_instance::forward(&invocation) {
+            return Ok(());
+        }
+        socket = InstanceSocket::bind();
+    }
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -31,7 +65,7 @@ fn main() -> eframe::Result<()> {
         options,
         Box::new(move |cc| {
             egui_extras::install_image_loaders(&cc.egui_ctx);
-            Ok(Box::new(TagEditorApp::new(cc, initial_path)))
+            Ok(Box::new(TagEditorApp::new(cc, invocation, socket)))
         }),
     )
 }
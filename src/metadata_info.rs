@@ -0,0 +1,299 @@
+use image::ImageDecoder;
+use little_exif::exif_tag::ExifTag;
+use little_exif::metadata::Metadata;
+use little_exif::rational::uR64;
+use std::path::Path;
+
+use crate::image_loader;
+
+/// Exifの生タグ1件分
+pub struct RawTag {
+    pub id: u16,
+    pub name: String,
+    pub value: String,
+}
+
+/// メタデータインスペクタに表示する情報
+#[derive(Default)]
+pub struct MetadataInfo {
+    /// 主要項目（ラベル, 値）
+    pub summary: Vec<(&'static str, String)>,
+    /// すべてのExifタグ
+    pub raw_tags: Vec<RawTag>,
+    /// UserCommentの生バイト列
+    pub user_comment: Option<Vec<u8>>,
+}
+
+impl MetadataInfo {
+    /// 画像ファイルからメタデータを読み込む
+    pub fn read(path: &Path) -> Self {
+        let mut info = Self::default();
+        let metadata = Metadata::new_from_path(path).ok();
+
+        if let Some(metadata) = &metadata {
+            for tag in metadata {
+                let debug = format!("{:?}", tag);
+                let (name, value) = match debug.find('(') {
+                    Some(pos) => (debug[..pos].to_string(), debug[pos..].to_string()),
+                    None => (debug.clone(), String::new()),
+                };
+                info.raw_tags.push(RawTag {
+                    id: tag.as_u16(),
+                    name,
+                    value,
+                });
+            }
+
+            if let Some(ExifTag::UserComment(data)) =
+                metadata.get_tag(&ExifTag::UserComment(Vec::new())).next()
+            {
+                info.user_comment = Some(data.clone());
+            }
+        }
+
+        let make = find_tag(&metadata, |t| match t {
+            ExifTag::Make(s) => Some(clean(s)),
+            _ => None,
+        });
+        let model = find_tag(&metadata, |t| match t {
+            ExifTag::Model(s) => Some(clean(s)),
+            _ => None,
+        });
+        let camera = match (make, model) {
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.or(model),
+        };
+        info.push("Camera", camera);
+
+        info.push(
+            "Lens",
+            find_tag(&metadata, |t| match t {
+                ExifTag::LensModel(s) => Some(clean(s)),
+                _ => None,
+            }),
+        );
+
+        let exposure = [
+            find_tag(&metadata, |t| match t {
+                ExifTag::ExposureTime(v) => v.first().map(format_exposure_time),
+                _ => None,
+            }),
+            find_tag(&metadata, |t| match t {
+                ExifTag::FNumber(v) => v.first().map(|r| format!("f/{:.1}", ratio(r))),
+                _ => None,
+            }),
+            find_tag(&metadata, |t| match t {
+                ExifTag::ISO(v) => v.first().map(|iso| format!("ISO {}", iso)),
+                _ => None,
+            }),
+            find_tag(&metadata, |t| match t {
+                ExifTag::FocalLength(v) => v.first().map(|r| format!("{:.0} mm", ratio(r))),
+                _ => None,
+            }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        info.push("Exposure", (!exposure.is_empty()).then(|| exposure.join("  ")));
+
+        info.push(
+            "Date taken",
+            find_tag(&metadata, |t| match t {
+                ExifTag::DateTimeOriginal(s) => Some(clean(s)),
+                _ => None,
+            })
+            .or_else(|| {
+                find_tag(&metadata, |t| match t {
+                    ExifTag::CreateDate(s) => Some(clean(s)),
+                    _ => None,
+                })
+            }),
+        );
+
+        let lat = find_tag(&metadata, |t| match t {
+            ExifTag::GPSLatitude(v) => dms_to_degrees(v),
+            _ => None,
+        });
+        let lon = find_tag(&metadata, |t| match t {
+            ExifTag::GPSLongitude(v) => dms_to_degrees(v),
+            _ => None,
+        });
+        let lat_ref = find_tag(&metadata, |t| match t {
+            ExifTag::GPSLatitudeRef(s) => Some(clean(s)),
+            _ => None,
+        });
+        let lon_ref = find_tag(&metadata, |t| match t {
+            ExifTag::GPSLongitudeRef(s) => Some(clean(s)),
+            _ => None,
+        });
+        let gps = match (lat, lon) {
+            (Some(lat), Some(lon)) => {
+                let lat = if lat_ref.as_deref() == Some("S") { -lat } else { lat };
+                let lon = if lon_ref.as_deref() == Some("W") { -lon } else { lon };
+                Some(format!("{:.6}, {:.6}", lat, lon))
+            }
+            _ => None,
+        };
+        info.push("GPS", gps);
+
+        info.push(
+            "Dimensions",
+            image_loader::dimensions(path).map(|(w, h)| format!("{} x {}", w, h)),
+        );
+
+        info.push(
+            "File size",
+            std::fs::metadata(path).ok().map(|m| format_file_size(m.len())),
+        );
+
+        let exif_color_space = find_tag(&metadata, |t| match t {
+            ExifTag::ColorSpace(v) => v.first().map(|c| match c {
+                1 => "sRGB".to_string(),
+                0xFFFF => "Uncalibrated".to_string(),
+                other => format!("Unknown ({})", other),
+            }),
+            _ => None,
+        });
+        info.push("Colour profile", icc_profile_description(path).or(exif_color_space));
+
+        info
+    }
+
+    fn push(&mut self, label: &'static str, value: Option<String>) {
+        if let Some(value) = value {
+            if !value.is_empty() {
+                self.summary.push((label, value));
+            }
+        }
+    }
+}
+
+/// Exifの撮影日時（YYYY:MM:DD HH:MM:SS）
+pub fn date_taken(path: &Path) -> Option<String> {
+    let metadata = Metadata::new_from_path(path).ok();
+    find_tag(&metadata, |t| match t {
+        ExifTag::DateTimeOriginal(s) => Some(clean(s)),
+        _ => None,
+    })
+    .or_else(|| {
+        find_tag(&metadata, |t| match t {
+            ExifTag::CreateDate(s) => Some(clean(s)),
+            _ => None,
+        })
+    })
+    .filter(|s| !s.is_empty())
+}
+
+/// 条件に合う最初のタグから値を取り出す
+fn find_tag<T>(metadata: &Option<Metadata>, f: impl Fn(&ExifTag) -> Option<T>) -> Option<T> {
+    metadata.as_ref()?.into_iter().find_map(f)
+}
+
+/// Exif文字列の末尾のヌル文字や空白を除去
+fn clean(s: &str) -> String {
+    s.trim_matches(char::from(0)).trim().to_string()
+}
+
+fn ratio(r: &uR64) -> f64 {
+    if r.denominator == 0 {
+        0.0
+    } else {
+        r.nominator as f64 / r.denominator as f64
+    }
+}
+
+fn format_exposure_time(r: &uR64) -> String {
+    let seconds = ratio(r);
+    if seconds > 0.0 && seconds < 1.0 {
+        format!("1/{:.0} s", 1.0 / seconds)
+    } else {
+        format!("{:.1} s", seconds)
+    }
+}
+
+/// 度・分・秒の有理数3つを10進の度に変換
+fn dms_to_degrees(v: &[uR64]) -> Option<f64> {
+    if v.len() < 3 {
+        return None;
+    }
+    Some(ratio(&v[0]) + ratio(&v[1]) / 60.0 + ratio(&v[2]) / 3600.0)
+}
+
+pub fn format_file_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {} ({} bytes)", size, UNITS[unit], bytes)
+    }
+}
+
+/// 埋め込みICCプロファイルの名前（descタグ）を取得
+fn icc_profile_description(path: &Path) -> Option<String> {
+    let mut decoder = image::ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let icc = decoder.icc_profile().ok()??;
+
+    let read_u32 = |pos: usize| -> Option<u32> {
+        icc.get(pos..pos + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    // ヘッダ（128バイト）の後にタグテーブルが続く
+    let count = read_u32(128)? as usize;
+    for i in 0..count {
+        let entry = 132 + i * 12;
+        if icc.get(entry..entry + 4)? != b"desc" {
+            continue;
+        }
+        let offset = read_u32(entry + 4)? as usize;
+        let data = icc.get(offset..offset + read_u32(entry + 8)? as usize)?;
+        let description = match data.get(0..4)? {
+            // ICC v2: ASCII文字列
+            b"desc" => {
+                let len = u32::from_be_bytes(data.get(8..12)?.try_into().ok()?) as usize;
+                String::from_utf8_lossy(data.get(12..12 + len)?).to_string()
+            }
+            // ICC v4: 多言語UTF-16BE文字列（最初のレコードを使う）
+            b"mluc" => {
+                let len = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?) as usize;
+                let start = u32::from_be_bytes(data.get(24..28)?.try_into().ok()?) as usize;
+                let units: Vec<u16> = data
+                    .get(start..start + len)?
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => continue,
+        };
+        return Some(clean(&description));
+    }
+
+    Some(format!("ICC profile ({} bytes)", icc.len()))
+}
+
+/// バイト列の16進ダンプ（オフセット・16進・ASCII）
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        out.push_str(&format!("{:08X}  {:<47}  {}\n", i * 16, hex.join(" "), ascii));
+    }
+    out
+}
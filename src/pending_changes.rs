@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// タグを編集した画像から離れるときの扱い
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnsavedPolicy {
+    /// 保存するか確認する（確認できない場面では保留にする）
+    #[default]
+    Prompt,
+    /// 自動的に保存する
+    AutoSave,
+    /// 保存せずに破棄する
+    Discard,
+}
+
+impl UnsavedPolicy {
+    pub const ALL: [UnsavedPolicy; 3] = [
+        UnsavedPolicy::Prompt,
+        UnsavedPolicy::AutoSave,
+        UnsavedPolicy::Discard,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            UnsavedPolicy::Prompt => "Ask",
+            UnsavedPolicy::AutoSave => "Save automatically",
+            UnsavedPolicy::Discard => "Discard changes",
+        }
+    }
+}
+
+/// まだ保存していないタグの編集（画像ごと）
+#[derive(Default)]
+pub struct PendingChanges {
+    tags: BTreeMap<PathBuf, Vec<String>>,
+}
+
+impl PendingChanges {
+    pub fn insert(&mut self, path: PathBuf, tags: Vec<String>) {
+        self.tags.insert(path, tags);
+    }
+
+    /// 画像の保留中の編集を取り出す
+    pub fn take(&mut self, path: &Path) -> Option<Vec<String>> {
+        self.tags.remove(path)
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.tags.contains_key(path)
+    }
+
+    /// 画像の名前が変わったら付け替える
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        if let Some(tags) = self.tags.remove(from) {
+            self.tags.insert(to.to_path_buf(), tags);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.tags.keys()
+    }
+
+    /// すべて取り出す（保存に失敗したものは呼び出し側で戻す）
+    pub fn drain(&mut self) -> Vec<(PathBuf, Vec<String>)> {
+        std::mem::take(&mut self.tags).into_iter().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.tags.clear();
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::safe_write;
+use crate::sort_order::{self, DateCache, SortOrder};
+use crate::tag_cache::TagCache;
+use crate::tag_manager::is_image_file;
+use crate::tag_query::TagQuery;
+
+/// スライドショーのプレイリスト
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Playlist {
+    /// 画像パスの固定リスト
+    Static { paths: Vec<PathBuf> },
+    /// 検索条件で毎回画像を集める
+    Dynamic {
+        /// タグの検索条件（TagQuery の書式）
+        query: String,
+        /// 対象フォルダ
+        folders: Vec<PathBuf>,
+        /// サブフォルダも含めるか
+        #[serde(default)]
+        recursive: bool,
+        /// 並び順
+        #[serde(default)]
+        order: SortOrder,
+    },
+}
+
+impl Playlist {
+    /// ファイルから読み込む（.m3u/.m3u8 は固定リスト、それ以外はJSON）
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        if is_m3u(path) {
+            let base = path.parent().unwrap_or(Path::new("."));
+            let paths = content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| base.join(line))
+                .collect();
+            return Ok(Playlist::Static { paths });
+        }
+        serde_json::from_str(&content).map_err(io::Error::other)
+    }
+
+    /// ファイルに保存する（.m3u/.m3u8 は固定リストのみ）
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if is_m3u(path) {
+            let Playlist::Static { paths } = self else {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "M3U can only store static playlists",
+                ));
+            };
+            let mut content = String::from("#EXTM3U\n");
+            for p in paths {
+                content.push_str(&p.to_string_lossy());
+                content.push('\n');
+            }
+            return fs::write(path, content);
+        }
+        let content = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, content)
+    }
+
+    /// 再生する画像のリストを作る（存在しないファイルは除く）
+    pub fn resolve(&self) -> Vec<PathBuf> {
+        match self {
+            Playlist::Static { paths } => paths.iter().filter(|p| p.is_file()).cloned().collect(),
+            Playlist::Dynamic {
+                query,
+                folders,
+                recursive,
+                order,
+            } => {
+                let query = TagQuery::parse(query);
+                // タグ数順のときに読み直さないよう、絞り込みで読んだタグを使い回す
+                let mut tags = TagCache::default();
+                let mut images: Vec<PathBuf> = folders
+                    .iter()
+                    .flat_map(|dir| {
+                        let max_depth = if *recursive { usize::MAX } else { 1 };
+                        WalkDir::new(dir)
+                            .max_depth(max_depth)
+                            .into_iter()
+                            .filter_entry(|e| !safe_write::is_backup_dir(e.path()))
+                            .flatten()
+                            .map(|e| e.into_path())
+                            .filter(|p| is_image_file(p))
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|p| query.is_empty() || query.matches(tags.tags(p)))
+                    .collect();
+                // 重なったフォルダを指定した場合の重複を除く
+                images.sort();
+                images.dedup();
+                sort_order::sort_paths(&mut images, *order, &mut DateCache::default(), &mut tags);
+                images
+            }
+        }
+    }
+}
+
+fn is_m3u(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| matches!(e.to_lowercase().as_str(), "m3u" | "m3u8"))
+        .unwrap_or(false)
+}
+
+/// 出力先で名前が重複しないファイル名を決める（name.jpg → name_1.jpg ...）
+fn unique_name(path: &Path, used: &mut Vec<String>) -> String {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let ext = path.extension().and_then(|s| s.to_str());
+    let make = |n: usize| {
+        let base = if n == 0 { stem.to_string() } else { format!("{}_{}", stem, n) };
+        match ext {
+            Some(ext) => format!("{}.{}", base, ext),
+            None => base,
+        }
+    };
+    let mut n = 0;
+    while used.contains(&make(n)) {
+        n += 1;
+    }
+    let name = make(n);
+    used.push(name.clone());
+    name
+}
+
+/// 画像をフォルダにコピーする。コピーした数を返す
+pub fn export_to_folder(images: &[PathBuf], dest: &Path) -> io::Result<usize> {
+    fs::create_dir_all(dest)?;
+    let mut used: Vec<String> = fs::read_dir(dest)?
+        .flatten()
+        .filter_map(|e| e.file_name().to_str().map(str::to_string))
+        .collect();
+    for image in images {
+        let name = unique_name(image, &mut used);
+        fs::copy(image, dest.join(name))?;
+    }
+    Ok(images.len())
+}
+
+/// 画像をzipにまとめる（画像は圧縮済みなので無圧縮で格納）
+pub fn export_to_zip(images: &[PathBuf], dest: &Path) -> io::Result<usize> {
+    let mut zip = zip::ZipWriter::new(File::create(dest)?);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored);
+    let mut used = Vec::new();
+    for image in images {
+        let name = unique_name(image, &mut used);
+        zip.start_file(name, options).map_err(io::Error::other)?;
+        zip.write_all(&fs::read(image)?)?;
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(images.len())
+}
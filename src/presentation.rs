@@ -0,0 +1,290 @@
+use eframe::egui::{self, Color32, Pos2, Rect, Vec2};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::metadata_info;
+
+/// マウスを動かさずにこの時間が経ったらカーソルを隠す
+const CURSOR_HIDE_DELAY: Duration = Duration::from_secs(2);
+/// Ken Burns で最終的に拡大する倍率
+const KEN_BURNS_ZOOM: f32 = 1.15;
+
+/// スライドショーの画像切り替え効果
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transition {
+    /// 即座に切り替え
+    None,
+    /// クロスフェード
+    #[default]
+    Crossfade,
+    /// 横にスライド
+    Slide,
+    /// ゆっくりズーム・パンしながらクロスフェード
+    KenBurns,
+}
+
+impl Transition {
+    pub const ALL: [Transition; 4] = [
+        Transition::None,
+        Transition::Crossfade,
+        Transition::Slide,
+        Transition::KenBurns,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Transition::None => "None",
+            Transition::Crossfade => "Crossfade",
+            Transition::Slide => "Slide",
+            Transition::KenBurns => "Ken Burns",
+        }
+    }
+}
+
+/// キャプションに表示する項目
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptionOptions {
+    pub filename: bool,
+    pub date: bool,
+    pub tags: bool,
+}
+
+impl CaptionOptions {
+    pub fn any(&self) -> bool {
+        self.filename || self.date || self.tags
+    }
+}
+
+struct Slide {
+    path: PathBuf,
+    texture: egui::TextureHandle,
+    /// キャプション用の撮影日時
+    date: Option<String>,
+    /// Ken Burns のパン方向
+    pan_direction: Vec2,
+}
+
+/// 全画面プレゼンテーション表示の状態
+pub struct Presentation {
+    current: Option<Slide>,
+    previous: Option<Slide>,
+    /// 画像を切り替えた時刻
+    switched_at: Instant,
+    /// 最後にマウスが動いた時刻
+    last_pointer_move: Instant,
+    last_pointer_pos: Option<Pos2>,
+}
+
+impl Default for Presentation {
+    fn default() -> Self {
+        Self {
+            current: None,
+            previous: None,
+            switched_at: Instant::now(),
+            last_pointer_move: Instant::now(),
+            last_pointer_pos: None,
+        }
+    }
+}
+
+impl Presentation {
+    /// 表示する画像を設定する（画像が変わったら切り替え効果を開始）
+    pub fn set_image(&mut self, path: &Path, texture: &egui::TextureHandle, with_date: bool) {
+        if let Some(current) = &mut self.current {
+            if current.path == path {
+                // アニメーション画像はフレームごとにテクスチャが変わる
+                current.texture = texture.clone();
+                return;
+            }
+        }
+
+        // パン方向はパスから決める（同じ画像は毎回同じ動きにする）
+        let hash = path
+            .to_string_lossy()
+            .bytes()
+            .fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+        let angle = (hash % 360) as f32 * std::f32::consts::PI / 180.0;
+
+        self.previous = self.current.take();
+        self.current = Some(Slide {
+            path: path.to_path_buf(),
+            texture: texture.clone(),
+            date: if with_date { metadata_info::date_taken(path) } else { None },
+            pan_direction: Vec2::angled(angle),
+        });
+        self.switched_at = Instant::now();
+    }
+
+    /// 表示をリセットする（プレゼンテーション終了時）
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.previous = None;
+    }
+
+    /// マウスが一定時間動いていなければカーソルを隠す
+    pub fn update_cursor(&mut self, ctx: &egui::Context) {
+        let pos = ctx.input(|i| i.pointer.latest_pos());
+        if pos != self.last_pointer_pos {
+            self.last_pointer_pos = pos;
+            self.last_pointer_move = Instant::now();
+        }
+        if self.last_pointer_move.elapsed() >= CURSOR_HIDE_DELAY {
+            ctx.set_cursor_icon(egui::CursorIcon::None);
+        } else {
+            ctx.request_repaint_after(CURSOR_HIDE_DELAY);
+        }
+    }
+
+    /// 画像を描画する
+    /// display_secs は現在の画像の表示時間（Ken Burns の動きに使う）
+    pub fn paint(
+        &self,
+        painter: &egui::Painter,
+        screen: Rect,
+        transition: Transition,
+        transition_secs: f32,
+        display_secs: f32,
+    ) {
+        painter.rect_filled(screen, 0.0, Color32::BLACK);
+
+        let Some(current) = &self.current else {
+            return;
+        };
+
+        let elapsed = self.switched_at.elapsed().as_secs_f32();
+        let t = if transition_secs > 0.0 {
+            (elapsed / transition_secs).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let t = ease_in_out(t);
+        let previous = self.previous.as_ref().filter(|_| t < 1.0);
+
+        match transition {
+            Transition::None => {
+                paint_image(painter, &current.texture, fit_rect(screen, &current.texture), 1.0);
+            }
+            Transition::Crossfade => {
+                if let Some(prev) = previous {
+                    paint_image(painter, &prev.texture, fit_rect(screen, &prev.texture), 1.0 - t);
+                }
+                paint_image(painter, &current.texture, fit_rect(screen, &current.texture), t);
+            }
+            Transition::Slide => {
+                let current_rect = fit_rect(screen, &current.texture);
+                if let Some(prev) = previous {
+                    // 前の画像を左に押し出しながら右から入ってくる
+                    let shift = screen.width() * t;
+                    let prev_rect = fit_rect(screen, &prev.texture).translate(Vec2::new(-shift, 0.0));
+                    paint_image(painter, &prev.texture, prev_rect, 1.0);
+                    paint_image(
+                        painter,
+                        &current.texture,
+                        current_rect.translate(Vec2::new(screen.width() - shift, 0.0)),
+                        1.0,
+                    );
+                } else {
+                    paint_image(painter, &current.texture, current_rect, 1.0);
+                }
+            }
+            Transition::KenBurns => {
+                if let Some(prev) = previous {
+                    // 前の画像は動きの終点のまま消えていく
+                    let rect = ken_burns_rect(screen, prev, 1.0);
+                    paint_image(painter, &prev.texture, rect, 1.0 - t);
+                }
+                let progress = if display_secs > 0.0 {
+                    (elapsed / (display_secs + transition_secs)).clamp(0.0, 1.0)
+                } else {
+                    1.0
+                };
+                let rect = ken_burns_rect(screen, current, progress);
+                paint_image(painter, &current.texture, rect, t);
+            }
+        }
+    }
+
+    /// キャプションを描画する
+    pub fn paint_caption(
+        &self,
+        painter: &egui::Painter,
+        screen: Rect,
+        options: CaptionOptions,
+        tags: &[String],
+    ) {
+        let Some(current) = &self.current else {
+            return;
+        };
+        if !options.any() {
+            return;
+        }
+
+        let mut lines = Vec::new();
+        if options.filename {
+            if let Some(name) = current.path.file_name().and_then(|n| n.to_str()) {
+                lines.push(name.to_string());
+            }
+        }
+        if options.date {
+            if let Some(date) = &current.date {
+                lines.push(date.clone());
+            }
+        }
+        if options.tags && !tags.is_empty() {
+            lines.push(tags.join(", "));
+        }
+        if lines.is_empty() {
+            return;
+        }
+
+        let galley = painter.layout_no_wrap(
+            lines.join("\n"),
+            egui::FontId::proportional(20.0),
+            Color32::WHITE,
+        );
+        let padding = Vec2::new(12.0, 8.0);
+        let pos = screen.left_bottom() + Vec2::new(24.0, -24.0 - galley.size().y - padding.y * 2.0);
+        painter.rect_filled(
+            Rect::from_min_size(pos, galley.size() + padding * 2.0),
+            6.0,
+            Color32::from_rgba_unmultiplied(0, 0, 0, 160),
+        );
+        painter.galley(pos + padding, galley, Color32::WHITE);
+    }
+}
+
+fn ease_in_out(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// 画面に収まるように縦横比を保って配置した矩形
+fn fit_rect(screen: Rect, texture: &egui::TextureHandle) -> Rect {
+    let size = texture.size_vec2();
+    let scale = (screen.width() / size.x).min(screen.height() / size.y);
+    Rect::from_center_size(screen.center(), size * scale)
+}
+
+/// Ken Burns の矩形（progress 0.0 → 1.0 でズームしながらパン）
+fn ken_burns_rect(screen: Rect, slide: &Slide, progress: f32) -> Rect {
+    let base = fit_rect(screen, &slide.texture);
+    let zoom = 1.0 + (KEN_BURNS_ZOOM - 1.0) * progress;
+    let size = base.size() * zoom;
+    // 拡大した分の範囲内でパンする
+    let max_shift = (size - base.size()) * 0.5;
+    let shift = slide.pan_direction * max_shift * progress;
+    Rect::from_center_size(base.center() + shift, size)
+}
+
+fn paint_image(painter: &egui::Painter, texture: &egui::TextureHandle, rect: Rect, alpha: f32) {
+    if alpha <= 0.0 {
+        return;
+    }
+    painter.image(
+        texture.id(),
+        rect,
+        Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+        Color32::WHITE.gamma_multiply(alpha),
+    );
+}
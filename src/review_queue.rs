@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+use crate::tag_query::TagQuery;
+
+/// 条件に合う画像だけを巡回するレビューキュー
+/// タグを付けて条件に合わなくなった画像は次から飛ばされる
+pub struct ReviewQueue {
+    query: TagQuery,
+    /// 条件の説明（ステータスバー用）
+    pub label: String,
+    /// 対象数を数えたフォルダ
+    folder: Option<PathBuf>,
+    /// フォルダを開いたときの対象数
+    pub total: usize,
+    /// 残りの対象数
+    pub remaining: usize,
+}
+
+impl ReviewQueue {
+    /// タグが1つもない画像
+    pub fn untagged() -> Self {
+        Self::new(TagQuery::parse("untagged"), "Untagged".to_string())
+    }
+
+    /// 指定したタグをどれも持っていない画像
+    pub fn missing_all(tags: &[String]) -> Self {
+        let query = tags
+            .iter()
+            .map(|t| format!("-{}", TagQuery::term(t)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self::new(TagQuery::parse(&query), format!("Missing {}", tags.join(", ")))
+    }
+
+    fn new(query: TagQuery, label: String) -> Self {
+        Self {
+            query,
+            label,
+            folder: None,
+            total: 0,
+            remaining: 0,
+        }
+    }
+
+    /// タグが条件に合う（まだレビューが必要）か
+    pub fn matches(&self, tags: &[String]) -> bool {
+        self.query.matches(tags)
+    }
+
+    /// 残りの数を更新する（フォルダが変わったら対象数を数え直す）
+    pub fn update(&mut self, folder: &Path, matching: &[bool]) {
+        self.remaining = matching.iter().filter(|&&m| m).count();
+        if self.folder.as_deref() != Some(folder) {
+            self.folder = Some(folder.to_path_buf());
+            self.total = self.remaining;
+        }
+        // 外部でファイルが増えた場合
+        self.total = self.total.max(self.remaining);
+    }
+
+    /// 完了した割合
+    pub fn progress(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            (self.total - self.remaining) as f32 / self.total as f32
+        }
+    }
+}
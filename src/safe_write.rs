@@ -0,0 +1,138 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// 書き込み途中のクラッシュやディスクフルで画像を壊さないための書き込み
+// 同じフォルダの一時ファイルに書いて fsync してから置き換える
+
+/// 元の画像のバックアップを置くフォルダ名（画像と同じフォルダに作る）
+pub const BACKUP_DIR: &str = ".tag_editor_backup";
+
+/// 一時ファイルの拡張子（画像として扱われないように）
+const TEMP_EXTENSION: &str = "tag_editor-tmp";
+
+/// ファイルを原子的に書き換える（失敗しても元のファイルはそのまま）
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.{}", std::process::id(), TEMP_EXTENSION));
+    let temp = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(data)?;
+        // 元のファイルの権限を引き継ぐ
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp, path)?;
+        sync_dir(path);
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// 名前の変更をディスクに反映させる（Windowsではフォルダを開けないので何もしない）
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// ファイルの更新日時・アクセス日時（書き換えた後に戻すため）
+pub fn file_times(path: &Path) -> Option<fs::FileTimes> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(
+        fs::FileTimes::new()
+            .set_accessed(metadata.accessed().ok()?)
+            .set_modified(metadata.modified().ok()?),
+    )
+}
+
+/// 更新日時・アクセス日時を設定する
+pub fn set_file_times(path: &Path, times: fs::FileTimes) -> io::Result<()> {
+    fs::OpenOptions::new().write(true).open(path)?.set_times(times)
+}
+
+/// 画像のバックアップのパス（dir/image.jpg -> dir/.tag_editor_backup/image.jpg）
+pub fn backup_path(image_path: &Path) -> PathBuf {
+    let dir = image_path.parent().unwrap_or(Path::new(""));
+    let name = image_path.file_name().unwrap_or_default();
+    dir.join(BACKUP_DIR).join(name)
+}
+
+/// バックアップ用のフォルダか（ファイルツリーや検索から除く）
+pub fn is_backup_dir(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| n == BACKUP_DIR)
+}
+
+pub fn has_backup(image_path: &Path) -> bool {
+    backup_path(image_path).is_file()
+}
+
+/// 最初に書き換える前の画像をバックアップする（既にあれば何もしない）
+/// limit が 0 でなければ、フォルダのバックアップが limit を超えた分を古い順に消す
+pub fn backup_original(image_path: &Path, limit: usize) -> io::Result<()> {
+    let backup = backup_path(image_path);
+    if backup.is_file() || !image_path.is_file() {
+        return Ok(());
+    }
+    if let Some(dir) = backup.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_atomic(&backup, &fs::read(image_path)?)?;
+    if limit > 0 {
+        if let Some(dir) = backup.parent() {
+            prune_backups(dir, limit);
+        }
+    }
+    Ok(())
+}
+
+/// 古いバックアップを消す
+fn prune_backups(dir: &Path, limit: usize) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut backups: Vec<(SystemTime, PathBuf)> = entries
+        .flatten()
+        .filter_map(|e| {
+            let modified = e.metadata().ok()?.modified().ok()?;
+            Some((modified, e.path()))
+        })
+        .collect();
+    if backups.len() <= limit {
+        return;
+    }
+    backups.sort();
+    for (_, path) in &backups[..backups.len() - limit] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// バックアップから元の画像に戻す（戻したらバックアップは消す）
+pub fn restore_original(image_path: &Path) -> io::Result<()> {
+    let backup = backup_path(image_path);
+    let data = fs::read(&backup)?;
+    write_atomic(image_path, &data)?;
+    fs::remove_file(&backup)?;
+    // 空になったバックアップフォルダは残さない
+    if let Some(dir) = backup.parent() {
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// 最近開いたフォルダ・画像を覚えておく数
+pub const MAX_RECENT: usize = 10;
+
+/// 前回終了時の表示状態（起動時に復元する）
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    /// ファイルツリーのルート
+    pub root: Option<PathBuf>,
+    /// 表示中の画像
+    pub current_image: Option<PathBuf>,
+    /// 表示中の画像のフォルダ内での位置（画像が消えていた場合に使う）
+    pub index: usize,
+    /// 展開していたフォルダ
+    pub expanded: Vec<PathBuf>,
+    /// ファイルツリーの絞り込み条件
+    pub tree_filter: String,
+}
+
+/// 最近使ったリストの先頭に追加する（重複は取り除く）
+pub fn push_recent(list: &mut Vec<PathBuf>, path: PathBuf) {
+    list.retain(|p| p != &path);
+    list.insert(0, path);
+    list.truncate(MAX_RECENT);
+}
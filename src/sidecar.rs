@@ -0,0 +1,179 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::safe_write;
+
+// Exifを埋め込めないファイル用のXMPサイドカー（image.gif -> image.gif.xmp）
+// タグは dc:subject に格納する（他のアプリでもキーワードとして読める）
+
+const SUBJECT_OPEN: &str = "<dc:subject>";
+const SUBJECT_CLOSE: &str = "</dc:subject>";
+
+/// サイドカーファイルのパス
+pub fn sidecar_path(image_path: &Path) -> PathBuf {
+    let mut name = image_path.as_os_str().to_owned();
+    name.push(".xmp");
+    PathBuf::from(name)
+}
+
+/// サイドカーが存在するか
+pub fn has_sidecar(image_path: &Path) -> bool {
+    sidecar_path(image_path).is_file()
+}
+
+/// サイドカーからタグを読み込む
+pub fn load_tags(image_path: &Path) -> Vec<String> {
+    let Ok(content) = fs::read_to_string(sidecar_path(image_path)) else {
+        return Vec::new();
+    };
+
+    let Some(start) = content.find(SUBJECT_OPEN) else {
+        return Vec::new();
+    };
+    let Some(end) = content[start..].find(SUBJECT_CLOSE) else {
+        return Vec::new();
+    };
+    let subject = &content[start + SUBJECT_OPEN.len()..start + end];
+
+    let mut tags = Vec::new();
+    let mut rest = subject;
+    while let Some(li_start) = rest.find("<rdf:li>") {
+        rest = &rest[li_start + "<rdf:li>".len()..];
+        let Some(li_end) = rest.find("</rdf:li>") else {
+            break;
+        };
+        let tag = unescape(rest[..li_end].trim());
+        if !tag.is_empty() {
+            tags.push(tag);
+        }
+        rest = &rest[li_end..];
+    }
+    tags
+}
+
+/// サイドカーにタグを保存する
+/// 既存のXMPがあれば dc:subject 部分だけを書き換える
+pub fn save_tags(image_path: &Path, tags: &[String]) -> std::io::Result<()> {
+    let path = sidecar_path(image_path);
+    let subject = subject_block(tags);
+
+    let content = match fs::read_to_string(&path) {
+        Ok(existing) => match (existing.find(SUBJECT_OPEN), existing.find(SUBJECT_CLOSE)) {
+            (Some(start), Some(end)) if start < end => format!(
+                "{}{}{}",
+                &existing[..start],
+                subject,
+                &existing[end + SUBJECT_CLOSE.len()..]
+            ),
+            _ => new_document(&subject),
+        },
+        Err(_) => new_document(&subject),
+    };
+
+    safe_write::write_atomic(&path, content.as_bytes())
+}
+
+fn subject_block(tags: &[String]) -> String {
+    let mut s = String::from(SUBJECT_OPEN);
+    s.push_str("\n    <rdf:Bag>\n");
+    for tag in tags {
+        s.push_str(&format!("     <rdf:li>{}</rdf:li>\n", escape(tag)));
+    }
+    s.push_str("    </rdf:Bag>\n   ");
+    s.push_str(SUBJECT_CLOSE);
+    s
+}
+
+fn new_document(subject: &str) -> String {
+    format!(
+        r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+   {}
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#,
+        subject
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_image(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tag_editor_sidecar_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("image.gif")
+    }
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn round_trip_escapes_xml() {
+        let image = temp_image("escape");
+        let list = tags(&["cat", "a<b>c", "rock & roll", "say \"hi\"", "it's", "&amp;", "</rdf:li>", "日本語"]);
+        save_tags(&image, &list).unwrap();
+        assert_eq!(load_tags(&image), list);
+        assert!(has_sidecar(&image));
+
+        save_tags(&image, &[]).unwrap();
+        assert!(load_tags(&image).is_empty());
+        fs::remove_dir_all(image.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn save_keeps_other_metadata() {
+        let image = temp_image("keep");
+        fs::write(
+            sidecar_path(&image),
+            "<x:xmpmeta><dc:title>Title &amp; more</dc:title>\
+             <dc:subject><rdf:Bag><rdf:li>old</rdf:li><rdf:li>it&apos;s</rdf:li></rdf:Bag></dc:subject></x:xmpmeta>",
+        )
+        .unwrap();
+        assert_eq!(load_tags(&image), tags(&["old", "it's"]));
+
+        save_tags(&image, &tags(&["new"])).unwrap();
+        let content = fs::read_to_string(sidecar_path(&image)).unwrap();
+        assert!(content.contains("<dc:title>Title &amp; more</dc:title>"));
+        assert!(!content.contains("old"));
+        assert_eq!(load_tags(&image), tags(&["new"]));
+        fs::remove_dir_all(image.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_or_broken_sidecar_has_no_tags() {
+        let image = temp_image("broken");
+        assert!(!has_sidecar(&image));
+        assert!(load_tags(&image).is_empty());
+
+        fs::write(sidecar_path(&image), "<dc:subject><rdf:Bag><rdf:li>cut").unwrap();
+        assert!(load_tags(&image).is_empty());
+
+        // 壊れたXMPは新しい文書で置き換える
+        save_tags(&image, &tags(&["a"])).unwrap();
+        assert_eq!(load_tags(&image), tags(&["a"]));
+        fs::remove_dir_all(image.parent().unwrap()).unwrap();
+    }
+}
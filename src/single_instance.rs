@@ -0,0 +1,192 @@
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::time::Duration;
+
+// 2つ目の起動を既に開いているウィンドウに渡す（Unix ドメインソケットを使う）
+// ソケットを使えない環境では毎回新しいウィンドウを開く
+
+/// 起動中のウィンドウからの応答を待つ最大時間
+#[cfg(unix)]
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// コマンドラインで指定された操作
+///
+///   tag_editor [--add-tag TAG]... [--remove-tag TAG]... [--new-instance] [PATH]
+#[derive(Default, Serialize, Deserialize)]
+pub struct Invocation {
+    /// 開く画像かフォルダ（絶対パス）
+    pub path: Option<PathBuf>,
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    /// 起動中のウィンドウがあっても新しいウィンドウを開く
+    #[serde(skip)]
+    pub new_instance: bool,
+}
+
+impl Invocation {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut invocation = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut option = |name: &str| -> Option<String> {
+                if arg == name {
+                    args.next()
+                } else {
+                    arg.strip_prefix(name)?.strip_prefix('=').map(str::to_string)
+                }
+            };
+            if let Some(tag) = option("--add-tag") {
+                invocation.add_tags.push(tag);
+            } else if let Some(tag) = option("--remove-tag") {
+                invocation.remove_tags.push(tag);
+            } else if arg == "--new-instance" {
+                invocation.new_instance = true;
+            } else if invocation.path.is_none() && !arg.starts_with("--") {
+                // 起動中のウィンドウとは作業フォルダが違うので絶対パスにする
+                let path = PathBuf::from(&arg);
+                if path.exists() {
+                    invocation.path = Some(std::fs::canonicalize(&path).unwrap_or(path));
+                }
+            }
+        }
+        invocation
+    }
+}
+
+/// ソケットの場所（本人専用のディレクトリを用意できなければ None）
+///
+/// 共有の一時ディレクトリに直接置くと、他のユーザーが先に同じ名前のソケットを作って
+/// 操作を横取りできるので、本人だけが使える（0700 の）ディレクトリの中に置く
+#[cfg(unix)]
+fn socket_path() -> Option<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    // SAFETY: getuid は常に成功し、副作用もない
+    let uid = unsafe { libc::getuid() };
+    let dir = match dirs::runtime_dir() {
+        Some(dir) => dir,
+        None => {
+            let dir = std::env::temp_dir().join(format!("tag_editor-{}", uid));
+            let _ = std::fs::DirBuilder::new().mode(0o700).create(&dir);
+            dir
+        }
+    };
+    // 他のユーザーが作ったディレクトリや、他のユーザーも入れるディレクトリは使わない
+    let meta = std::fs::symlink_metadata(&dir).ok()?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return None;
+    }
+    Some(dir.join("tag_editor.sock"))
+}
+
+/// 起動中のウィンドウに操作を渡す（渡せたら true、渡せなければ新しいウィンドウを開く）
+pub fn forward(invocation: &Invocation) -> bool {
+    #[cfg(unix)]
+    {
+        let send = || -> std::io::Result<bool> {
+            let path = socket_path().ok_or(std::io::ErrorKind::PermissionDenied)?;
+            let mut stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(FORWARD_TIMEOUT))?;
+            stream.set_write_timeout(Some(FORWARD_TIMEOUT))?;
+            let message = serde_json::to_string(invocation).map_err(std::io::Error::other)?;
+            writeln!(stream, "{}", message)?;
+            let mut reply = String::new();
+            BufReader::new(&stream).read_line(&mut reply)?;
+            Ok(reply.trim() == "ok")
+        };
+        send().unwrap_or(false)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = invocation;
+        false
+    }
+}
+
+/// 2つ目の起動を受け付けるソケット（ウィンドウを作る前に確保しておく）
+pub struct InstanceSocket {
+    path: PathBuf,
+    #[cfg(unix)]
+    listener: UnixListener,
+}
+
+impl InstanceSocket {
+    /// ソケットを作る（前回異常終了して残ったソケットは作り直す）
+    pub fn bind() -> Option<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let path = socket_path()?;
+            if path.exists() && UnixStream::connect(&path).is_err() {
+                let _ = std::fs::remove_file(&path);
+            }
+            let listener = UnixListener::bind(&path).ok()?;
+            // 他のユーザーからは接続させない
+            let _ = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600));
+            Some(Self { path, listener })
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    /// 受け付けを始める
+    pub fn listen(self, ctx: &egui::Context) -> InstanceListener {
+        let (tx, rx) = std::sync::mpsc::channel();
+        #[cfg(unix)]
+        {
+            let ctx = ctx.clone();
+            let listener = self.listener;
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let _ = stream.set_read_timeout(Some(FORWARD_TIMEOUT));
+                    let mut line = String::new();
+                    if BufReader::new(&stream).read_line(&mut line).is_err() {
+                        continue;
+                    }
+                    let Ok(invocation) = serde_json::from_str::<Invocation>(&line) else {
+                        continue;
+                    };
+                    if tx.send(invocation).is_err() {
+                        break;
+                    }
+                    ctx.request_repaint();
+                    let _ = (&stream).write_all(b"ok\n");
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (ctx, tx);
+        }
+        InstanceListener { path: self.path, rx }
+    }
+}
+
+/// 2つ目の起動から渡された操作（破棄するとソケットを消す）
+pub struct InstanceListener {
+    path: PathBuf,
+    rx: Receiver<Invocation>,
+}
+
+impl InstanceListener {
+    pub fn poll(&self) -> Vec<Invocation> {
+        self.rx.try_iter().collect()
+    }
+}
+
+impl Drop for InstanceListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -1,15 +1,33 @@
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+use crate::sort_order::{self, SplitMix64};
+use crate::tag_manager;
+
+/// 表示時間を指定するタグの接頭辞（例: "duration:5", "duration:2.5s"）
+const DURATION_TAG_PREFIX: &str = "duration:";
 
 pub struct Slideshow {
     /// スライドショーが実行中かどうか
     pub is_running: bool,
+    /// 一時停止中かどうか
+    pub is_paused: bool,
     /// スライドショー対象の画像リスト
     pub images: Vec<PathBuf>,
-    /// 現在のインデックス
+    /// 再生順（images のインデックス）。シャッフル時は並びが入れ替わる
+    order: Vec<usize>,
+    /// 再生順での現在位置
     pub current_index: usize,
+    /// 画像ごとの表示時間（duration: タグで指定されたもの）
+    durations: Vec<Option<f32>>,
     /// 最後に画像を切り替えた時刻
     last_switch: Instant,
+    /// 一時停止した時点での経過時間
+    paused_elapsed: Duration,
+    /// シャッフル再生か
+    shuffle: bool,
+    /// シャッフル用の乱数
+    rng: SplitMix64,
     /// 1巡目が完了したか
     pub completed_once: bool,
 }
@@ -18,52 +36,170 @@ impl Default for Slideshow {
     fn default() -> Self {
         Self {
             is_running: false,
+            is_paused: false,
             images: Vec::new(),
+            order: Vec::new(),
             current_index: 0,
+            durations: Vec::new(),
             last_switch: Instant::now(),
+            paused_elapsed: Duration::ZERO,
+            shuffle: false,
+            rng: SplitMix64(0),
             completed_once: false,
         }
     }
 }
 
+/// タグから表示時間（秒）を取得
+pub fn duration_from_tags(tags: &[String]) -> Option<f32> {
+    tags.iter().find_map(|tag| {
+        let value = tag.strip_prefix(DURATION_TAG_PREFIX)?.trim();
+        let value = value.strip_suffix('s').unwrap_or(value).trim();
+        value.parse::<f32>().ok().filter(|d| *d > 0.0)
+    })
+}
+
 impl Slideshow {
     /// スライドショーを開始
-    pub fn start(&mut self, images: Vec<PathBuf>) {
+    pub fn start(&mut self, images: Vec<PathBuf>, shuffle: bool) {
+        self.durations = images
+            .iter()
+            .map(|p| duration_from_tags(&tag_manager::load_tags(p)))
+            .collect();
         self.images = images;
+        self.shuffle = shuffle;
+        self.rng = SplitMix64(sort_order::new_seed());
+        self.order = (0..self.images.len()).collect();
+        if shuffle {
+            self.reshuffle(None);
+        }
         self.current_index = 0;
         self.is_running = !self.images.is_empty();
+        self.is_paused = false;
         self.last_switch = Instant::now();
         self.completed_once = false;
     }
 
+    /// 再生順をシャッフルする
+    /// 1巡の中では同じ画像を繰り返さず、巡の境目でも直前の画像が続かないようにする
+    fn reshuffle(&mut self, previous: Option<usize>) {
+        let seed = self.rng.next_u64();
+        sort_order::shuffle(&mut self.order, seed);
+        if self.order.len() > 1 && self.order.first().copied() == previous {
+            let last = self.order.len() - 1;
+            self.order.swap(0, last);
+        }
+    }
+
     /// スライドショーを停止
     pub fn stop(&mut self) {
         self.is_running = false;
+        self.is_paused = false;
+    }
+
+    /// 一時停止/再開（表示中の画像の経過時間は保持する）
+    pub fn toggle_pause(&mut self) {
+        if !self.is_running {
+            return;
+        }
+        if self.is_paused {
+            self.last_switch = Instant::now() - self.paused_elapsed;
+            self.is_paused = false;
+        } else {
+            self.paused_elapsed = self.last_switch.elapsed();
+            self.is_paused = true;
+        }
+    }
+
+    /// 現在の画像の表示時間（秒）
+    pub fn current_duration(&self, interval: f32) -> f32 {
+        self.order
+            .get(self.current_index)
+            .and_then(|&i| self.durations.get(i).copied().flatten())
+            .unwrap_or(interval)
+    }
+
+    /// 現在の画像の経過時間（秒）
+    pub fn elapsed(&self) -> f32 {
+        if self.is_paused {
+            self.paused_elapsed.as_secs_f32()
+        } else {
+            self.last_switch.elapsed().as_secs_f32()
+        }
+    }
+
+    /// タイマーをリセットする（一時停止中なら経過時間を0にする）
+    fn reset_timer(&mut self) {
+        self.last_switch = Instant::now();
+        self.paused_elapsed = Duration::ZERO;
+    }
+
+    /// 次の画像に進む。最後まで来たらループ設定に従う
+    fn advance(&mut self, should_loop: bool) -> Option<PathBuf> {
+        self.current_index += 1;
+        if self.current_index >= self.order.len() {
+            self.completed_once = true;
+            if should_loop {
+                if self.shuffle {
+                    let previous = self.order.last().copied();
+                    self.reshuffle(previous);
+                }
+                self.current_index = 0;
+            } else {
+                self.current_index = self.order.len() - 1;
+                self.is_running = false;
+                return None;
+            }
+        }
+        self.current_image().cloned()
+    }
+
+    /// 手動で次の画像へ（タイマーはリセット）
+    pub fn next(&mut self, should_loop: bool) -> Option<PathBuf> {
+        if !self.is_running {
+            return None;
+        }
+        self.reset_timer();
+        self.advance(should_loop)
+    }
+
+    /// 手動で前の画像へ（タイマーはリセット）。先頭ではループしないなら先頭に留まる
+    pub fn prev(&mut self, should_loop: bool) -> Option<PathBuf> {
+        if !self.is_running || self.order.is_empty() {
+            return None;
+        }
+        self.reset_timer();
+        self.current_index = match self.current_index {
+            0 if should_loop => self.order.len() - 1,
+            0 => return None,
+            i => i - 1,
+        };
+        self.current_image().cloned()
+    }
+
+    /// 再生順の指定位置にジャンプ（タイマーはリセット）
+    pub fn goto(&mut self, index: usize) -> Option<PathBuf> {
+        if index >= self.order.len() {
+            return None;
+        }
+        self.reset_timer();
+        self.current_index = index;
+        self.current_image().cloned()
     }
 
     /// 更新処理（intervalは秒単位、loopは繰り返すかどうか）
+    /// duration: タグのある画像はその秒数を優先する
+    /// holdがtrueの間は時間が経過しても切り替えない（アニメーション再生待ちなど）
     /// 次の画像のパスを返す場合がある
-    pub fn update(&mut self, interval: f32, should_loop: bool) -> Option<PathBuf> {
-        if !self.is_running || self.images.is_empty() {
+    pub fn update(&mut self, interval: f32, should_loop: bool, hold: bool) -> Option<PathBuf> {
+        if !self.is_running || self.is_paused || self.images.is_empty() {
             return None;
         }
 
         let elapsed = self.last_switch.elapsed().as_secs_f32();
-        if elapsed >= interval {
+        if elapsed >= self.current_duration(interval) && !hold {
             self.last_switch = Instant::now();
-            self.current_index += 1;
-
-            if self.current_index >= self.images.len() {
-                self.completed_once = true;
-                if should_loop {
-                    self.current_index = 0;
-                } else {
-                    self.is_running = false;
-                    return None;
-                }
-            }
-
-            return self.images.get(self.current_index).cloned();
+            return self.advance(should_loop);
         }
 
         None
@@ -71,6 +207,13 @@ impl Slideshow {
 
     /// 現在の画像パスを取得
     pub fn current_image(&self) -> Option<&PathBuf> {
-        self.images.get(self.current_index)
+        self.order
+            .get(self.current_index)
+            .and_then(|&i| self.images.get(i))
+    }
+
+    /// 画像の総数
+    pub fn total_images(&self) -> usize {
+        self.order.len()
     }
 }
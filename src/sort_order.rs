@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::metadata_info;
+use crate::tag_cache::TagCache;
+
+/// 画像リストの並び順
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    /// 数字を数値として比較する名前順（img2 < img10）
+    #[default]
+    Natural,
+    /// 更新日時順
+    Modified,
+    /// Exifの撮影日時順（なければ更新日時）
+    DateTaken,
+    /// ファイルサイズ順
+    FileSize,
+    /// タグ数順
+    TagCount,
+    /// シード付きシャッフル
+    Random { seed: u64 },
+}
+
+impl SortOrder {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortOrder::Natural => "Name (natural)",
+            SortOrder::Modified => "Modified time",
+            SortOrder::DateTaken => "Date taken",
+            SortOrder::FileSize => "File size",
+            SortOrder::TagCount => "Tag count",
+            SortOrder::Random { .. } => "Random",
+        }
+    }
+}
+
+/// フォルダごとの並び順設定
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SortSettings {
+    /// 個別指定がないフォルダの並び順
+    pub default: SortOrder,
+    /// フォルダごとの並び順
+    pub per_folder: HashMap<PathBuf, SortOrder>,
+}
+
+impl SortSettings {
+    /// フォルダに適用する並び順
+    pub fn order_for(&self, dir: &Path) -> SortOrder {
+        self.per_folder.get(dir).copied().unwrap_or(self.default)
+    }
+}
+
+/// 撮影日時の並べ替えキー（更新日時が変わるまで読み直さない）
+#[derive(Default)]
+pub struct DateCache {
+    keys: HashMap<PathBuf, (Option<SystemTime>, String)>,
+}
+
+impl DateCache {
+    /// 撮影日時（なければ更新日時）をExifと同じ書式で返す
+    fn key(&mut self, path: &Path) -> String {
+        let modified = modified_time(path);
+        if let Some((cached, key)) = self.keys.get(path) {
+            if *cached == modified {
+                return key.clone();
+            }
+        }
+        let key = metadata_info::date_taken(path)
+            .or_else(|| modified.map(format_system_time))
+            .unwrap_or_default();
+        self.keys.insert(path.to_path_buf(), (modified, key.clone()));
+        key
+    }
+
+    /// paths 以外の画像のキーを捨てる
+    pub fn retain(&mut self, paths: &[PathBuf]) {
+        let keep: HashSet<&PathBuf> = paths.iter().collect();
+        self.keys.retain(|path, _| keep.contains(path));
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+}
+
+/// 並べ替えの設定と、並べ替えに使う値の読み込み先
+pub struct SortContext<'a> {
+    pub settings: &'a SortSettings,
+    pub dates: &'a mut DateCache,
+    pub tags: &'a mut TagCache,
+}
+
+impl SortContext<'_> {
+    /// フォルダの設定に従って並べ替える
+    pub fn sort(&mut self, dir: &Path, paths: &mut [PathBuf]) {
+        sort_paths(paths, self.settings.order_for(dir), self.dates, self.tags);
+    }
+}
+
+/// パスのリストを並び替える（タグ数は tags に読み込み済みのものを使う）
+pub fn sort_paths(paths: &mut [PathBuf], order: SortOrder, dates: &mut DateCache, tags: &mut TagCache) {
+    // 先に名前順にしておく（sort_by_cached_key は安定ソートなので同順位は名前順になる）
+    paths.sort_by(|a, b| natural_cmp(&file_name(a), &file_name(b)));
+
+    match order {
+        SortOrder::Natural => {}
+        SortOrder::Modified => {
+            paths.sort_by_cached_key(|p| modified_time(p));
+        }
+        SortOrder::DateTaken => {
+            paths.sort_by_cached_key(|p| dates.key(p));
+        }
+        SortOrder::FileSize => {
+            paths.sort_by_cached_key(|p| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0));
+        }
+        SortOrder::TagCount => {
+            paths.sort_by_cached_key(|p| tags.tags(p).len());
+        }
+        SortOrder::Random { seed } => shuffle(paths, seed),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// SystemTimeをExifの日時書式（YYYY:MM:DD HH:MM:SS, ローカル時刻）に変換
+pub fn format_system_time(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(time)
+        .format("%Y:%m:%d %H:%M:%S")
+        .to_string()
+}
+
+/// 数字の並びを数値として比較する（自然順）
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let mut na = String::new();
+                while let Some(c) = a_chars.peek().copied().filter(|c| c.is_ascii_digit()) {
+                    na.push(c);
+                    a_chars.next();
+                }
+                let mut nb = String::new();
+                while let Some(c) = b_chars.peek().copied().filter(|c| c.is_ascii_digit()) {
+                    nb.push(c);
+                    b_chars.next();
+                }
+                // 先頭の0を除いた桁数 → 値 → 0の数の順で比較
+                let ta = na.trim_start_matches('0');
+                let tb = nb.trim_start_matches('0');
+                let ord = ta
+                    .len()
+                    .cmp(&tb.len())
+                    .then_with(|| ta.cmp(tb))
+                    .then_with(|| na.len().cmp(&nb.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(ca), Some(cb)) => {
+                let ord = ca.cmp(&cb);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+/// シードから決定的にシャッフルする（Fisher-Yates + SplitMix64）
+pub fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut rng = SplitMix64(seed);
+    for i in (1..items.len()).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// 依存を増やさないための簡易乱数生成器
+pub struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// 現在時刻から適当なシードを作る
+pub fn new_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::tag_manager::{self, is_image_file};
+
+/// フォルダ内の画像の集計
+#[derive(Clone, Copy, Default)]
+pub struct FolderCounts {
+    /// 画像の数
+    pub images: usize,
+    /// タグが1つもない画像の数
+    pub untagged: usize,
+    /// 指定したタグを持つ画像の数
+    pub with_tag: usize,
+}
+
+/// ファイルツリーの表示用にタグを覚えておく（毎フレーム読み込まないため）
+#[derive(Default)]
+pub struct TagCache {
+    tags: HashMap<PathBuf, Vec<String>>,
+    /// フォルダ直下の画像
+    folders: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl TagCache {
+    /// 画像のタグ（未読み込みなら読み込む）
+    pub fn tags(&mut self, path: &Path) -> &[String] {
+        cached_tags(&mut self.tags, path)
+    }
+
+    /// 読み込み済みならそのタグ（読み込みはしない）
+    pub fn cached(&self, path: &Path) -> Option<&[String]> {
+        self.tags.get(path).map(Vec::as_slice)
+    }
+
+    /// 保存したタグで更新する
+    pub fn set(&mut self, path: &Path, tags: &[String]) {
+        self.tags.insert(path.to_path_buf(), tags.to_vec());
+    }
+
+    /// 読み込み済みのタグの写し（バックグラウンドの集計に渡す）
+    pub fn snapshot(&self) -> HashMap<PathBuf, Vec<String>> {
+        self.tags.clone()
+    }
+
+    /// 画像のタグを読み直させる
+    pub fn invalidate(&mut self, path: &Path) {
+        self.tags.remove(path);
+    }
+
+    /// フォルダの画像一覧を読み直させる
+    pub fn invalidate_dir(&mut self, dir: &Path) {
+        self.folders.remove(dir);
+    }
+
+    /// フォルダ直下の画像を集計する（tag が空なら with_tag は 0）
+    pub fn folder_counts(&mut self, dir: &Path, tag: &str) -> FolderCounts {
+        let images = self.folders.entry(dir.to_path_buf()).or_insert_with(|| {
+            std::fs::read_dir(dir)
+                .map(|entries| {
+                    entries
+                        .flatten()
+                        .map(|e| e.path())
+                        .filter(|p| is_image_file(p))
+                        .collect()
+                })
+                .unwrap_or_default()
+        });
+
+        let mut counts = FolderCounts {
+            images: images.len(),
+            ..Default::default()
+        };
+        for path in images.iter() {
+            let tags = cached_tags(&mut self.tags, path);
+            if tags.is_empty() {
+                counts.untagged += 1;
+            }
+            if !tag.is_empty() && tags.iter().any(|t| t == tag) {
+                counts.with_tag += 1;
+            }
+        }
+        counts
+    }
+}
+
+fn cached_tags<'a>(tags: &'a mut HashMap<PathBuf, Vec<String>>, path: &Path) -> &'a [String] {
+    if !tags.contains_key(path) {
+        tags.insert(path.to_path_buf(), tag_manager::load_tags(path));
+    }
+    &tags[path]
+}
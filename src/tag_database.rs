@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::safe_write;
+use crate::tag_manager;
+use crate::write_check;
+use crate::write_queue::{self, WriteOptions};
+
+// ライブラリモード：画像ファイルを書き換えず、設定フォルダのデータベースにタグを保存する
+// キーは画像データのハッシュなので、ファイルを移動・リネームしてもタグが付いてくる
+
+/// 有効な間だけ読み込んでおく（無効なら None）
+static DATABASE: Mutex<Option<TagDatabase>> = Mutex::new(None);
+
+/// 続けての編集で毎回ファイル全体を書き直さないよう、保存の間隔をこれだけ空ける
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 1つの画像のタグ
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Entry {
+    tags: Vec<String>,
+    /// 最後に見たときのパス（確認用）
+    path: PathBuf,
+}
+
+/// 計算済みのハッシュ
+#[derive(Clone, Serialize, Deserialize)]
+struct HashEntry {
+    modified: SystemTime,
+    len: u64,
+    key: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct TagDatabase {
+    /// 画像データのハッシュ（16進数）ごとのタグ
+    entries: HashMap<String, Entry>,
+    /// 計算済みのハッシュ（更新日時とサイズが変わっていなければ使い回す）
+    /// 起動のたびにすべての画像を読み直さないよう一緒に保存する
+    #[serde(default)]
+    hashes: HashMap<PathBuf, HashEntry>,
+    /// 保存していない変更があるか
+    #[serde(skip)]
+    dirty: bool,
+    #[serde(skip)]
+    last_saved: Option<Instant>,
+}
+
+/// 同期の結果
+#[derive(Default)]
+pub struct SyncReport {
+    /// タグが変わった画像の数
+    pub changed: usize,
+    /// 読み書きできなかった画像の数
+    pub failed: usize,
+}
+
+/// データベースファイルのパス
+pub fn database_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("tag_editor")
+        .join("tag_database.json")
+}
+
+fn lock() -> MutexGuard<'static, Option<TagDatabase>> {
+    DATABASE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 有効なデータベースに対して処理する（ロックは f の間だけ持つ）
+fn with_database<T>(f: impl FnOnce(&mut TagDatabase) -> T) -> io::Result<T> {
+    let mut database = lock();
+    let db = database
+        .as_mut()
+        .ok_or_else(|| io::Error::other("Library mode is off"))?;
+    Ok(f(db))
+}
+
+pub fn is_enabled() -> bool {
+    lock().is_some()
+}
+
+/// ライブラリモードを切り替える（有効にしたときにデータベースを読み込む）
+pub fn set_enabled(enabled: bool) {
+    let mut database = lock();
+    if !enabled {
+        // 保存を待っている変更を書いてから閉じる
+        if let Some(db) = database.as_mut() {
+            let _ = db.save();
+        }
+        *database = None;
+    } else if database.is_none() {
+        let mut loaded: TagDatabase = fs::read_to_string(database_path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        // 削除・リネームされた画像のハッシュは持ち続けない
+        let count = loaded.hashes.len();
+        loaded.hashes.retain(|path, _| path.is_file());
+        loaded.dirty = loaded.hashes.len() != count;
+        *database = Some(loaded);
+    }
+}
+
+impl TagDatabase {
+    /// 画像のキー
+    fn key(&mut self, path: &Path) -> io::Result<String> {
+        let metadata = fs::metadata(path)?;
+        let (modified, len) = (metadata.modified()?, metadata.len());
+        if let Some(entry) = self.hashes.get(path) {
+            if entry.modified == modified && entry.len == len {
+                return Ok(entry.key.clone());
+            }
+        }
+        let key = format!("{:016x}", write_check::stable_hash(path)?);
+        self.hashes.insert(
+            path.to_path_buf(),
+            HashEntry {
+                modified,
+                len,
+                key: key.clone(),
+            },
+        );
+        self.dirty = true;
+        Ok(key)
+    }
+
+    fn tags(&mut self, path: &Path) -> Vec<String> {
+        match self.key(path) {
+            Ok(key) => self.entries.get(&key).map(|e| e.tags.clone()).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// タグを設定する（変わったら true）
+    fn set(&mut self, path: &Path, tags: &[String]) -> io::Result<bool> {
+        let key = self.key(path)?;
+        let old = self.entries.get(&key).map(|e| e.tags.as_slice()).unwrap_or_default();
+        if old == tags {
+            return Ok(false);
+        }
+        self.dirty = true;
+        if tags.is_empty() {
+            self.entries.remove(&key);
+        } else {
+            self.entries.insert(
+                key,
+                Entry {
+                    tags: tags.to_vec(),
+                    path: path.to_path_buf(),
+                },
+            );
+        }
+        Ok(true)
+    }
+
+    /// 変更があれば保存する
+    fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let path = database_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // 失敗しても間を空けてやり直す
+        self.last_saved = Some(Instant::now());
+        // 大きくなるので整形せずに書く
+        let content = serde_json::to_string(self).map_err(io::Error::other)?;
+        safe_write::write_atomic(&path, content.as_bytes())?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// 前回の保存から SAVE_INTERVAL 経っていれば保存する
+    fn save_if_due(&mut self) -> io::Result<()> {
+        if self.last_saved.is_some_and(|t| t.elapsed() < SAVE_INTERVAL) {
+            return Ok(());
+        }
+        self.save()
+    }
+}
+
+/// データベースからタグを読み込む
+pub fn load_tags(path: &Path) -> Vec<String> {
+    lock().as_mut().map(|db| db.tags(path)).unwrap_or_default()
+}
+
+/// データベースにタグを保存する（続けての保存はまとめて、後で save_pending が書く）
+pub fn save_tags(path: &Path, tags: &[String]) -> io::Result<()> {
+    with_database(|db| {
+        db.set(path, tags)?;
+        db.save_if_due()
+    })?
+}
+
+/// 画像の移動・リネームに合わせて計算済みのハッシュを付け替える
+pub fn rename(from: &Path, to: &Path) {
+    if let Some(db) = lock().as_mut() {
+        if let Some(entry) = db.hashes.remove(from) {
+            db.hashes.insert(to.to_path_buf(), entry);
+            db.dirty = true;
+        }
+    }
+}
+
+/// まだ保存していない変更があるか
+pub fn has_unsaved() -> bool {
+    lock().as_ref().is_some_and(|db| db.dirty)
+}
+
+/// まだ保存していない変更を保存する（now が false なら前回の保存から間が空いたときだけ）
+pub fn save_pending(now: bool) -> io::Result<()> {
+    match lock().as_mut() {
+        Some(db) if now => db.save(),
+        Some(db) => db.save_if_due(),
+        None => Ok(()),
+    }
+}
+
+/// 画像に埋め込まれたタグをデータベースに取り込む（既にあるタグには追加する）
+/// 画像の読み込み中は他のスレッドが待たないよう、1枚ごとにロックを取り直す
+pub fn pull(paths: &[PathBuf]) -> io::Result<SyncReport> {
+    let mut report = SyncReport::default();
+    for path in paths {
+        let embedded = tag_manager::load_embedded_tags(path);
+        if embedded.is_empty() {
+            continue;
+        }
+        let changed = with_database(|db| {
+            let mut tags = db.tags(path);
+            for tag in &embedded {
+                tag_manager::add_tag(&mut tags, tag);
+            }
+            db.set(path, &tags)
+        })?;
+        match changed {
+            Ok(true) => report.changed += 1,
+            Ok(false) => {}
+            Err(_) => report.failed += 1,
+        }
+    }
+    with_database(|db| db.save())??;
+    Ok(report)
+}
+
+/// データベースのタグを画像に埋め込む（埋め込み済みのタグと同じなら書き込まない）
+/// 通常の保存と同じく options に従ってバックアップ・日時の維持・確認を行う
+/// 書き込み中はロックを離し、書き込み後にキーを付け替えるときだけ取り直す
+pub fn push(paths: &[PathBuf], options: WriteOptions) -> io::Result<SyncReport> {
+    let mut report = SyncReport::default();
+    for path in paths {
+        let (tags, old_key) = with_database(|db| (db.tags(path), db.key(path)))?;
+        if tags.is_empty() || tag_manager::load_embedded_tags(path) == tags {
+            continue;
+        }
+        let Ok(old_key) = old_key else {
+            report.failed += 1;
+            continue;
+        };
+        // 書き込めたが確認で問題が見つかった場合はログに記録される
+        if write_queue::write_embedded_tags(path, &tags, options).is_err() {
+            report.failed += 1;
+            continue;
+        }
+        report.changed += 1;
+        // 画像データのハッシュを使えない形式はキーが変わるので付け替える
+        with_database(|db| {
+            if let Ok(new_key) = db.key(path) {
+                if new_key != old_key {
+                    if let Some(entry) = db.entries.remove(&old_key) {
+                        db.entries.insert(new_key, entry);
+                    }
+                }
+            }
+        })?;
+    }
+    with_database(|db| db.save())??;
+    Ok(report)
+}
@@ -1,13 +1,39 @@
 use little_exif::metadata::Metadata;
 use little_exif::exif_tag::ExifTag;
-use std::collections::HashSet;
+use little_exif::filetype;
 use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 
-/// タグを読み込む (Exif UserCommentから)
+use crate::safe_write;
+use crate::sidecar;
+use crate::tag_database;
+use crate::write_check;
+
+/// タグを読み込む（ライブラリモードならデータベースから）
 pub fn load_tags(image_path: &Path) -> Vec<String> {
+    if tag_database::is_enabled() {
+        return tag_database::load_tags(image_path);
+    }
+    load_embedded_tags(image_path)
+}
+
+/// タグを保存する（ライブラリモードならデータベースへ。画像は書き換えない）
+pub fn save_tags(image_path: &Path, tags: &[String]) -> std::io::Result<()> {
+    if tag_database::is_enabled() {
+        return tag_database::save_tags(image_path, tags);
+    }
+    save_embedded_tags(image_path, tags)
+}
+
+/// 画像に埋め込まれたタグを読み込む (Exif UserCommentから)
+/// Exifを埋め込めずサイドカーに退避したファイルはXMPサイドカーから読み込む
+pub fn load_embedded_tags(image_path: &Path) -> Vec<String> {
     if !is_supported_format(image_path) {
         return Vec::new();
     }
+    if uses_sidecar_fallback(image_path) && sidecar::has_sidecar(image_path) {
+        return sidecar::load_tags(image_path);
+    }
 
     // メタデータ読み込み
     if let Ok(metadata) = Metadata::new_from_path(image_path) {
@@ -16,44 +42,44 @@ pub fn load_tags(image_path: &Path) -> Vec<String> {
         // バージョンによって異なるが、一般的にTag Variantを渡す
         
         // UserComment (0x9286)
-        if let Some(tag) = metadata.get_tag(&ExifTag::UserComment(Vec::new())).next() {
-            if let ExifTag::UserComment(data) = tag {
-                // データの先頭に文字コード識別子がある場合とない場合がある
-                // ASCII\0\0\0 または UNICODE\0 など
-                // ここでは単純にUTF8文字列としてパースを試みる
-                
-                let s = String::from_utf8_lossy(data);
-                let content = s.trim();
-                
-                // "ASCII\0\0\0" などを除去
-                let clean_content = if content.starts_with("ASCII") {
-                    &content[8..] 
-                } else if content.starts_with("UNICODE") {
-                     &content[8..]
-                } else {
-                    content
-                };
-                
-                // ヌル文字が含まれている場合があるので除去
-                let clean_content = clean_content.trim_matches(char::from(0));
-
-                return clean_content
-                    .split(';')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect();
-            }
+        if let Some(ExifTag::UserComment(data)) = metadata.get_tag(&ExifTag::UserComment(Vec::new())).next() {
+            // データの先頭に文字コード識別子がある場合とない場合がある
+            // ASCII\0\0\0 または UNICODE\0 など
+            // ここでは単純にUTF8文字列としてパースを試みる
+            
+            let s = String::from_utf8_lossy(data);
+            let content = s.trim();
+            
+            // "ASCII\0\0\0" などを除去
+            let clean_content = if content.starts_with("ASCII") || content.starts_with("UNICODE") {
+                &content[8..]
+            } else {
+                content
+            };
+            
+            // ヌル文字が含まれている場合があるので除去
+            let clean_content = clean_content.trim_matches(char::from(0));
+
+            return clean_content
+                .split(';')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
         }
     }
     
     Vec::new()
 }
 
-/// タグを保存する (Exif UserCommentへ)
-pub fn save_tags(image_path: &Path, tags: &[String]) -> std::io::Result<()> {
+/// タグを画像に埋め込む (Exif UserCommentへ)
+pub fn save_embedded_tags(image_path: &Path, tags: &[String]) -> std::io::Result<()> {
     if !is_supported_format(image_path) {
         return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Unsupported format"));
     }
+    // 一度サイドカーに退避したファイルは以後もサイドカーを更新する
+    if uses_sidecar_fallback(image_path) && sidecar::has_sidecar(image_path) {
+        return sidecar::save_tags(image_path, tags);
+    }
 
     // 既存のメタデータを読み込むか、新規作成
     let mut metadata = Metadata::new_from_path(image_path).unwrap_or_else(|_| Metadata::new());
@@ -71,8 +97,34 @@ pub fn save_tags(image_path: &Path, tags: &[String]) -> std::io::Result<()> {
     metadata.set_tag(ExifTag::UserComment(content.into_bytes()));
 
     // ファイルに書き込む
-    // little_exifのwrite_to_fileは既存ファイルを上書き保存する
-    metadata.write_to_file(image_path)
+    // little_exifのwrite_to_fileは元のファイルをその場で書き換えるので、
+    // メモリ上で書き換えてから一時ファイル経由で置き換える
+    match write_metadata(image_path, &metadata) {
+        // HEIF/JXLはファイル構造によっては埋め込めないのでサイドカーに退避
+        // 埋め込めなかった理由は後から調べられるようログに残す
+        Err(e) if uses_sidecar_fallback(image_path) => {
+            write_check::log_failure(image_path, &format!("Could not embed tags, using XMP sidecar: {}", e));
+            sidecar::save_tags(image_path, tags)
+        }
+        result => result,
+    }
+}
+
+fn write_metadata(image_path: &Path, metadata: &Metadata) -> std::io::Result<()> {
+    let file_type = filetype::get_file_type(image_path)?;
+    let mut buffer = std::fs::read(image_path)?;
+    metadata.write_to_vec(&mut buffer, file_type)?;
+    safe_write::write_atomic(image_path, &buffer)
+}
+
+/// タグを画像に埋め込むか（サイドカーやデータベースに保存する場合は false）
+pub fn embeds_tags(image_path: &Path) -> bool {
+    !tag_database::is_enabled() && embeds_into_image(image_path)
+}
+
+/// save_embedded_tags が画像ファイル自体を書き換えるか（サイドカーに書く場合は false）
+pub fn embeds_into_image(image_path: &Path) -> bool {
+    is_supported_format(image_path) && !(uses_sidecar_fallback(image_path) && sidecar::has_sidecar(image_path))
 }
 
 /// タグの追加
@@ -100,60 +152,65 @@ pub fn toggle_tag(tags: &mut Vec<String>, tag: &str) -> bool {
     }
 }
 
-/// ディレクトリ内の全画像からタグを収集
-pub fn collect_all_tags(dir: &Path) -> HashSet<String> {
-    let mut all_tags = HashSet::new();
-    if let Ok(entries) = std::fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if is_supported_format(&path) {
-                for tag in load_tags(&path) {
-                    all_tags.insert(tag);
-                }
-            }
+/// メタデータ埋め込みに対応しているフォーマットか判定
+pub fn is_supported_format(path: &Path) -> bool {
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        match ext.to_lowercase().as_str() {
+            "png" | "jpg" | "jpeg" | "webp" | "tif" | "tiff" | "jxl" => true,
+            #[cfg(feature = "heif")]
+            "heic" | "heif" | "avif" => true,
+            _ => false,
         }
+    } else {
+        false
     }
-    all_tags
 }
 
-/// メタデータ埋め込みに対応しているフォーマットか判定
-pub fn is_supported_format(path: &Path) -> bool {
+/// Exifの埋め込みに失敗した場合にサイドカーへ退避するフォーマットか判定
+/// （HEIF系はExifアイテムの追加、JXLは素のコードストリームで失敗することがある）
+/// HEIF/AVIF に XMP アイテムとして埋め込む処理は未対応で、その場合はサイドカーに書く
+fn uses_sidecar_fallback(path: &Path) -> bool {
     if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
         matches!(
             ext.to_lowercase().as_str(),
-            "png" | "jpg" | "jpeg" | "webp"
+            "heic" | "heif" | "avif" | "jxl"
         )
     } else {
         false
     }
 }
 
+/// 表示できる画像の拡張子一覧
+pub fn image_extensions() -> &'static [&'static str] {
+    #[cfg(feature = "heif")]
+    {
+        &["png", "jpg", "jpeg", "gif", "webp", "bmp", "tif", "tiff", "jxl", "heic", "heif", "avif"]
+    }
+    #[cfg(not(feature = "heif"))]
+    {
+        &["png", "jpg", "jpeg", "gif", "webp", "bmp", "tif", "tiff", "jxl"]
+    }
+}
+
+/// フォルダ内の画像を探す（バックアップフォルダは除く）
+pub fn find_images(root: &Path, recursive: bool) -> Vec<PathBuf> {
+    let max_depth = if recursive { usize::MAX } else { 1 };
+    WalkDir::new(root)
+        .max_depth(max_depth)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !safe_write::is_backup_dir(e.path()))
+        .flatten()
+        .map(|e| e.into_path())
+        .filter(|p| is_image_file(p))
+        .collect()
+}
+
 /// ファイルが画像かどうかを判定 (表示用)
 pub fn is_image_file(path: &Path) -> bool {
     if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-        matches!(
-            ext.to_lowercase().as_str(),
-            "png" | "jpg" | "jpeg" | "gif" | "webp" | "bmp"
-        )
+        image_extensions().contains(&ext.to_lowercase().as_str())
     } else {
         false
     }
 }
-
-/// 特定のタグを持つ画像を検索
-pub fn find_images_with_tag(dir: &Path, tag: &str) -> Vec<PathBuf> {
-    let mut result = Vec::new();
-    if let Ok(entries) = std::fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if is_supported_format(&path) {
-                let tags = load_tags(&path);
-                if tags.iter().any(|t| t == tag) {
-                    result.push(path);
-                }
-            }
-        }
-    }
-    result.sort();
-    result
-}
@@ -0,0 +1,205 @@
+/// タグの検索条件
+/// スペース区切りの各項目をすべて満たす画像にマッチする（AND）
+/// - `cat`       : タグ cat を持つ
+/// - `-cat`      : タグ cat を持たない
+/// - `cat|dog`   : cat か dog のどちらかを持つ（OR）
+/// - `"two words"`: 引用符の中はそのままのタグ（スペース・`|`・先頭の `-`・`untagged` も文字として扱う）
+///   引用符の中の `\"` と `\\` はそれぞれ `"` と `\`
+/// - `untagged`  : タグが1つもない（`-untagged` で1つ以上ある）
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagQuery {
+    terms: Vec<Term>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Term {
+    negated: bool,
+    /// いずれかを持っていればマッチ
+    alternatives: Vec<Alternative>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Alternative {
+    Tag(String),
+    /// タグが1つもない
+    Untagged,
+}
+
+const UNTAGGED: &str = "untagged";
+
+impl TagQuery {
+    /// 検索文字列を解析する
+    pub fn parse(query: &str) -> Self {
+        let chars: Vec<char> = query.chars().collect();
+        let mut terms = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i].is_whitespace() {
+                i += 1;
+                continue;
+            }
+            // "-" だけならタグ名として扱う
+            let negated = chars[i] == '-' && chars.get(i + 1).is_some_and(|c| !c.is_whitespace());
+            if negated {
+                i += 1;
+            }
+            let mut alternatives = Vec::new();
+            let mut text = String::new();
+            let mut quoted = false;
+            let mut in_quotes = false;
+            while i < chars.len() {
+                let c = chars[i];
+                i += 1;
+                if in_quotes {
+                    match c {
+                        '"' => in_quotes = false,
+                        '\\' if matches!(chars.get(i), Some('"' | '\\')) => {
+                            text.push(chars[i]);
+                            i += 1;
+                        }
+                        c => text.push(c),
+                    }
+                } else {
+                    match c {
+                        '"' => {
+                            in_quotes = true;
+                            quoted = true;
+                        }
+                        '|' => alternatives.extend(alternative(std::mem::take(&mut text), std::mem::take(&mut quoted))),
+                        c if c.is_whitespace() => break,
+                        c => text.push(c),
+                    }
+                }
+            }
+            alternatives.extend(alternative(text, quoted));
+            if !alternatives.is_empty() {
+                terms.push(Term {
+                    negated,
+                    alternatives,
+                });
+            }
+        }
+        Self { terms }
+    }
+
+    /// タグを検索条件の1項目として書く（必要なら引用符で囲んでエスケープする）
+    pub fn term(tag: &str) -> String {
+        let plain = !tag.is_empty()
+            && !tag.contains(char::is_whitespace)
+            && !tag.contains(['|', '"'])
+            && !tag.starts_with('-')
+            && tag != UNTAGGED;
+        if plain {
+            tag.to_string()
+        } else {
+            format!("\"{}\"", tag.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+    }
+
+    /// 条件がない（すべてにマッチする）か
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// タグの一覧が条件にマッチするか
+    pub fn matches(&self, tags: &[String]) -> bool {
+        self.terms.iter().all(|term| {
+            let hit = term.alternatives.iter().any(|alt| match alt {
+                Alternative::Untagged => tags.is_empty(),
+                Alternative::Tag(tag) => tags.contains(tag),
+            });
+            hit != term.negated
+        })
+    }
+}
+
+/// `|` で区切った1つの候補（引用符を使っていればキーワードにしない）
+fn alternative(text: String, quoted: bool) -> Option<Alternative> {
+    if text.is_empty() {
+        None
+    } else if !quoted && text == UNTAGGED {
+        Some(Alternative::Untagged)
+    } else {
+        Some(Alternative::Tag(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn matches(query: &str, list: &[&str]) -> bool {
+        TagQuery::parse(query).matches(&tags(list))
+    }
+
+    #[test]
+    fn or_binds_within_a_term_and_terms_are_anded() {
+        assert!(matches("cat|dog outdoor", &["dog", "outdoor"]));
+        assert!(!matches("cat|dog outdoor", &["dog"]));
+        assert!(!matches("cat|dog outdoor", &["outdoor"]));
+        assert!(matches("cat|dog|bird", &["bird"]));
+    }
+
+    #[test]
+    fn negation_applies_to_the_whole_term() {
+        assert!(matches("-cat", &["dog"]));
+        assert!(!matches("-cat", &["cat"]));
+        assert!(!matches("-cat|dog", &["dog"]));
+        assert!(matches("-cat|dog", &["bird"]));
+        assert!(matches("outdoor -cat", &["outdoor", "dog"]));
+    }
+
+    #[test]
+    fn untagged_keyword() {
+        assert!(matches("untagged", &[]));
+        assert!(!matches("untagged", &["cat"]));
+        assert!(matches("-untagged", &["cat"]));
+        assert!(matches("untagged|cat", &["cat"]));
+        // 引用符で囲めばタグ名
+        assert!(matches("\"untagged\"", &["untagged"]));
+        assert!(!matches("\"untagged\"", &[]));
+    }
+
+    #[test]
+    fn quoted_tags_are_literal() {
+        assert!(matches("\"two words\"", &["two words"]));
+        assert!(matches("\"a|b\"", &["a|b"]));
+        assert!(!matches("\"a|b\"", &["a"]));
+        assert!(matches("\"-x\"", &["-x"]));
+        assert!(matches("-\"-x\"", &["x"]));
+        assert!(matches("\"say \\\"hi\\\"\"", &["say \"hi\""]));
+        assert!(matches("\"back\\\\slash\"", &["back\\slash"]));
+        assert!(matches("\"two words\"|cat", &["cat"]));
+    }
+
+    #[test]
+    fn lone_dash_is_a_tag() {
+        assert!(matches("-", &["-"]));
+        assert!(!matches("-", &[]));
+    }
+
+    #[test]
+    fn malformed_input_does_not_panic() {
+        for query in ["\"", "\"open", "\\", "\"\\", "|", "||", "a||b", "-|", "- -", "\"\"", "-\"", "\u{3000}cat"] {
+            let _ = TagQuery::parse(query).matches(&tags(&["cat"]));
+        }
+        // 閉じていない引用符は最後までを1つのタグにする
+        assert!(matches("\"two words", &["two words"]));
+        // 空の候補は無視する
+        assert!(matches("a||b", &["b"]));
+        assert!(TagQuery::parse("  |  ").is_empty());
+    }
+
+    #[test]
+    fn term_round_trips_through_parse() {
+        for tag in ["cat", "two words", "a|b", "-x", "untagged", "say \"hi\"", "back\\slash", "-"] {
+            let query = TagQuery::parse(&TagQuery::term(tag));
+            assert!(query.matches(&tags(&[tag])), "{}", tag);
+            assert!(!query.matches(&tags(&["other"])), "{}", tag);
+        }
+    }
+}
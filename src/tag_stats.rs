@@ -0,0 +1,198 @@
+use eframe::egui;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+
+use crate::tag_manager;
+
+/// フォルダ内の画像のタグの集計
+#[derive(Serialize)]
+pub struct TagStats {
+    pub root: PathBuf,
+    /// サブフォルダも含めたか
+    pub recursive: bool,
+    pub images: usize,
+    /// タグが1つもない画像の数
+    pub untagged: usize,
+    /// タグごとの画像数（多い順）
+    pub frequency: Vec<(String, usize)>,
+    /// タグの数ごとの画像数（添字がタグの数）
+    pub tags_per_image: Vec<usize>,
+    /// 一緒に付いている2つのタグと画像数（多い順、1枚もない組み合わせは含まない）
+    pub co_occurrence: Vec<(String, String, usize)>,
+    /// frequency の添字の組み合わせ（小さい順）から co_occurrence の画像数
+    #[serde(skip)]
+    pairs: HashMap<(usize, usize), usize>,
+}
+
+impl TagStats {
+    /// 画像ごとのタグを集計する
+    fn compute(root: &Path, recursive: bool, all_tags: &[Vec<String>]) -> Self {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut tags_per_image = Vec::new();
+        for tags in all_tags {
+            for tag in tags {
+                *counts.entry(tag).or_default() += 1;
+            }
+            if tags_per_image.len() <= tags.len() {
+                tags_per_image.resize(tags.len() + 1, 0);
+            }
+            tags_per_image[tags.len()] += 1;
+        }
+
+        let mut frequency: Vec<(String, usize)> = counts.into_iter().map(|(t, n)| (t.to_string(), n)).collect();
+        frequency.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        // タグの数の2乗の表は大きくなりすぎるので、実際にある組み合わせだけを数える
+        let index: HashMap<&str, usize> = frequency
+            .iter()
+            .enumerate()
+            .map(|(i, (tag, _))| (tag.as_str(), i))
+            .collect();
+        let mut pairs: HashMap<(usize, usize), usize> = HashMap::new();
+        for tags in all_tags {
+            let mut ids: Vec<usize> = tags.iter().filter_map(|t| index.get(t.as_str()).copied()).collect();
+            ids.sort_unstable();
+            ids.dedup();
+            for (i, &a) in ids.iter().enumerate() {
+                for &b in &ids[i + 1..] {
+                    *pairs.entry((a, b)).or_default() += 1;
+                }
+            }
+        }
+        let mut co_occurrence: Vec<((usize, usize), usize)> = pairs.iter().map(|(k, n)| (*k, *n)).collect();
+        co_occurrence.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let co_occurrence = co_occurrence
+            .into_iter()
+            .map(|((a, b), n)| (frequency[a].0.clone(), frequency[b].0.clone(), n))
+            .collect();
+
+        Self {
+            root: root.to_path_buf(),
+            recursive,
+            images: all_tags.len(),
+            untagged: tags_per_image.first().copied().unwrap_or(0),
+            frequency,
+            tags_per_image,
+            co_occurrence,
+            pairs,
+        }
+    }
+
+    /// frequency の a 番目と b 番目のタグを両方持つ画像の数
+    pub fn together(&self, a: usize, b: usize) -> usize {
+        if a == b {
+            return self.frequency[a].1;
+        }
+        self.pairs.get(&(a.min(b), a.max(b))).copied().unwrap_or(0)
+    }
+
+    pub fn max_frequency(&self) -> usize {
+        self.frequency.first().map(|(_, n)| *n).unwrap_or(0)
+    }
+
+    /// CSV に書き出す（タグごとの画像数と割合、空行に続けて一緒に付いているタグの組み合わせ）
+    pub fn export_csv(&self, path: &Path) -> io::Result<()> {
+        let mut out = String::from("tag,images,percent\n");
+        for (tag, count) in &self.frequency {
+            out.push_str(&format!("{},{},{:.2}\n", csv_field(tag), count, self.percent(*count)));
+        }
+        out.push_str(&format!("(untagged),{},{:.2}\n", self.untagged, self.percent(self.untagged)));
+        out.push_str("\ntag,other tag,images\n");
+        for (a, b, count) in &self.co_occurrence {
+            out.push_str(&format!("{},{},{}\n", csv_field(a), csv_field(b), count));
+        }
+        fs::write(path, out)
+    }
+
+    /// JSON に書き出す
+    pub fn export_json(&self, path: &Path) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, content)
+    }
+
+    /// 画像全体に対する割合（%）
+    pub fn percent(&self, count: usize) -> f32 {
+        if self.images == 0 {
+            0.0
+        } else {
+            count as f32 * 100.0 / self.images as f32
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// バックグラウンドでの集計（破棄すると取りやめる）
+pub struct StatsCollection {
+    pub root: PathBuf,
+    rx: Receiver<TagStats>,
+    /// タグを読み込んだ画像の数
+    done: Arc<AtomicUsize>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl StatsCollection {
+    /// フォルダ以下の画像の集計を始める
+    /// known には読み込み済み・書き込み待ちのタグを渡す（ディスクから読み直さない）
+    pub fn start(root: &Path, recursive: bool, known: HashMap<PathBuf, Vec<String>>, ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let done = Arc::new(AtomicUsize::new(0));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let ctx = ctx.clone();
+        let thread_root = root.to_path_buf();
+        let thread_done = done.clone();
+        let thread_cancel = cancel.clone();
+        std::thread::spawn(move || {
+            let paths = tag_manager::find_images(&thread_root, recursive);
+            let mut all_tags = Vec::with_capacity(paths.len());
+            for path in &paths {
+                if thread_cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                all_tags.push(match known.get(path) {
+                    Some(tags) => tags.clone(),
+                    None => tag_manager::load_tags(path),
+                });
+                thread_done.fetch_add(1, Ordering::Relaxed);
+                ctx.request_repaint();
+            }
+            let _ = tx.send(TagStats::compute(&thread_root, recursive, &all_tags));
+            ctx.request_repaint();
+        });
+
+        Self {
+            root: root.to_path_buf(),
+            rx,
+            done,
+            cancel,
+        }
+    }
+
+    /// 集計が終わっていれば結果を受け取る
+    pub fn poll(&self) -> Option<TagStats> {
+        self.rx.try_recv().ok()
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for StatsCollection {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
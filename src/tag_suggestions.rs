@@ -0,0 +1,389 @@
+use eframe::egui;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::duplicates;
+use crate::image_loader;
+use crate::tag_cache::TagCache;
+use crate::tag_manager;
+
+// 見た目の似ているタグ付き画像からタグを提案する（すべてローカルで計算する）
+// 知覚ハッシュ（形）と色のヒストグラム（色合い）を組み合わせて似ている度合いを測る
+
+/// ハッシュを計算するときの縮小サイズ
+const FINGERPRINT_SIZE: u32 = 64;
+/// ヒストグラムの色ごとの区切り数（4x4x4 = 64 ビン）
+const HISTOGRAM_LEVELS: usize = 4;
+/// タグを集める似ている画像の数
+const NEIGHBOURS: usize = 10;
+/// これより似ていない画像は使わない
+const MIN_SIMILARITY: f32 = 0.5;
+/// これより確度の低いタグは提案しない
+const MIN_CONFIDENCE: f32 = 0.2;
+/// 表示する提案の数
+pub const MAX_SUGGESTIONS: usize = 8;
+/// 読み込み中に提案を計算し直す間隔
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 画像の特徴
+#[derive(Clone)]
+struct Fingerprint {
+    perceptual: u64,
+    difference: u64,
+    /// 色のヒストグラム（合計 1）
+    histogram: [f32; HISTOGRAM_LEVELS * HISTOGRAM_LEVELS * HISTOGRAM_LEVELS],
+}
+
+impl Fingerprint {
+    fn compute(path: &Path) -> Option<Self> {
+        let img = image_loader::open(path).ok()?;
+        let small = img.thumbnail(FINGERPRINT_SIZE, FINGERPRINT_SIZE);
+        Some(Self {
+            perceptual: duplicates::perceptual_hash(&small),
+            difference: duplicates::difference_hash(&small),
+            histogram: histogram(&small),
+        })
+    }
+
+    /// 似ている度合い（0〜1）
+    fn similarity(&self, other: &Fingerprint) -> f32 {
+        // 無関係な画像のハッシュは半分（32ビット）ほど違うので、そこを 0 とする
+        let distance = ((self.perceptual ^ other.perceptual).count_ones()
+            + (self.difference ^ other.difference).count_ones()) as f32
+            / 2.0;
+        let shape = (1.0 - distance / 32.0).max(0.0);
+        let colour: f32 = self
+            .histogram
+            .iter()
+            .zip(&other.histogram)
+            .map(|(a, b)| a.min(*b))
+            .sum();
+        (shape + colour) / 2.0
+    }
+}
+
+fn histogram(img: &DynamicImage) -> [f32; HISTOGRAM_LEVELS * HISTOGRAM_LEVELS * HISTOGRAM_LEVELS] {
+    let mut bins = [0.0; HISTOGRAM_LEVELS * HISTOGRAM_LEVELS * HISTOGRAM_LEVELS];
+    let rgb = img
+        .resize_exact(FINGERPRINT_SIZE, FINGERPRINT_SIZE, FilterType::Triangle)
+        .to_rgb8();
+    // 区切りの近くの色が少しずれただけで別のビンにならないよう、隣のビンにも振り分ける
+    let split = |v: u8| -> [(usize, f32); 2] {
+        let t = (v as f32 + 0.5) / 256.0 * HISTOGRAM_LEVELS as f32 - 0.5;
+        let t = t.clamp(0.0, (HISTOGRAM_LEVELS - 1) as f32);
+        let low = (t.floor() as usize).min(HISTOGRAM_LEVELS - 2);
+        let frac = t - low as f32;
+        [(low, 1.0 - frac), (low + 1, frac)]
+    };
+    for p in rgb.pixels() {
+        let [r, g, b] = p.0.map(split);
+        for (ri, rw) in r {
+            for (gi, gw) in g {
+                for (bi, bw) in b {
+                    bins[(ri * HISTOGRAM_LEVELS + gi) * HISTOGRAM_LEVELS + bi] += rw * gw * bw;
+                }
+            }
+        }
+    }
+    let total = (rgb.width() * rgb.height()).max(1) as f32;
+    bins.iter_mut().for_each(|b| *b /= total);
+    bins
+}
+
+/// 提案するタグ
+pub struct Suggestion {
+    pub tag: String,
+    /// 確度（0〜1）
+    pub confidence: f32,
+    /// タグが付いていた似ている画像の数
+    pub found: usize,
+    /// 使った似ている画像の数
+    pub neighbours: usize,
+}
+
+enum Command {
+    /// フォルダ以下の画像の読み込みを始める（前の読み込みは取りやめる）
+    Index(PathBuf),
+    /// 先に読み込む
+    Prioritize(PathBuf),
+    /// 読み込みをやめて、読み込んだ記録も捨てる
+    Stop,
+}
+
+enum Message {
+    /// フォルダの画像を調べ終えた（paths はすべての画像、queued はこれから読み込む画像）
+    Listed {
+        paths: HashSet<PathBuf>,
+        queued: HashSet<PathBuf>,
+    },
+    Fingerprint(PathBuf, Option<Box<Fingerprint>>),
+}
+
+/// 表示中の画像の提案（計算したときの状態）
+struct Computed {
+    path: PathBuf,
+    fingerprints: usize,
+    at: Instant,
+    suggestions: Vec<Suggestion>,
+}
+
+/// 似ている画像からのタグの提案（フォルダの走査と画像の読み込みはバックグラウンドで行う）
+pub struct TagSuggester {
+    tx: Sender<Command>,
+    rx: Receiver<Message>,
+    /// 読み込む対象のフォルダ
+    root: Option<PathBuf>,
+    /// フォルダを走査中か
+    listing: bool,
+    /// 読み込んだ画像の特徴（ファイルが更新されたもの・フォルダの外のものは走査のたびに捨てる）
+    fingerprints: HashMap<PathBuf, Fingerprint>,
+    /// 読み込み待ちの画像
+    pending: HashSet<PathBuf>,
+    /// 読み込めなかった画像
+    failed: HashSet<PathBuf>,
+    /// 先に読み込むよう頼んだ画像
+    prioritized: Option<PathBuf>,
+    computed: Option<Computed>,
+}
+
+impl TagSuggester {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, command_rx) = mpsc::channel();
+        let (result_tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || run_worker(command_rx, result_tx, ctx));
+        Self {
+            tx,
+            rx,
+            root: None,
+            listing: false,
+            fingerprints: HashMap::new(),
+            pending: HashSet::new(),
+            failed: HashSet::new(),
+            prioritized: None,
+            computed: None,
+        }
+    }
+
+    /// 読み込む対象のフォルダを設定する（変わったときだけ読み込み直す）
+    pub fn set_root(&mut self, root: Option<&Path>) {
+        if self.root.as_deref() == root {
+            return;
+        }
+        self.root = root.map(Path::to_path_buf);
+        self.refresh();
+    }
+
+    /// フォルダの画像を読み込み直す（更新されていない画像は使い回す）
+    pub fn refresh(&mut self) {
+        self.computed = None;
+        match &self.root {
+            Some(root) => {
+                self.listing = true;
+                let _ = self.tx.send(Command::Index(root.clone()));
+            }
+            None => self.stop(),
+        }
+    }
+
+    /// 読み込みをやめて、読み込んだ特徴も捨てる（提案を無効にしたとき）
+    pub fn stop(&mut self) {
+        self.root = None;
+        self.listing = false;
+        self.fingerprints.clear();
+        self.pending.clear();
+        self.failed.clear();
+        self.prioritized = None;
+        self.computed = None;
+        let _ = self.tx.send(Command::Stop);
+    }
+
+    /// 走査の結果と読み込んだ画像を受け取る
+    pub fn poll(&mut self) {
+        for message in self.rx.try_iter() {
+            match message {
+                Message::Listed { paths, queued } => {
+                    self.listing = false;
+                    // 表示中の画像はフォルダの外でも残す
+                    let prioritized = self.prioritized.as_ref();
+                    let keep = |p: &PathBuf| !queued.contains(p) && (paths.contains(p) || prioritized == Some(p));
+                    self.fingerprints.retain(|p, _| keep(p));
+                    self.failed.retain(keep);
+                    self.pending = queued;
+                    self.computed = None;
+                }
+                Message::Fingerprint(path, fingerprint) => {
+                    // 取りやめた読み込みの結果は使わない
+                    if !self.pending.remove(&path) && self.prioritized.as_ref() != Some(&path) {
+                        continue;
+                    }
+                    match fingerprint {
+                        Some(fingerprint) => {
+                            self.fingerprints.insert(path, *fingerprint);
+                        }
+                        None => {
+                            self.failed.insert(path);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// 読み込みの進み具合（読み込み済み, 全体）
+    pub fn progress(&self) -> (usize, usize) {
+        let total = self.fingerprints.len() + self.pending.len();
+        (self.fingerprints.len(), total)
+    }
+
+    pub fn is_indexing(&self) -> bool {
+        self.listing || !self.pending.is_empty()
+    }
+
+    /// 画像のタグの提案（既に付いているタグも含む）
+    pub fn suggestions(&mut self, path: &Path, cache: &mut TagCache) -> &[Suggestion] {
+        let stale = match &self.computed {
+            Some(c) if c.path == path => {
+                c.fingerprints != self.fingerprints.len()
+                    && (!self.is_indexing() || c.at.elapsed() >= REFRESH_INTERVAL)
+            }
+            _ => true,
+        };
+        if stale {
+            let missing = !self.fingerprints.contains_key(path) && !self.failed.contains(path);
+            if missing && self.prioritized.as_deref() != Some(path) {
+                self.prioritized = Some(path.to_path_buf());
+                self.pending.insert(path.to_path_buf());
+                let _ = self.tx.send(Command::Prioritize(path.to_path_buf()));
+            }
+            self.computed = Some(Computed {
+                path: path.to_path_buf(),
+                fingerprints: self.fingerprints.len(),
+                at: Instant::now(),
+                suggestions: self.compute(path, cache),
+            });
+        }
+        self.computed.as_ref().map(|c| c.suggestions.as_slice()).unwrap_or_default()
+    }
+
+    /// 提案を計算し直す（他の画像のタグが変わったときなど）
+    pub fn invalidate(&mut self) {
+        self.computed = None;
+    }
+
+    fn compute(&self, path: &Path, cache: &mut TagCache) -> Vec<Suggestion> {
+        let Some(target) = self.fingerprints.get(path) else {
+            return Vec::new();
+        };
+        let mut neighbours: Vec<(f32, &PathBuf)> = self
+            .fingerprints
+            .iter()
+            .filter(|(p, _)| p.as_path() != path)
+            .map(|(p, f)| (target.similarity(f), p))
+            .filter(|(similarity, _)| *similarity >= MIN_SIMILARITY)
+            .collect();
+        neighbours.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        // タグの付いている画像だけを使う
+        let mut weights: HashMap<String, (f32, usize)> = HashMap::new();
+        let mut total = 0.0;
+        let mut used = 0;
+        for (similarity, neighbour) in neighbours {
+            let tags = cache.tags(neighbour);
+            if tags.is_empty() {
+                continue;
+            }
+            for tag in tags {
+                let entry = weights.entry(tag.clone()).or_default();
+                entry.0 += similarity;
+                entry.1 += 1;
+            }
+            total += similarity;
+            used += 1;
+            if used == NEIGHBOURS {
+                break;
+            }
+        }
+
+        let mut suggestions: Vec<Suggestion> = weights
+            .into_iter()
+            .map(|(tag, (weight, found))| Suggestion {
+                tag,
+                confidence: weight / total,
+                found,
+                neighbours: used,
+            })
+            .filter(|s| s.confidence >= MIN_CONFIDENCE)
+            .collect();
+        suggestions.sort_by(|a, b| b.confidence.total_cmp(&a.confidence).then_with(|| a.tag.cmp(&b.tag)));
+        suggestions
+    }
+}
+
+/// フォルダを走査し、画像を読み込んで特徴を計算するスレッド
+fn run_worker(rx: Receiver<Command>, tx: Sender<Message>, ctx: egui::Context) {
+    let mut queue: VecDeque<PathBuf> = VecDeque::new();
+    // 読み込んだ画像と、そのときの更新日時（変わっていなければ読み込み直さない）
+    let mut done: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    loop {
+        // 待っている指示を先に反映する（何もすることがなければ待つ）
+        loop {
+            let command = if queue.is_empty() {
+                match rx.recv() {
+                    Ok(command) => command,
+                    Err(_) => return,
+                }
+            } else {
+                match rx.try_recv() {
+                    Ok(command) => command,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            };
+            match command {
+                Command::Index(root) => {
+                    let paths: HashSet<PathBuf> = tag_manager::find_images(&root, true).into_iter().collect();
+                    // フォルダの外の画像は忘れる
+                    done.retain(|p, _| paths.contains(p));
+                    let queued: HashSet<PathBuf> = paths
+                        .iter()
+                        .filter(|p| done.get(*p).is_none_or(|m| *m != modified(p)))
+                        .cloned()
+                        .collect();
+                    let mut order: Vec<PathBuf> = queued.iter().cloned().collect();
+                    order.sort();
+                    queue = order.into();
+                    if tx.send(Message::Listed { paths, queued }).is_err() {
+                        return;
+                    }
+                    ctx.request_repaint();
+                }
+                Command::Prioritize(path) => queue.push_front(path),
+                Command::Stop => {
+                    queue.clear();
+                    done.clear();
+                }
+            }
+        }
+
+        let Some(path) = queue.pop_front() else {
+            continue;
+        };
+        let mtime = modified(&path);
+        if done.get(&path) == Some(&mtime) {
+            continue;
+        }
+        done.insert(path.clone(), mtime);
+        let fingerprint = Fingerprint::compute(&path).map(Box::new);
+        if tx.send(Message::Fingerprint(path, fingerprint)).is_err() {
+            return;
+        }
+        ctx.request_repaint();
+    }
+}
@@ -0,0 +1,268 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::fnv::Fnv;
+use crate::image_loader;
+
+// タグの書き込みで画像データが壊れていないかの確認
+// メタデータ以外の部分（圧縮された画像データ）のハッシュを書き込み前後で比べる
+
+/// 書き込み前の画像の状態
+pub struct Snapshot {
+    /// 画像データのハッシュ（対応していない形式なら None）
+    payload: Option<u64>,
+}
+
+/// 書き込み前の状態を記録する
+pub fn snapshot(path: &Path) -> Snapshot {
+    Snapshot {
+        payload: fs::read(path).ok().and_then(|data| payload_hash(path, &data)),
+    }
+}
+
+/// 書き込み後も画像として読めて、画像データが変わっていないか確認する
+pub fn verify(path: &Path, before: &Snapshot) -> Result<(), String> {
+    if let Err(e) = image_loader::open(path) {
+        return Err(format!("Image no longer decodes: {}", e));
+    }
+    if let Some(before) = before.payload {
+        let after = fs::read(path).ok().and_then(|data| payload_hash(path, &data));
+        if after != Some(before) {
+            return Err("Image data changed while writing tags".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// 書き込みの失敗を記録するログファイル
+pub fn log_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("tag_editor")
+        .join("write_errors.log")
+}
+
+/// ログに1行追記する
+pub fn log_failure(path: &Path, message: &str) {
+    let log = log_path();
+    if let Some(parent) = log.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&log) {
+        let _ = writeln!(
+            file,
+            "{}\t{}\t{}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z"),
+            path.display(),
+            message
+        );
+    }
+}
+
+/// メタデータを書き換えても変わらないハッシュ
+/// 画像データのハッシュ（対応していない形式はファイル全体のハッシュ）
+pub fn stable_hash(path: &Path) -> std::io::Result<u64> {
+    let data = fs::read(path)?;
+    Ok(payload_hash(path, &data).unwrap_or_else(|| {
+        let mut hash = Fnv::default();
+        hash.write(&data);
+        hash.finish()
+    }))
+}
+
+/// メタデータを除いた画像データのハッシュ（JPEG, PNG, WebP のみ）
+fn payload_hash(path: &Path, data: &[u8]) -> Option<u64> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let mut hash = Fnv::default();
+    match ext.as_str() {
+        "jpg" | "jpeg" => jpeg_payload(data, &mut hash)?,
+        "png" => png_payload(data, &mut hash)?,
+        "webp" => webp_payload(data, &mut hash)?,
+        _ => return None,
+    }
+    Some(hash.finish())
+}
+
+/// APPn・COM 以外のセグメントと、SOS 以降のデータ
+fn jpeg_payload(data: &[u8], hash: &mut Fnv) -> Option<()> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut i = 2;
+    loop {
+        if *data.get(i)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(i + 1)?;
+        // 詰め物と長さを持たないマーカー
+        if marker == 0xFF {
+            i += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            i += 2;
+            continue;
+        }
+        if marker == 0xDA {
+            hash.write(&data[i..]);
+            return Some(());
+        }
+        let len = u16::from_be_bytes([*data.get(i + 2)?, *data.get(i + 3)?]) as usize;
+        let segment = data.get(i..i + 2 + len)?;
+        if !(0xE0..=0xEF).contains(&marker) && marker != 0xFE {
+            hash.write(segment);
+        }
+        i += 2 + len;
+    }
+}
+
+/// 必須チャンク（IHDR, PLTE, IDAT, IEND）
+fn png_payload(data: &[u8], hash: &mut Fnv) -> Option<()> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return None;
+    }
+    let mut i = 8;
+    while i < data.len() {
+        let len = u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?) as usize;
+        let end = len.checked_add(i + 8)?;
+        let chunk = data.get(i + 4..end)?;
+        if chunk[0].is_ascii_uppercase() {
+            hash.write(chunk);
+        }
+        i = end + 4;
+    }
+    Some(())
+}
+
+/// VP8X・EXIF・XMP 以外のチャンク
+fn webp_payload(data: &[u8], hash: &mut Fnv) -> Option<()> {
+    if data.get(0..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let mut i = 12;
+    while i < data.len() {
+        let fourcc = data.get(i..i + 4)?;
+        let len = u32::from_le_bytes(data.get(i + 4..i + 8)?.try_into().ok()?) as usize;
+        let end = len.checked_add(i + 8)?;
+        let chunk = data.get(i..end)?;
+        if !matches!(fourcc, b"VP8X" | b"EXIF" | b"XMP ") {
+            hash.write(chunk);
+        }
+        i = end + len % 2;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(name: &str, data: &[u8]) -> Option<u64> {
+        payload_hash(Path::new(name), data)
+    }
+
+    fn jpeg(exif: &[u8], table: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        for (marker, body) in [(0xE1, exif), (0xDB, table)] {
+            data.extend_from_slice(&[0xFF, marker]);
+            data.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+            data.extend_from_slice(body);
+        }
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        data
+    }
+
+    fn png(text: &[u8], pixels: &[u8]) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, body) in [(b"IHDR", &[0u8; 13][..]), (b"tEXt", text), (b"IDAT", pixels), (b"IEND", &[])] {
+            data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            data.extend_from_slice(kind);
+            data.extend_from_slice(body);
+            data.extend_from_slice(&[0; 4]);
+        }
+        data
+    }
+
+    fn webp(xmp: &[u8], image: &[u8]) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        for (kind, body) in [(b"VP8X", &[0u8; 10][..]), (b"VP8L", image), (b"XMP ", xmp)] {
+            chunks.extend_from_slice(kind);
+            chunks.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(body);
+            if body.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        }
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend_from_slice(&chunks);
+        data
+    }
+
+    /// 決まった擬似乱数列（xorshift）
+    fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn metadata_changes_keep_the_hash() {
+        assert_eq!(hash("a.jpg", &jpeg(b"old", b"q")), hash("a.jpg", &jpeg(b"new exif", b"q")));
+        assert_ne!(hash("a.jpg", &jpeg(b"old", b"q")), hash("a.jpg", &jpeg(b"old", b"r")));
+        assert_eq!(hash("a.png", &png(b"old", b"px")), hash("a.png", &png(b"new text", b"px")));
+        assert_ne!(hash("a.png", &png(b"old", b"px")), hash("a.png", &png(b"old", b"py")));
+        assert_eq!(hash("a.webp", &webp(b"old", b"img")), hash("a.webp", &webp(b"longer", b"img")));
+        assert_ne!(hash("a.webp", &webp(b"old", b"img")), hash("a.webp", &webp(b"old", b"imh")));
+        assert!(hash("a.jpg", &jpeg(b"", b"q")).is_some());
+        assert!(hash("a.png", &png(b"", b"px")).is_some());
+        assert!(hash("a.webp", &webp(b"", b"img")).is_some());
+    }
+
+    #[test]
+    fn truncated_files_do_not_panic() {
+        for (name, data) in [
+            ("a.jpg", jpeg(b"exif", b"q")),
+            ("a.png", png(b"text", b"px")),
+            ("a.webp", webp(b"xmp", b"img")),
+        ] {
+            for len in 0..data.len() {
+                let _ = hash(name, &data[..len]);
+            }
+        }
+        assert_eq!(hash("a.png", &png(b"text", b"px")[..20]), None);
+        assert_eq!(hash("a.webp", &webp(b"xmp", b"img")[..26]), None);
+    }
+
+    #[test]
+    fn malformed_files_do_not_panic() {
+        let huge = [0xFF; 8];
+        for (name, header) in [
+            ("a.jpg", &b"\xFF\xD8"[..]),
+            ("a.png", &b"\x89PNG\r\n\x1a\n"[..]),
+            ("a.webp", &b"RIFF\0\0\0\0WEBP"[..]),
+        ] {
+            for seed in 1..200 {
+                let mut data = header.to_vec();
+                data.extend_from_slice(&noise(seed as usize, seed));
+                let _ = hash(name, &data);
+            }
+            // 巨大な長さ
+            let mut data = header.to_vec();
+            data.extend_from_slice(b"\xFF\xE1");
+            data.extend_from_slice(&huge);
+            assert_eq!(hash(name, &data), None);
+        }
+        // 長さ0のセグメントや詰め物が続いても止まる
+        assert_eq!(hash("a.jpg", b"\xFF\xD8\xFF\xFF\xFF\xDB\x00\x00\xFF\xDB\x00\x00"), None);
+        assert_eq!(hash("a.jpg", b"not a jpeg"), None);
+        assert_eq!(hash("a.gif", b"GIF89a"), None);
+    }
+}
@@ -0,0 +1,276 @@
+use eframe::egui;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use crate::safe_write;
+use crate::tag_manager;
+use crate::write_check;
+
+/// 同じ画像への続けての編集をまとめるために待つ時間
+const COALESCE: Duration = Duration::from_millis(400);
+/// 一時的なエラーで書き込みをやり直す回数
+const MAX_RETRIES: u32 = 5;
+/// 最初のやり直しまでの時間（やり直すたびに倍にする）
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// 書き込みの設定（書き込むときの Config の値）
+#[derive(Clone, Copy)]
+pub struct WriteOptions {
+    /// 元の画像をバックアップする場合はフォルダごとに残す数（None ならバックアップしない）
+    pub backup: Option<usize>,
+    pub preserve_timestamps: bool,
+    pub verify: bool,
+    /// 書き込む前のタグを読んでおくか（フックで追加・削除されたタグを調べる）
+    pub previous_tags: bool,
+}
+
+/// 書き込めたときの結果
+pub struct Written {
+    /// 書き込み後の確認で見つかった問題
+    pub problem: Option<String>,
+    /// 書き込む前のタグ（WriteOptions::previous_tags のときだけ）
+    pub previous: Option<Vec<String>>,
+}
+
+/// タグを書き込む（設定に応じてバックアップ・日時の維持・書き込み後の確認を行う）
+pub fn write_tags(path: &Path, tags: &[String], options: WriteOptions) -> io::Result<Written> {
+    let previous = options.previous_tags.then(|| tag_manager::load_tags(path));
+    write_with(path, tags, options, tag_manager::embeds_tags(path), previous, tag_manager::save_tags)
+}
+
+/// ライブラリモードでもデータベースではなく画像にタグを埋め込む（データベースからの書き戻し）
+pub fn write_embedded_tags(path: &Path, tags: &[String], options: WriteOptions) -> io::Result<Written> {
+    let previous = options.previous_tags.then(|| tag_manager::load_embedded_tags(path));
+    write_with(
+        path,
+        tags,
+        options,
+        tag_manager::embeds_into_image(path),
+        previous,
+        tag_manager::save_embedded_tags,
+    )
+}
+
+/// save でタグを保存する（embeds なら画像ファイルを書き換えるので安全のための処理を行う）
+fn write_with(
+    path: &Path,
+    tags: &[String],
+    options: WriteOptions,
+    embeds: bool,
+    previous: Option<Vec<String>>,
+    save: fn(&Path, &[String]) -> io::Result<()>,
+) -> io::Result<Written> {
+    if let (Some(limit), true) = (options.backup, embeds) {
+        safe_write::backup_original(path, limit)?;
+    }
+    let times = if options.preserve_timestamps && embeds {
+        safe_write::file_times(path)
+    } else {
+        None
+    };
+    let snapshot = (options.verify && embeds).then(|| write_check::snapshot(path));
+
+    if let Err(e) = save(path, tags) {
+        write_check::log_failure(path, &format!("Write failed: {}", e));
+        return Err(e);
+    }
+    if let Some(times) = times {
+        if let Err(e) = safe_write::set_file_times(path, times) {
+            write_check::log_failure(path, &format!("Could not restore timestamps: {}", e));
+        }
+    }
+
+    let problem = snapshot.and_then(|snapshot| write_check::verify(path, &snapshot).err());
+    if let Some(problem) = &problem {
+        write_check::log_failure(path, problem);
+    }
+    Ok(Written { problem, previous })
+}
+
+/// やり直せば成功するかもしれないエラーか（ネットワークドライブの瞬断など）
+fn is_transient(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::Unsupported
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+    )
+}
+
+struct Job {
+    path: PathBuf,
+    tags: Vec<String>,
+    options: WriteOptions,
+}
+
+enum Command {
+    Write(Job),
+    /// 待っている書き込みをすぐに行い、終わったら知らせる
+    Flush(Sender<()>),
+}
+
+/// 書き込みの結果
+pub struct WriteResult {
+    pub path: PathBuf,
+    pub tags: Vec<String>,
+    /// 失敗したらエラー
+    pub outcome: Result<Written, String>,
+}
+
+/// バックグラウンドでタグを書き込むキュー（UIスレッドを止めないため）
+pub struct WriteQueue {
+    tx: Sender<Command>,
+    rx: Receiver<WriteResult>,
+    /// 書き込み待ち・書き込み中のタグ
+    queued: HashMap<PathBuf, Vec<String>>,
+    /// 書き込めなかった画像とエラー
+    pub failed: BTreeMap<PathBuf, String>,
+}
+
+impl WriteQueue {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, command_rx) = mpsc::channel();
+        let (result_tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || run_worker(command_rx, result_tx, ctx));
+        Self {
+            tx,
+            rx,
+            queued: HashMap::new(),
+            failed: BTreeMap::new(),
+        }
+    }
+
+    /// 書き込みを依頼する（同じ画像の書き込みが待っていれば置き換える）
+    pub fn submit(&mut self, path: PathBuf, tags: Vec<String>, options: WriteOptions) {
+        self.queued.insert(path.clone(), tags.clone());
+        let _ = self.tx.send(Command::Write(Job { path, tags, options }));
+    }
+
+    /// 書き込み待ちのタグ（まだディスクには書かれていない）
+    pub fn queued_tags(&self, path: &Path) -> Option<&Vec<String>> {
+        self.queued.get(path)
+    }
+
+    pub fn is_queued(&self, path: &Path) -> bool {
+        self.queued.contains_key(path)
+    }
+
+    /// 書き込み待ち・書き込み中の数
+    pub fn pending_count(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// 終わった書き込みの結果を受け取る
+    pub fn poll(&mut self) -> Vec<WriteResult> {
+        let results: Vec<WriteResult> = self.rx.try_iter().collect();
+        for result in &results {
+            // 後から別のタグで依頼されていればまだ待ち
+            if self.queued.get(&result.path) == Some(&result.tags) {
+                self.queued.remove(&result.path);
+            }
+            match &result.outcome {
+                Ok(_) => {
+                    self.failed.remove(&result.path);
+                }
+                Err(e) => {
+                    self.failed.insert(result.path.clone(), e.clone());
+                }
+            }
+        }
+        results
+    }
+
+    /// 待っている書き込みをすべて終わらせて結果を受け取る
+    /// （やり直しの回数には上限があるので、いつまでも待ち続けることはない）
+    pub fn flush(&mut self) -> Vec<WriteResult> {
+        if !self.queued.is_empty() {
+            let (done_tx, done_rx) = mpsc::channel();
+            if self.tx.send(Command::Flush(done_tx)).is_ok() {
+                let _ = done_rx.recv();
+            }
+        }
+        self.poll()
+    }
+}
+
+/// 書き込みスレッド
+fn run_worker(rx: Receiver<Command>, tx: Sender<WriteResult>, ctx: egui::Context) {
+    // 画像ごとの書き込み待ち（やり直し回数と次に書き込む時刻）
+    let mut jobs: BTreeMap<PathBuf, (Job, u32, Instant)> = BTreeMap::new();
+    loop {
+        let next_due = jobs.values().map(|(_, _, due)| *due).min();
+        let command = match next_due {
+            Some(due) => match rx.recv_timeout(due.saturating_duration_since(Instant::now())) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(command) => Some(command),
+                Err(_) => break,
+            },
+        };
+
+        let mut flushed = None;
+        match command {
+            Some(Command::Write(job)) => {
+                jobs.insert(job.path.clone(), (job, 0, Instant::now() + COALESCE));
+            }
+            Some(Command::Flush(done)) => flushed = Some(done),
+            None => {}
+        }
+
+        let now = Instant::now();
+        let due: Vec<PathBuf> = jobs
+            .iter()
+            .filter(|(_, (_, _, due))| flushed.is_some() || *due <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in due {
+            let Some((job, attempts, _)) = jobs.remove(&path) else {
+                continue;
+            };
+            let outcome = if flushed.is_some() {
+                // 終了前などは待たずにその場でやり直す
+                write_with_retries(&job)
+            } else {
+                match write_tags(&job.path, &job.tags, job.options) {
+                    Err(e) if is_transient(&e) && attempts < MAX_RETRIES => {
+                        let delay = RETRY_DELAY * 2u32.pow(attempts);
+                        jobs.insert(path, (job, attempts + 1, now + delay));
+                        continue;
+                    }
+                    outcome => outcome.map_err(|e| e.to_string()),
+                }
+            };
+            let _ = tx.send(WriteResult {
+                path: job.path,
+                tags: job.tags,
+                outcome,
+            });
+            ctx.request_repaint();
+        }
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn write_with_retries(job: &Job) -> Result<Written, String> {
+    let mut attempts = 0;
+    loop {
+        match write_tags(&job.path, &job.tags, job.options) {
+            Err(e) if is_transient(&e) && attempts < MAX_RETRIES => {
+                attempts += 1;
+                std::thread::sleep(RETRY_DELAY);
+            }
+            outcome => return outcome.map_err(|e| e.to_string()),
+        }
+    }
+}